@@ -0,0 +1,118 @@
+// MIT License
+// Copyright (c) 2025 Gianluca Cannata <gcannata23@gmail.com>
+//
+// av-gif - A GIF encoder written in Rust
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::encoder::{GifEncoder, GifEncoderState, GifEvent};
+
+// Drives a `GifEncoderState` and streams its output to an `AsyncWrite` as soon as
+// it's final, accepting the same events in the same states as `GifEncoder::process_event`.
+pub struct AsyncGifEncoder<W> {
+    state: GifEncoderState,
+    writer: W,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncGifEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        Self::with_state(GifEncoderState::new(), writer)
+    }
+
+    // Use an already configured encoder, e.g. with a lossy level or frame coalescing.
+    // It should not have been given any events yet.
+    pub fn with_state(state: GifEncoderState, writer: W) -> Self {
+        AsyncGifEncoder { state, writer }
+    }
+
+    pub async fn process_event<'a>(&mut self, event: GifEvent<'a>) -> Result<(), String> {
+        let is_end = matches!(event, GifEvent::EndGif);
+
+        self.state.process_event(event)?;
+
+        let ready = self.state.take_ready_output();
+        if !ready.is_empty() {
+            self.writer
+                .write_all(&ready)
+                .await
+                .map_err(|err| err.to_string())?;
+        }
+
+        if is_end {
+            self.writer.flush().await.map_err(|err| err.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::{DisposalMethod, LoopConfig, ScreenOptions};
+
+    fn events(delay: u16) -> Vec<GifEvent<'static>> {
+        let palette = vec![[0, 0, 0], [255, 0, 0], [0, 255, 0], [0, 0, 255]];
+        let mut events = vec![GifEvent::StartGif { width: 4, height: 2, global_palette: Some(palette.into()), background_color_index: 0, looping: LoopConfig::infinite(), version: None, screen: ScreenOptions::default() }];
+        for frame in 0..3u8 {
+            events.push(GifEvent::StartFrame { delay, disposal_method: DisposalMethod::None, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: None, user_input: false, is_interlaced: false, rect: None });
+            events.push(GifEvent::WriteImageChunk { data: vec![frame % 4, 1, 2, 3].into() });
+            events.push(GifEvent::WriteImageChunk { data: vec![3, 2, 1, frame % 4].into() });
+            events.push(GifEvent::EndFrame);
+        }
+        events.push(GifEvent::EndGif);
+        events
+    }
+
+    #[tokio::test]
+    async fn test_matches_sync_encoder() -> Result<(), String> {
+        for delay in [0, 10] {
+            let mut sync_encoder = GifEncoderState::new();
+            for event in events(delay) {
+                sync_encoder.process_event(event)?;
+            }
+
+            let mut async_encoder = AsyncGifEncoder::new(Vec::new());
+            for event in events(delay) {
+                async_encoder.process_event(event).await?;
+            }
+
+            assert_eq!(async_encoder.into_inner(), sync_encoder.get_encoded_data());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_configured_state() -> Result<(), String> {
+        let configured = || GifEncoderState::new().with_lossy_level(20).with_frame_coalescing(255);
+        let mut sync_encoder = configured();
+        for event in events(10) {
+            sync_encoder.process_event(event)?;
+        }
+
+        let mut async_encoder = AsyncGifEncoder::with_state(configured(), Vec::new());
+        for event in events(10) {
+            async_encoder.process_event(event).await?;
+        }
+
+        // The three frames are within the threshold of each other, so only one is left
+        let output = async_encoder.into_inner();
+        assert_eq!(output, sync_encoder.get_encoded_data());
+        assert_eq!(crate::decoder::decode(&output)?.frames.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rejects_invalid_events() {
+        let mut encoder = AsyncGifEncoder::new(Vec::new());
+        assert!(encoder.process_event(GifEvent::EndFrame).await.is_err());
+        assert!(encoder.get_ref().is_empty());
+    }
+}
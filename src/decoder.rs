@@ -0,0 +1,520 @@
+// MIT License
+// Copyright (c) 2025 Gianluca Cannata <gcannata23@gmail.com>
+//
+// av-gif - A GIF encoder written in Rust
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+use weezl::{decode::Decoder, BitOrder};
+
+use crate::encoder::{
+    interlaced_rows, DisposalMethod, FrameRect, GifEvent, GifVersion, LoopApplication, LoopConfig,
+    LoopCount, ScreenOptions,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedFrame {
+    pub left: u16,
+    pub top: u16,
+    pub width: u16,
+    pub height: u16,
+    pub delay: u16,
+    pub disposal_method: DisposalMethod,
+    pub transparent_color_index: Option<u8>,
+    pub user_input: bool,
+    pub is_interlaced: bool,
+    pub local_palette: Option<Vec<[u8; 3]>>,
+    pub local_palette_sorted: bool,
+    pub indices: Vec<u8>, // Palette indices in display order, deinterlaced if needed
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedGif {
+    pub version: GifVersion,
+    pub width: u16,
+    pub height: u16,
+    pub global_palette: Option<Vec<[u8; 3]>>,
+    pub background_color_index: u8,
+    pub screen: ScreenOptions,
+    pub looping: LoopConfig,
+    pub comments: Vec<Vec<u8>>,
+    pub frames: Vec<DecodedFrame>,
+}
+
+impl DecodedGif {
+    // Events that write this GIF again, to feed an encoder with `replay::replay`. Image
+    // data is recompressed and the encoder picks the version the stream needs.
+    pub fn events(&self) -> Vec<GifEvent<'_>> {
+        let mut events = vec![GifEvent::StartGif {
+            width: self.width,
+            height: self.height,
+            global_palette: self.global_palette.as_deref().map(Into::into),
+            background_color_index: self.background_color_index,
+            looping: self.looping.clone(),
+            version: None,
+            screen: self.screen,
+        }];
+
+        for comment in &self.comments {
+            events.push(GifEvent::Comment {
+                text: comment.as_slice().into(),
+            });
+        }
+
+        for frame in &self.frames {
+            events.push(GifEvent::StartFrame {
+                delay: frame.delay,
+                disposal_method: frame.disposal_method,
+                global_palette: None,
+                local_palette: frame.local_palette.as_deref().map(Into::into),
+                local_palette_sorted: frame.local_palette_sorted,
+                transparent_color_index: frame.transparent_color_index,
+                user_input: frame.user_input,
+                is_interlaced: frame.is_interlaced,
+                rect: Some(frame.rect()),
+            });
+            events.push(GifEvent::WriteImageChunk {
+                data: frame.indices.as_slice().into(),
+            });
+            events.push(GifEvent::EndFrame);
+        }
+
+        events.push(GifEvent::EndGif);
+        events
+    }
+}
+
+impl DecodedFrame {
+    pub fn rect(&self) -> FrameRect {
+        FrameRect::new(self.left, self.top, self.width, self.height)
+    }
+
+    // Screen coordinates of every pixel in the frame, with its position in `indices`
+    fn rect_pixels(&self) -> impl Iterator<Item = (usize, usize, usize)> + Clone {
+        let (left, top, width) = (self.left as usize, self.top as usize, self.width as usize);
+        (0..self.height as usize)
+            .flat_map(move |y| (0..width).map(move |x| (left + x, top + y, y * width + x)))
+    }
+}
+
+// Screen contents, one color per pixel, None where nothing opaque is shown
+pub type Canvas = Vec<Option<[u8; 3]>>;
+
+// Graphic control values waiting for the image they apply to
+#[derive(Debug, Default)]
+struct GraphicControl {
+    delay: u16,
+    disposal_method: Option<DisposalMethod>,
+    transparent_color_index: Option<u8>,
+    user_input: bool,
+}
+
+pub(crate) struct Reader<'a> {
+    pub(crate) data: &'a [u8],
+    pub(crate) position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn u8(&mut self) -> Result<u8, String> {
+        let byte = *self
+            .data
+            .get(self.position)
+            .ok_or_else(|| format!("Unexpected end of data at offset {}", self.position))?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.position + len;
+        let bytes = self
+            .data
+            .get(self.position..end)
+            .ok_or_else(|| format!("Unexpected end of data at offset {}", self.position))?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn color_table(&mut self, size_field: u8) -> Result<Vec<[u8; 3]>, String> {
+        let len = 2usize << (size_field & 0b0000_0111);
+        let bytes = self.bytes(len * 3)?;
+        Ok(bytes
+            .chunks_exact(3)
+            .map(|color| [color[0], color[1], color[2]])
+            .collect())
+    }
+
+    // Concatenated contents of a sub-block chain, up to and including its terminator
+    pub(crate) fn sub_blocks(&mut self) -> Result<Vec<u8>, String> {
+        let mut data = Vec::new();
+        loop {
+            let len = self.u8()? as usize;
+            if len == 0 {
+                return Ok(data);
+            }
+            data.extend_from_slice(self.bytes(len)?);
+        }
+    }
+
+    // Number of sub-blocks in a chain and their total size, without copying them
+    pub(crate) fn skip_sub_blocks(&mut self) -> Result<(usize, usize), String> {
+        let (mut count, mut size) = (0, 0);
+        loop {
+            let len = self.u8()? as usize;
+            if len == 0 {
+                return Ok((count, size));
+            }
+            self.bytes(len)?;
+            count += 1;
+            size += len;
+        }
+    }
+}
+
+pub fn disposal_method_from_bits(bits: u8) -> DisposalMethod {
+    match bits & 0b0000_0111 {
+        1 => DisposalMethod::Keep,
+        2 => DisposalMethod::Background,
+        3 => DisposalMethod::Previous,
+        _ => DisposalMethod::None,
+    }
+}
+
+pub fn decode(data: &[u8]) -> Result<DecodedGif, String> {
+    let mut reader = Reader { data, position: 0 };
+
+    let version = match reader.bytes(6)? {
+        b"GIF87a" => GifVersion::Gif87a,
+        b"GIF89a" => GifVersion::Gif89a,
+        _ => return Err("Not a GIF file".to_string()),
+    };
+
+    // Logical Screen Descriptor
+    let width = reader.u16()?;
+    let height = reader.u16()?;
+    let packed_fields = reader.u8()?;
+    let background_color_index = reader.u8()?;
+    let pixel_aspect_ratio = reader.u8()?;
+
+    let screen = ScreenOptions {
+        pixel_aspect_ratio,
+        color_resolution: ((packed_fields >> 4) & 0b0000_0111) + 1,
+        sort_flag: packed_fields & 0b0000_1000 != 0,
+    };
+
+    let global_palette = if packed_fields & 0b1000_0000 != 0 {
+        Some(reader.color_table(packed_fields)?)
+    } else {
+        None
+    };
+
+    let mut gif = DecodedGif {
+        version,
+        width,
+        height,
+        global_palette,
+        background_color_index,
+        screen,
+        looping: LoopConfig::once(),
+        comments: Vec::new(),
+        frames: Vec::new(),
+    };
+
+    let mut graphic_control: Option<GraphicControl> = None;
+
+    loop {
+        match reader.u8()? {
+            // Extension Introducer
+            0x21 => {
+                let label = reader.u8()?;
+                let body = reader.sub_blocks()?;
+
+                match label {
+                    0xF9 => graphic_control = Some(parse_graphic_control(&body)?),
+                    0xFE => gif.comments.push(body),
+                    0xFF => parse_application(&body, &mut gif.looping),
+                    _ => {} // Plain text and unknown extensions are skipped
+                }
+            }
+
+            // Image Separator
+            0x2C => {
+                let frame = decode_frame(&mut reader, graphic_control.take().unwrap_or_default())?;
+                gif.frames.push(frame);
+            }
+
+            // GIF Trailer
+            0x3B => return Ok(gif),
+
+            byte => {
+                return Err(format!(
+                    "Unexpected block introducer 0x{:02X} at offset {}",
+                    byte,
+                    reader.position - 1
+                ))
+            }
+        }
+    }
+}
+
+fn parse_graphic_control(body: &[u8]) -> Result<GraphicControl, String> {
+    if body.len() < 4 {
+        return Err("Graphic control extension is too short".to_string());
+    }
+
+    let packed_fields = body[0];
+    Ok(GraphicControl {
+        delay: u16::from_le_bytes([body[1], body[2]]),
+        disposal_method: Some(disposal_method_from_bits(packed_fields >> 2)),
+        transparent_color_index: (packed_fields & 0b0000_0001 != 0).then_some(body[3]),
+        user_input: packed_fields & 0b0000_0010 != 0,
+    })
+}
+
+fn parse_application(body: &[u8], looping: &mut LoopConfig) {
+    // The identifier block and the data sub-blocks were joined by `sub_blocks`
+    let application = match body.get(..11) {
+        Some(b"NETSCAPE2.0") => LoopApplication::Netscape,
+        Some(b"ANIMEXTS1.0") => LoopApplication::AnimExts,
+        _ => return,
+    };
+    looping.application = application;
+
+    let mut data = &body[11..];
+    while let Some(&id) = data.first() {
+        match (id, data.len()) {
+            (0x01, 3..) => {
+                looping.count = LoopCount::from_disk(Some(u16::from_le_bytes([data[1], data[2]])));
+                data = &data[3..];
+            }
+            (0x02, 5..) => {
+                looping.buffer_size = Some(u32::from_le_bytes([data[1], data[2], data[3], data[4]]));
+                data = &data[5..];
+            }
+            _ => break,
+        }
+    }
+}
+
+fn decode_frame(reader: &mut Reader, graphic_control: GraphicControl) -> Result<DecodedFrame, String> {
+    let left = reader.u16()?;
+    let top = reader.u16()?;
+    let width = reader.u16()?;
+    let height = reader.u16()?;
+    let packed_fields = reader.u8()?;
+
+    let local_palette = if packed_fields & 0b1000_0000 != 0 {
+        Some(reader.color_table(packed_fields)?)
+    } else {
+        None
+    };
+
+    let min_code_size = reader.u8()?;
+    if !(2..=8).contains(&min_code_size) {
+        return Err(format!("Invalid LZW minimum code size {}", min_code_size));
+    }
+
+    let compressed_data = reader.sub_blocks()?;
+    let mut indices = Decoder::new(BitOrder::Lsb, min_code_size)
+        .decode(&compressed_data)
+        .map_err(|err| format!("Invalid image data: {:?}", err))?;
+    // Never padded: the size comes from the untrusted descriptor, and a few bytes of input
+    // could otherwise make us allocate gigabytes
+    let pixel_count = width as usize * height as usize;
+    if indices.len() < pixel_count {
+        return Err(format!("Frame of {}x{} has only {} pixels of image data", width, height, indices.len()));
+    }
+    indices.truncate(pixel_count);
+
+    let is_interlaced = packed_fields & 0b0100_0000 != 0;
+    if is_interlaced {
+        indices = deinterlace(&indices, width, height);
+    }
+
+    Ok(DecodedFrame {
+        left,
+        top,
+        width,
+        height,
+        delay: graphic_control.delay,
+        disposal_method: graphic_control.disposal_method.unwrap_or(DisposalMethod::None),
+        transparent_color_index: graphic_control.transparent_color_index,
+        user_input: graphic_control.user_input,
+        is_interlaced,
+        local_palette,
+        local_palette_sorted: packed_fields & 0b0010_0000 != 0,
+        indices,
+    })
+}
+
+// Put the rows of an interlaced image back in display order
+pub fn deinterlace(data: &[u8], width: u16, height: u16) -> Vec<u8> {
+    let width = width as usize;
+    let mut deinterlaced = vec![0; width * height as usize];
+
+    for (stored, row) in interlaced_rows(height).enumerate() {
+        let Some(source) = data.get(stored * width..(stored + 1) * width) else {
+            break;
+        };
+        deinterlaced[row * width..(row + 1) * width].copy_from_slice(source);
+    }
+
+    deinterlaced
+}
+
+// Screen contents after each frame is drawn, following the disposal methods. The screen
+// starts out transparent and Background disposal clears back to transparent, as browsers
+// do, so the background color index is not used.
+pub fn render(gif: &DecodedGif) -> Result<Vec<Canvas>, String> {
+    let (width, height) = (gif.width as usize, gif.height as usize);
+    let mut canvas: Canvas = vec![None; width * height];
+    let mut canvases = Vec::with_capacity(gif.frames.len());
+
+    for (number, frame) in gif.frames.iter().enumerate() {
+        let palette = frame
+            .local_palette
+            .as_ref()
+            .or(gif.global_palette.as_ref())
+            .ok_or(format!("Frame {} has no color table", number))?;
+
+        let saved = (frame.disposal_method == DisposalMethod::Previous).then(|| canvas.clone());
+
+        // Parts of the frame outside the screen are clipped
+        let visible = |x: usize, y: usize| x < width && y < height;
+        let pixels = frame.rect_pixels();
+
+        for (x, y, i) in pixels.clone() {
+            let index = frame.indices[i];
+            if Some(index) == frame.transparent_color_index || !visible(x, y) {
+                continue;
+            }
+            canvas[y * width + x] = Some(palette.get(index as usize).copied().unwrap_or([0, 0, 0]));
+        }
+
+        canvases.push(canvas.clone());
+
+        match frame.disposal_method {
+            DisposalMethod::Background => {
+                for (x, y, _) in pixels.filter(|&(x, y, _)| visible(x, y)) {
+                    canvas[y * width + x] = None;
+                }
+            }
+            DisposalMethod::Previous => canvas = saved.unwrap_or(canvas),
+            DisposalMethod::None | DisposalMethod::Keep => {}
+        }
+    }
+
+    Ok(canvases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::{GifEncoder, GifEncoderState, GifEvent};
+
+    #[test]
+    fn test_decode_encoder_output() -> Result<(), String> {
+        let mut encoder = GifEncoderState::new();
+        let palette = vec![[0, 0, 0], [255, 0, 0], [0, 255, 0]];
+        let pixels: Vec<u8> = (0..12 * 5).map(|i| (i % 3) as u8).collect();
+
+        encoder.process_event(GifEvent::StartGif { width: 12, height: 5, global_palette: Some(palette.as_slice().into()), background_color_index: 2, looping: LoopConfig::infinite(), version: None, screen: ScreenOptions::default() })?;
+        encoder.process_event(GifEvent::Comment { text: b"made by av-gif".as_slice().into() })?;
+        for delay in [0, 25] {
+            encoder.process_event(GifEvent::StartFrame { delay, disposal_method: DisposalMethod::Background, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: Some(2), user_input: false, is_interlaced: false, rect: None })?;
+            encoder.process_event(GifEvent::WriteImageChunk { data: pixels[..30].into() })?;
+            encoder.process_event(GifEvent::WriteImageChunk { data: pixels[30..].into() })?;
+            encoder.process_event(GifEvent::EndFrame)?;
+        }
+        encoder.process_event(GifEvent::EndGif)?;
+
+        crate::validate::assert_valid(encoder.get_encoded_data());
+        let gif = decode(encoder.get_encoded_data())?;
+        assert_eq!(gif.version, GifVersion::Gif89a);
+        assert_eq!((gif.width, gif.height), (12, 5));
+        assert_eq!(gif.background_color_index, 2);
+        assert_eq!(gif.global_palette, Some(vec![[0, 0, 0], [255, 0, 0], [0, 255, 0], [0, 0, 0]]));
+        assert_eq!(gif.looping, LoopConfig::infinite());
+        assert_eq!(gif.comments, vec![b"made by av-gif".to_vec()]);
+
+        assert_eq!(gif.frames.len(), 2);
+        assert_eq!(gif.frames[1].delay, 25);
+        for frame in &gif.frames {
+            assert_eq!(frame.disposal_method, DisposalMethod::Background);
+            assert_eq!(frame.transparent_color_index, Some(2));
+            assert_eq!(frame.indices, pixels);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_input_flag() -> Result<(), String> {
+        let mut encoder = GifEncoderState::new();
+
+        encoder.process_event(GifEvent::StartGif { width: 1, height: 1, global_palette: Some(vec![[0, 0, 0], [255, 255, 255]].into()), background_color_index: 0, looping: LoopConfig::once(), version: None, screen: ScreenOptions::default() })?;
+        for user_input in [true, false] {
+            encoder.process_event(GifEvent::StartFrame { delay: 0, disposal_method: DisposalMethod::None, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: None, user_input, is_interlaced: false, rect: None })?;
+            encoder.process_event(GifEvent::WriteImageChunk { data: vec![1].into() })?;
+            encoder.process_event(GifEvent::EndFrame)?;
+        }
+        encoder.process_event(GifEvent::EndGif)?;
+
+        crate::validate::assert_valid(encoder.get_encoded_data());
+        let gif = decode(encoder.get_encoded_data())?;
+
+        // Waiting for input needs a graphic control extension, hence GIF89a
+        assert_eq!(gif.version, GifVersion::Gif89a);
+        assert!(gif.frames[0].user_input);
+        assert!(!gif.frames[1].user_input);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_truncated_data() {
+        assert!(decode(b"GIF89a\x01\x00").is_err());
+        assert!(decode(b"PNG").is_err());
+
+        // One pixel of data for a 65535x65535 frame
+        let huge_frame = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff\x2c\x00\x00\x00\x00\xff\xff\xff\xff\x00\x02\x02\x44\x01\x00\x3b";
+        assert_eq!(decode(huge_frame).err(), Some("Frame of 65535x65535 has only 1 pixels of image data".to_string()));
+    }
+
+    #[test]
+    fn test_deinterlace_round_trip() {
+        let mut writer = crate::encoder::GifWriter::new();
+
+        for height in 1..=17u16 {
+            let width = 3u16;
+            let data: Vec<u8> = (0..width as usize * height as usize).map(|i| i as u8).collect();
+
+            let interlaced = writer.encode_interlaced_data(&data, width, height);
+            assert_eq!(deinterlace(&interlaced, width, height), data, "height {}", height);
+        }
+    }
+
+    #[test]
+    fn test_decode_interlaced_frames() -> Result<(), String> {
+        for height in 1..=17u16 {
+            let mut encoder = GifEncoderState::new();
+            let pixels: Vec<u8> = (0..4 * height as usize).map(|i| (i / 4 % 4) as u8).collect();
+
+            encoder.process_event(GifEvent::StartGif { width: 4, height, global_palette: Some(vec![[0, 0, 0], [85, 85, 85], [170, 170, 170], [255, 255, 255]].into()), background_color_index: 0, looping: LoopConfig::once(), version: None, screen: ScreenOptions::default() })?;
+            encoder.process_event(GifEvent::StartFrame { delay: 0, disposal_method: DisposalMethod::None, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: None, user_input: false, is_interlaced: true, rect: None })?;
+            encoder.process_event(GifEvent::WriteImageChunk { data: pixels.as_slice().into() })?;
+            encoder.process_event(GifEvent::EndFrame)?;
+            encoder.process_event(GifEvent::EndGif)?;
+
+            crate::validate::assert_valid(encoder.get_encoded_data());
+            let gif = decode(encoder.get_encoded_data())?;
+            assert!(gif.frames[0].is_interlaced);
+            assert_eq!(gif.frames[0].indices, pixels, "height {}", height);
+        }
+        Ok(())
+    }
+}
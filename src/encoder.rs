@@ -2,11 +2,19 @@
 // Copyright (c) 2025 Gianluca Cannata <gcannata23@gmail.com>
 //
 // av-gif - A GIF encoder written in Rust
-use std::borrow::Cow;
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
 
-use crate::lzw::LzwEncoder;
+use weezl::{decode::Decoder, BitOrder};
 
-#[derive(Debug, PartialEq)]
+use crate::decoder::deinterlace;
+use crate::lzw::{LossyStats, LzwEncoder};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DisposalMethod {
     None,       // 0 - No disposal specified
     Keep,       // 1 - Keep previous image
@@ -14,46 +22,317 @@ pub enum DisposalMethod {
     Previous,   // 3 - Restore previous frame
 }
 
-#[derive(Debug)]
+// How many times an animation is played
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LoopCount {
+    Once,        // Play once, no loop extension is written
+    Infinite,    // Loop forever (on-disk count 0)
+    Repeat(u16), // Repeat N times after the first play (on-disk count N)
+}
+
+impl LoopCount {
+    // Loop count as stored in the application extension, `None` when no extension is needed
+    pub fn to_disk(self) -> Option<u16> {
+        match self {
+            LoopCount::Once | LoopCount::Repeat(0) => None,
+            LoopCount::Infinite => Some(0),
+            LoopCount::Repeat(count) => Some(count),
+        }
+    }
+
+    pub fn from_disk(count: Option<u16>) -> Self {
+        match count {
+            None => LoopCount::Once,
+            Some(0) => LoopCount::Infinite,
+            Some(count) => LoopCount::Repeat(count),
+        }
+    }
+}
+
+// Application identifier used for the looping extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LoopApplication {
+    Netscape, // NETSCAPE2.0, understood by every browser
+    AnimExts, // ANIMEXTS1.0, same layout with a different identifier
+}
+
+impl LoopApplication {
+    pub fn identifier(self) -> &'static [u8; 11] {
+        match self {
+            LoopApplication::Netscape => b"NETSCAPE2.0",
+            LoopApplication::AnimExts => b"ANIMEXTS1.0",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LoopConfig {
+    pub count: LoopCount,
+    pub application: LoopApplication,
+    // Optional buffering hint (sub-block id 2): bytes to buffer before playback
+    pub buffer_size: Option<u32>,
+}
+
+impl LoopConfig {
+    pub fn once() -> Self {
+        LoopConfig {
+            count: LoopCount::Once,
+            application: LoopApplication::Netscape,
+            buffer_size: None,
+        }
+    }
+
+    pub fn infinite() -> Self {
+        LoopConfig {
+            count: LoopCount::Infinite,
+            ..Self::once()
+        }
+    }
+
+    pub fn repeat(count: u16) -> Self {
+        LoopConfig {
+            count: LoopCount::Repeat(count),
+            ..Self::once()
+        }
+    }
+
+    pub fn with_application(mut self, application: LoopApplication) -> Self {
+        self.application = application;
+        self
+    }
+
+    pub fn with_buffer_size(mut self, buffer_size: u32) -> Self {
+        self.buffer_size = Some(buffer_size);
+        self
+    }
+}
+
+impl Default for LoopConfig {
+    fn default() -> Self {
+        Self::once()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GifVersion {
+    Gif87a, // No extensions at all, readable by the oldest decoders
+    Gif89a, // Needed for graphic control, application and comment extensions
+}
+
+impl GifVersion {
+    pub fn signature(self) -> &'static [u8; 6] {
+        match self {
+            GifVersion::Gif87a => b"GIF87a",
+            GifVersion::Gif89a => b"GIF89a",
+        }
+    }
+}
+
+// Logical Screen Descriptor fields that don't depend on the frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScreenOptions {
+    pub pixel_aspect_ratio: u8, // 0 = no information, otherwise aspect = (value + 15) / 64
+    pub color_resolution: u8,   // Bits per primary color in the source image (1..=8)
+    pub sort_flag: bool,        // Global color table is ordered by decreasing importance
+}
+
+impl ScreenOptions {
+    // Store a width / height pixel ratio, 1.0 is written as 0 since that's what decoders assume
+    pub fn with_aspect_ratio(mut self, ratio: f32) -> Self {
+        self.pixel_aspect_ratio = if (ratio - 1.0).abs() < f32::EPSILON || !ratio.is_finite() {
+            0
+        } else {
+            // Adding 0.5 rounds to nearest, the value is positive once clamped
+            (ratio * 64.0 - 15.0 + 0.5).clamp(1.0, 255.0) as u8
+        };
+        self
+    }
+
+    pub fn aspect_ratio(&self) -> Option<f32> {
+        match self.pixel_aspect_ratio {
+            0 => None,
+            value => Some((value as f32 + 15.0) / 64.0),
+        }
+    }
+}
+
+impl Default for ScreenOptions {
+    fn default() -> Self {
+        ScreenOptions {
+            pixel_aspect_ratio: 0,
+            color_resolution: 8,
+            sort_flag: false,
+        }
+    }
+}
+
+// Area of the screen covered by a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameRect {
+    pub left: u16,
+    pub top: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl FrameRect {
+    pub fn new(left: u16, top: u16, width: u16, height: u16) -> Self {
+        FrameRect {
+            left,
+            top,
+            width,
+            height,
+        }
+    }
+
+    pub fn fits(&self, screen_width: u16, screen_height: u16) -> bool {
+        self.width > 0
+            && self.height > 0
+            && self.left as u32 + self.width as u32 <= screen_width as u32
+            && self.top as u32 + self.height as u32 <= screen_height as u32
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum GifEvent<'a> {
     StartGif {
         width: u16,
         height: u16,
         global_palette: Option<Cow<'a, [[u8; 3]]>>, // Borrowed or owned palette
         background_color_index: u8,
-        loop_count: Option<u16>,
+        looping: LoopConfig,
+        version: Option<GifVersion>, // None picks GIF87a unless a 89a feature is used
+        screen: ScreenOptions,
     },
     StartFrame {
         delay: u16,
         disposal_method: DisposalMethod,
         global_palette: Option<Cow<'a, [[u8; 3]]>>,
         local_palette: Option<Cow<'a, [[u8; 3]]>>,
+        local_palette_sorted: bool, // Sets the sort flag of the local color table
         transparent_color_index: Option<u8>,
+        user_input: bool, // Wait for user input before moving on (delay acts as a timeout)
         is_interlaced: bool,
+        rect: Option<FrameRect>, // Part of the screen the frame covers, None for all of it
     },
     WriteImageChunk {
         data: Cow<'a, [u8]>, // Uncompressed image data
     },
+    WriteCompressedData {
+        data: Cow<'a, [u8]>, // LZW data compressed ahead of time with the frame's minimum code size
+    },
     FlushFrame, // Optional event to force buffer writing before EndFrame
     EndFrame,
+    Comment {
+        text: Cow<'a, [u8]>, // Written as a comment extension between frames
+    },
     EndGif,
 }
 
+impl GifEvent<'_> {
+    // Copy any borrowed data so the event can outlive its source, e.g. to record it
+    pub fn into_owned(self) -> GifEvent<'static> {
+        let owned = |palette: Option<Cow<[[u8; 3]]>>| palette.map(|palette| Cow::Owned(palette.into_owned()));
+
+        match self {
+            GifEvent::StartGif {
+                width,
+                height,
+                global_palette,
+                background_color_index,
+                looping,
+                version,
+                screen,
+            } => GifEvent::StartGif {
+                width,
+                height,
+                global_palette: owned(global_palette),
+                background_color_index,
+                looping,
+                version,
+                screen,
+            },
+            GifEvent::StartFrame {
+                delay,
+                disposal_method,
+                global_palette,
+                local_palette,
+                local_palette_sorted,
+                transparent_color_index,
+                user_input,
+                is_interlaced,
+                rect,
+            } => GifEvent::StartFrame {
+                delay,
+                disposal_method,
+                global_palette: owned(global_palette),
+                local_palette: owned(local_palette),
+                local_palette_sorted,
+                transparent_color_index,
+                user_input,
+                is_interlaced,
+                rect,
+            },
+            GifEvent::WriteImageChunk { data } => GifEvent::WriteImageChunk {
+                data: Cow::Owned(data.into_owned()),
+            },
+            GifEvent::WriteCompressedData { data } => GifEvent::WriteCompressedData {
+                data: Cow::Owned(data.into_owned()),
+            },
+            GifEvent::FlushFrame => GifEvent::FlushFrame,
+            GifEvent::EndFrame => GifEvent::EndFrame,
+            GifEvent::Comment { text } => GifEvent::Comment {
+                text: Cow::Owned(text.into_owned()),
+            },
+            GifEvent::EndGif => GifEvent::EndGif,
+        }
+    }
+}
+
 pub trait GifEncoder {
     fn process_event<'a>(&mut self, event: GifEvent<'a>) -> Result<(), String>;
 }
 
+// Minimal byte sink for streaming output, so it also works without std::io
+pub trait GifWrite {
+    fn write_all(&mut self, data: &[u8]) -> Result<(), String>;
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> GifWrite for W {
+    fn write_all(&mut self, data: &[u8]) -> Result<(), String> {
+        std::io::Write::write_all(self, data).map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl GifWrite for Vec<u8> {
+    fn write_all(&mut self, data: &[u8]) -> Result<(), String> {
+        self.extend_from_slice(data);
+        Ok(())
+    }
+}
+
 // State Transitions
 //
-// Current State | Event           | Next State    | Notes
-// Idle          | StartGif        | WritingHeader | Initialized GIF encoding
-// WritingHeader | StartFrame      | WritingFrame  | Begin a new frame
-// WritingFrame  | WriteImageChunk | WritingFrame  | Accept LZW-compressed image data
-// WritingFrame  | FlushFrame      | FlushingFrame | Force writing buffered data
-// WritingFrame  | EndFrame        | WritingHeader | End current frame
-// FlushingFrame | EndFrame        | WritingHeader | Ensure all data is written before moving on
-// WritingHeader | EndGif          | Finalizing    | Close GIF stream
-// Finalizing    | (Completed)     | Done          | GIF is fully encoded
+// Current State | Event               | Next State    | Notes
+// Idle          | StartGif            | WritingHeader | Initialized GIF encoding
+// WritingHeader | StartFrame          | WritingFrame  | Begin a new frame
+// WritingFrame  | WriteImageChunk     | WritingFrame  | Accept uncompressed image data
+// WritingFrame  | WriteCompressedData | WritingFrame  | Accept LZW-compressed image data instead
+// WritingFrame  | FlushFrame          | FlushingFrame | Force writing buffered data
+// WritingFrame  | EndFrame            | WritingHeader | End current frame
+// FlushingFrame | EndFrame            | WritingHeader | Ensure all data is written before moving on
+// WritingHeader | Comment             | WritingHeader | Write a comment extension (GIF89a only)
+// WritingHeader | EndGif              | Finalizing    | Close GIF stream
+// Finalizing    | (Completed)         | Done          | GIF is fully encoded
 #[derive(Debug, PartialEq)]
 pub enum EncoderState {
     Idle,          // Before 'StartGif'
@@ -64,6 +343,85 @@ pub enum EncoderState {
     Done,          // GIF is finalized
 }
 
+// A frame's events kept back while coalescing
+struct BufferedFrame {
+    events: Vec<GifEvent<'static>>, // StartFrame, the image data, EndFrame
+    rect: FrameRect,
+    palette_len: usize,
+    pixels: Option<Vec<Option<[u8; 3]>>>,
+}
+
+impl BufferedFrame {
+    // The error `process_direct` would give for `event` once this frame is written
+    fn check_event(&self, event: &GifEvent) -> Result<(), String> {
+        let has = |wanted: fn(&GifEvent) -> bool| self.events.iter().any(wanted);
+        match event {
+            _ if matches!(self.events.last(), Some(GifEvent::FlushFrame)) => {
+                Err("Invalid event for current state".to_string())
+            }
+            GifEvent::WriteImageChunk { .. } if has(|event| matches!(event, GifEvent::WriteCompressedData { .. })) => {
+                Err("Frame already has compressed data".to_string())
+            }
+            GifEvent::WriteImageChunk { data } => check_indices(data, self.palette_len),
+            GifEvent::WriteCompressedData { .. } if has(|event| matches!(event, GifEvent::WriteImageChunk { .. })) => {
+                Err("Frame already has uncompressed data".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Extend this frame's delay by `next`'s when showing `next` after it changes nothing
+    // on screen, telling whether it did
+    fn absorb(&mut self, next: &BufferedFrame, threshold: u8) -> bool {
+        let (
+            GifEvent::StartFrame {
+                delay,
+                disposal_method,
+                user_input,
+                ..
+            },
+            GifEvent::StartFrame {
+                delay: next_delay,
+                disposal_method: next_disposal_method,
+                user_input: next_user_input,
+                ..
+            },
+        ) = (&mut self.events[0], &next.events[0])
+        else {
+            return false;
+        };
+        let (Some(pixels), Some(next_pixels)) = (&self.pixels, &next.pixels) else {
+            return false;
+        };
+
+        // Clearing to the background shows what's under transparent pixels differently
+        // the second time, and frames waiting for input must stay separate
+        let same_result = self.rect == next.rect
+            && disposal_method == next_disposal_method
+            && !(*disposal_method == DisposalMethod::Background && pixels.contains(&None))
+            && !*user_input
+            && !*next_user_input;
+        let Some(total_delay) = delay.checked_add(*next_delay) else {
+            return false;
+        };
+
+        let looks_the_same = pixels.iter().zip(next_pixels).all(|pair| match pair {
+            (Some(color), Some(next_color)) => color
+                .iter()
+                .zip(next_color)
+                .all(|(&channel, &next_channel)| channel.abs_diff(next_channel) <= threshold),
+            (None, None) => true,
+            _ => false,
+        });
+
+        if !same_result || !looks_the_same {
+            return false;
+        }
+        *delay = total_delay;
+        true
+    }
+}
+
 pub struct GifEncoderState {
     state: EncoderState,
     writer: GifWriter,
@@ -71,15 +429,281 @@ pub struct GifEncoderState {
     frame_count: u16,
     width: u16,
     height: u16,
-    // Store loop count for animated GIFs
-    loop_count: Option<u16>,
+    // Store loop configuration for animated GIFs
+    looping: LoopConfig,
+    // Where the loop extension goes once a second frame shows the GIF is animated
+    loop_extension_offset: Option<usize>,
+    // Global palette, used when frames don't bring their own
+    global_palette: Option<Vec<[u8; 3]>>,
     is_interlaced: bool,
-    // Buffer in which we store the LZW compressed data
-    compressed_buffer: Vec<u8>,
+    // Position and size of the current frame, and the number of colors its indices can use
+    frame_rect: FrameRect,
+    palette_len: usize,
+    // Forced version, or None to pick the oldest one the stream allows
+    version: Option<GifVersion>,
+    // Set as soon as an extension only GIF89a understands is written
+    uses_89a: bool,
+    // Interlaced frames are reordered as a whole, so their pixels are buffered until the frame ends
+    frame_buffer: Vec<u8>,
+    // Whether the current frame got uncompressed or precompressed data
+    has_image_chunks: bool,
+    compressed_data: Option<Vec<u8>>,
+    // Lossy LZW level for every frame, 0 for lossless
+    lossy_level: u16,
+    lossy_stats: LossyStats,
+    // Largest per-channel difference for a frame to count as a repeat of the previous one,
+    // None when every frame is written
+    coalesce_threshold: Option<u8>,
+    // While coalescing: the frame being received, and the last one, held back in case
+    // the next frames repeat it and its delay grows
+    current_frame: Option<BufferedFrame>,
+    pending_frame: Option<BufferedFrame>,
+    coalesced_frames: usize,
 }
 
-impl GifEncoder for GifEncoderState {
-    fn process_event<'a>(&mut self, event: GifEvent<'a>) -> Result<(), String> {
+impl GifEncoderState {
+    pub fn new() -> Self {
+        GifEncoderState {
+            state: EncoderState::Idle,
+            writer: GifWriter::new(),
+            lzw_encoder: LzwEncoder::new(2),
+            frame_count: 0,
+            width: 0,
+            height: 0,
+            looping: LoopConfig::once(),
+            loop_extension_offset: None,
+            global_palette: None,
+            is_interlaced: false,
+            frame_rect: FrameRect::new(0, 0, 0, 0),
+            palette_len: 256,
+            version: None,
+            uses_89a: false,
+            frame_buffer: Vec::new(),
+            has_image_chunks: false,
+            compressed_data: None,
+            lossy_level: 0,
+            lossy_stats: LossyStats::default(),
+            coalesce_threshold: None,
+            current_frame: None,
+            pending_frame: None,
+            coalesced_frames: 0,
+        }
+    }
+
+    // Compress frames with lossy LZW (see `LzwEncoder::with_lossy`). Precompressed
+    // frame data is rejected, since it can't be recompressed.
+    pub fn with_lossy_level(mut self, level: u16) -> Self {
+        self.lossy_level = level;
+        self
+    }
+
+    // Size reduction from lossy LZW over all frames written so far
+    pub fn lossy_stats(&self) -> LossyStats {
+        self.lossy_stats
+    }
+
+    // Leave out frames that look the same as the one before, within `threshold` per color
+    // channel (0 for exact repeats), and add their delay to it instead. Each frame is held
+    // back until the next one arrives, since its graphic control extension isn't final
+    // before then.
+    pub fn with_frame_coalescing(mut self, threshold: u8) -> Self {
+        self.coalesce_threshold = Some(threshold);
+        self
+    }
+
+    // Number of frames left out by frame coalescing so far
+    pub fn coalesced_frames(&self) -> usize {
+        self.coalesced_frames
+    }
+
+    // Output not yet taken with `take_ready_output`
+    pub fn get_encoded_data(&self) -> &[u8] {
+        self.writer.get_encoded_data()
+    }
+
+    // Remove and return the output that won't change anymore, so it can be streamed out.
+    // The signature is held back while the version is undecided, and everything after it
+    // while the loop extension may still be inserted before the first frame.
+    pub fn take_ready_output(&mut self) -> Vec<u8> {
+        let ready = if self.version().is_none() {
+            0
+        } else {
+            self.loop_extension_offset
+                .unwrap_or(self.writer.buffer.len())
+        };
+
+        if let Some(offset) = self.loop_extension_offset.as_mut() {
+            *offset -= ready;
+        }
+        self.writer.buffer.drain(..ready).collect()
+    }
+
+    // Stream the output that's ready (see `take_ready_output`) to `writer`
+    pub fn write_ready_output<W: GifWrite + ?Sized>(&mut self, writer: &mut W) -> Result<(), String> {
+        let ready = self.take_ready_output();
+        if ready.is_empty() {
+            return Ok(());
+        }
+        writer.write_all(&ready)
+    }
+
+    // Version the output is known to have so far, None while it may still become GIF87a
+    pub fn version(&self) -> Option<GifVersion> {
+        match self.version {
+            Some(version) => Some(version),
+            None if self.uses_89a => Some(GifVersion::Gif89a),
+            None if self.state == EncoderState::Done => Some(GifVersion::Gif87a),
+            None => None,
+        }
+    }
+
+    fn check_89a(&self, feature: &str) -> Result<(), String> {
+        match self.version {
+            Some(GifVersion::Gif87a) => Err(format!("{} requires GIF89a", feature)),
+            _ => Ok(()),
+        }
+    }
+
+    fn require_89a(&mut self, feature: &str) -> Result<(), String> {
+        self.check_89a(feature)?;
+        self.uses_89a = true;
+        Ok(())
+    }
+
+    // What a StartFrame must pass before any of its frame is written or held back, so
+    // coalesced frames fail on the same event as direct ones. Returns the frame's rect.
+    fn check_start_frame(
+        &self,
+        local_palette: Option<&[[u8; 3]]>,
+        rect: Option<FrameRect>,
+        needs_graphic_control: bool,
+    ) -> Result<FrameRect, String> {
+        if let Some(palette) = local_palette {
+            check_palette(palette)?;
+        }
+
+        let rect = rect.unwrap_or(FrameRect::new(0, 0, self.width, self.height));
+        if !rect.fits(self.width, self.height) {
+            return Err(format!(
+                "Frame of {}x{} at {},{} doesn't fit the {}x{} screen",
+                rect.width, rect.height, rect.left, rect.top, self.width, self.height
+            ));
+        }
+
+        if needs_graphic_control {
+            self.check_89a("Graphic control extension")?;
+        }
+        Ok(rect)
+    }
+
+    // Each event was checked when it was buffered, and absorbing only adds up delays those
+    // checks allowed, so errors here come from the writer alone
+    fn commit_pending_frame(&mut self) -> Result<(), String> {
+        let Some(frame) = self.pending_frame.take() else {
+            return Ok(());
+        };
+        for event in frame.events {
+            self.process_direct(event)?;
+        }
+        Ok(())
+    }
+
+    // Colors a buffered frame shows, None for transparent pixels. Frames that can't be
+    // read this way are never coalesced and get their errors once written.
+    fn frame_pixels(&self, frame: &BufferedFrame) -> Option<Vec<Option<[u8; 3]>>> {
+        let GifEvent::StartFrame {
+            global_palette,
+            local_palette,
+            transparent_color_index,
+            is_interlaced,
+            ..
+        } = &frame.events[0]
+        else {
+            return None;
+        };
+
+        let global_palette = global_palette.as_deref().or(self.global_palette.as_deref());
+        let palette = local_palette.as_deref().or(global_palette)?;
+
+        let mut indices = Vec::new();
+        let mut compressed = Vec::new();
+        for event in &frame.events[1..] {
+            match event {
+                GifEvent::WriteImageChunk { data } => indices.extend_from_slice(data),
+                GifEvent::WriteCompressedData { data } => compressed.extend_from_slice(data),
+                _ => {}
+            }
+        }
+
+        // Precompressed data is stored in interlaced order when the frame is interlaced
+        if !compressed.is_empty() {
+            let min_code_size = GifWriter::calculate_min_code_size(global_palette, local_palette.as_deref());
+            indices = Decoder::new(BitOrder::Lsb, min_code_size).decode(&compressed).ok()?;
+            indices.resize(frame.rect.width as usize * frame.rect.height as usize, 0);
+            if *is_interlaced {
+                indices = deinterlace(&indices, frame.rect.width, frame.rect.height);
+            }
+        }
+
+        if indices.len() != frame.rect.width as usize * frame.rect.height as usize {
+            return None;
+        }
+
+        indices
+            .iter()
+            .map(|&index| match *transparent_color_index == Some(index) {
+                true => Some(None),
+                false => palette.get(index as usize).map(|&color| Some(color)),
+            })
+            .collect()
+    }
+
+    // Compress the pending pixels and write them out as image data sub-blocks
+    fn finish_frame_data(&mut self) {
+        self.has_image_chunks = false;
+        if let Some(compressed_data) = self.compressed_data.take() {
+            self.writer.write_image_data(&compressed_data);
+            return;
+        }
+
+        if self.is_interlaced {
+            let interlaced_data = self.writer.encode_interlaced_data(
+                &self.frame_buffer,
+                self.frame_rect.width,
+                self.frame_rect.height,
+            );
+            self.lzw_encoder.encode_chunk(&interlaced_data);
+            self.frame_buffer.clear();
+        }
+
+        self.lzw_encoder.finalize(); // Finalize encoding
+        if let Some(stats) = self.lzw_encoder.lossy_stats() {
+            self.lossy_stats.add(&stats);
+        }
+
+        self.writer
+            .write_image_data(self.lzw_encoder.get_encoded_data());
+        self.lzw_encoder.reset();
+    }
+}
+
+impl Default for GifEncoderState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GifEncoderState {
+    // Lossy LZW works on the indices, which precompressed data doesn't give us
+    fn check_uncompressed_only(&self) -> Result<(), String> {
+        match self.lossy_level {
+            0 => Ok(()),
+            _ => Err("Precompressed data can't be written with a lossy level".to_string()),
+        }
+    }
+
+    // Handle an event as soon as it arrives
+    fn process_direct(&mut self, event: GifEvent) -> Result<(), String> {
         match (&self.state, event) {
             (
                 EncoderState::Idle,
@@ -88,21 +712,42 @@ impl GifEncoder for GifEncoderState {
                     height,
                     background_color_index,
                     global_palette,
-                    loop_count,
+                    looping,
+                    version,
+                    screen,
                 },
             ) => {
+                if let Some(palette) = &global_palette {
+                    check_palette(palette)?;
+                }
+                if !(1..=8).contains(&screen.color_resolution) {
+                    return Err("Color resolution must be between 1 and 8 bits".to_string());
+                }
+                // Caught here rather than at the second frame, once a GIF87a frame is already out
+                if version == Some(GifVersion::Gif87a) && looping.count.to_disk().is_some() {
+                    return Err("Loop extension requires GIF89a".to_string());
+                }
+
                 self.state = EncoderState::WritingHeader;
+                self.version = version;
                 self.writer.write_gif_header(
+                    version.unwrap_or(GifVersion::Gif89a),
                     width,
                     height,
                     background_color_index,
                     global_palette.as_deref(),
-                    loop_count,
+                    &screen,
                 );
 
                 self.width = width;
                 self.height = height;
-                self.loop_count = loop_count;
+                self.global_palette = global_palette.map(|palette| palette.into_owned());
+
+                // A single frame never loops, so hold the extension back until a second frame arrives
+                if looping.count.to_disk().is_some() {
+                    self.loop_extension_offset = Some(self.writer.buffer.len());
+                }
+                self.looping = looping;
 
                 Ok(())
             }
@@ -114,68 +759,105 @@ impl GifEncoder for GifEncoderState {
                     disposal_method,
                     global_palette,
                     local_palette,
+                    local_palette_sorted,
                     transparent_color_index,
+                    user_input,
                     is_interlaced,
+                    rect,
                 },
             ) => {
+                let needs_graphic_control =
+                    needs_graphic_control(delay, disposal_method, transparent_color_index, user_input);
+                let rect = self.check_start_frame(local_palette.as_deref(), rect, needs_graphic_control)?;
+
+                let needs_loop_extension =
+                    self.frame_count == 1 && self.loop_extension_offset.is_some();
+                if needs_loop_extension {
+                    self.require_89a("Loop extension")?;
+                }
+
+                if needs_graphic_control {
+                    self.require_89a("Graphic control extension")?;
+                }
+
                 self.state = EncoderState::WritingFrame;
 
-                self.lzw_encoder.reset();
+                if needs_loop_extension {
+                    if let Some(offset) = self.loop_extension_offset.take() {
+                        self.writer.insert_loop_extension(offset, &self.looping);
+                    }
+                }
 
                 // Write Graphic Color Extension
-                self.writer.write_graphic_control_exension(
-                    disposal_method,
-                    delay,
+                if needs_graphic_control {
+                    self.writer.write_graphic_control_exension(
+                        disposal_method,
+                        delay,
+                        transparent_color_index,
+                        user_input,
+                    );
+                }
+
+                let global_palette = global_palette
+                    .as_deref()
+                    .or(self.global_palette.as_deref());
+                let min_code_size =
+                    GifWriter::calculate_min_code_size(global_palette, local_palette.as_deref());
+                self.lzw_encoder = LzwEncoder::new(min_code_size).with_lossy(
+                    self.lossy_level,
+                    local_palette.as_deref().or(global_palette).unwrap_or_default(),
                     transparent_color_index,
                 );
 
                 // Write Image Descriptor
                 self.writer.write_image_descriptor(
-                    0,
-                    0,
-                    self.width,
-                    self.height,
-                    global_palette.as_deref(),
+                    rect.left,
+                    rect.top,
+                    rect.width,
+                    rect.height,
+                    global_palette,
                     local_palette.as_deref(),
+                    local_palette_sorted,
                     is_interlaced,
                 );
 
                 self.is_interlaced = is_interlaced;
+                self.frame_rect = rect;
+                self.palette_len = local_palette.as_deref().or(global_palette).map_or(256, <[_]>::len);
 
                 Ok(())
             }
 
             (EncoderState::WritingFrame, GifEvent::WriteImageChunk { data }) => {
+                if self.compressed_data.is_some() {
+                    return Err("Frame already has compressed data".to_string());
+                }
+                check_indices(&data, self.palette_len)?;
+                self.has_image_chunks = true;
+
                 if self.is_interlaced {
-                    let interlaced_data =
-                        self.writer
-                            .encode_interlaced_data(data.as_ref(), self.width, self.height);
-                        self.lzw_encoder.encode_chunk(&interlaced_data);
+                    self.frame_buffer.extend_from_slice(&data);
                 } else {
                     self.lzw_encoder.encode_chunk(&data);
                 }
 
-                self.lzw_encoder.finalize(); // Finalize encoding
+                Ok(())
+            }
 
-                // Get the encoded data from the LZW encoder
-                let compressed_data = self.lzw_encoder.get_encoded_data();
-                self.compressed_buffer.extend_from_slice(compressed_data);
+            (EncoderState::WritingFrame, GifEvent::WriteCompressedData { data }) => {
+                if self.has_image_chunks {
+                    return Err("Frame already has uncompressed data".to_string());
+                }
+                self.check_uncompressed_only()?;
 
+                self.compressed_data
+                    .get_or_insert_with(Vec::new)
+                    .extend_from_slice(&data);
                 Ok(())
             }
 
             (EncoderState::WritingFrame, GifEvent::FlushFrame) => {
-                let compressed_data = &self.compressed_buffer;
-
-                // GIF stores image data in blocks (each max 255 bytes)
-                for chunk in compressed_data.chunks(255) {
-                    // Block size
-                    self.writer.buffer.push(chunk.len() as u8);
-                    self.writer.buffer.extend_from_slice(chunk);
-                }
-
-                // Block terminator
-                self.writer.buffer.push(0x00);
+                self.finish_frame_data();
 
                 self.state = EncoderState::FlushingFrame;
                 Ok(())
@@ -183,18 +865,31 @@ impl GifEncoder for GifEncoderState {
 
             (EncoderState::FlushingFrame, GifEvent::EndFrame)
             | (EncoderState::WritingFrame, GifEvent::EndFrame) => {
+                if self.state == EncoderState::WritingFrame {
+                    self.finish_frame_data();
+                }
+
                 self.state = EncoderState::WritingHeader;
-                self.writer.write_frame_trailer();
                 self.frame_count += 1;
 
-                self.compressed_buffer.clear();
-                self.lzw_encoder.reset();
+                Ok(())
+            }
 
+            (EncoderState::WritingHeader, GifEvent::Comment { text }) => {
+                self.require_89a("Comment extension")?;
+                self.writer.write_comment_extension(&text);
                 Ok(())
             }
 
             (EncoderState::WritingHeader, GifEvent::EndGif) => {
                 self.state = EncoderState::Finalizing;
+                self.loop_extension_offset = None;
+
+                // Nothing needed GIF89a, so fall back to the most compatible signature
+                if self.version.is_none() && !self.uses_89a {
+                    self.writer.set_version(GifVersion::Gif87a);
+                }
+
                 self.writer.write_gif_trailer();
                 self.state = EncoderState::Done;
                 Ok(())
@@ -205,10 +900,142 @@ impl GifEncoder for GifEncoderState {
     }
 }
 
+impl GifEncoder for GifEncoderState {
+    fn process_event<'a>(&mut self, event: GifEvent<'a>) -> Result<(), String> {
+        let Some(threshold) = self.coalesce_threshold else {
+            return self.process_direct(event);
+        };
+
+        if let Some(mut frame) = self.current_frame.take() {
+            return match event {
+                GifEvent::WriteImageChunk { .. } | GifEvent::WriteCompressedData { .. } | GifEvent::FlushFrame => {
+                    let result = frame.check_event(&event).and_then(|()| match &event {
+                        GifEvent::WriteCompressedData { .. } => self.check_uncompressed_only(),
+                        _ => Ok(()),
+                    });
+                    if result.is_ok() {
+                        frame.events.push(event.into_owned());
+                    }
+                    self.current_frame = Some(frame);
+                    result
+                }
+                GifEvent::EndFrame => {
+                    frame.events.push(GifEvent::EndFrame);
+                    frame.pixels = self.frame_pixels(&frame);
+
+                    let absorbed = self
+                        .pending_frame
+                        .as_mut()
+                        .is_some_and(|pending| pending.absorb(&frame, threshold));
+                    if absorbed {
+                        self.coalesced_frames += 1;
+                    } else {
+                        self.commit_pending_frame()?;
+                        self.pending_frame = Some(frame);
+                    }
+                    Ok(())
+                }
+                _ => {
+                    self.current_frame = Some(frame);
+                    Err("Invalid event for current state".to_string())
+                }
+            };
+        }
+
+        match event {
+            GifEvent::StartFrame {
+                delay,
+                disposal_method,
+                ref global_palette,
+                ref local_palette,
+                transparent_color_index,
+                user_input,
+                rect,
+                ..
+            } if self.state == EncoderState::WritingHeader => {
+                let needs_graphic_control =
+                    needs_graphic_control(delay, disposal_method, transparent_color_index, user_input);
+                let rect = self.check_start_frame(local_palette.as_deref(), rect, needs_graphic_control)?;
+
+                let palette_len = local_palette
+                    .as_deref()
+                    .or(global_palette.as_deref())
+                    .or(self.global_palette.as_deref())
+                    .map_or(256, <[_]>::len);
+                self.current_frame = Some(BufferedFrame {
+                    events: vec![event.into_owned()],
+                    rect,
+                    palette_len,
+                    pixels: None,
+                });
+                Ok(())
+            }
+
+            // Comments and the trailer go after the frame held back
+            event @ (GifEvent::Comment { .. } | GifEvent::EndGif) => {
+                self.commit_pending_frame()?;
+                self.process_direct(event)
+            }
+
+            event => self.process_direct(event),
+        }
+    }
+}
+
+// Rows of an interlaced image in the order they are stored
+pub fn interlaced_rows(height: u16) -> impl Iterator<Item = usize> {
+    let height = height as usize;
+
+    // First pass (every 8th row from 0)
+    (0..height)
+        .step_by(8)
+        // Second pass (every 8th row from 4)
+        .chain((4..height).step_by(8))
+        // Third pass (every 4th row from 2)
+        .chain((2..height).step_by(4))
+        // Fourth pass (every 2nd row from 1)
+        .chain((1..height).step_by(2))
+}
+
+// A graphic control extension with only default values is left out
+fn needs_graphic_control(
+    delay: u16,
+    disposal_method: DisposalMethod,
+    transparent_color_index: Option<u8>,
+    user_input: bool,
+) -> bool {
+    delay != 0 || disposal_method != DisposalMethod::None || transparent_color_index.is_some() || user_input
+}
+
+// Indices at or past the palette's end would show undefined colors, or be read back as
+// LZW control codes
+fn check_indices(data: &[u8], palette_len: usize) -> Result<(), String> {
+    match data.iter().find(|&&index| index as usize >= palette_len) {
+        Some(index) => Err(format!("Color index {} is outside the {} color palette", index, palette_len)),
+        None => Ok(()),
+    }
+}
+
+fn check_palette(palette: &[[u8; 3]]) -> Result<(), String> {
+    if palette.is_empty() || palette.len() > 256 {
+        return Err(format!(
+            "Palette must have between 1 and 256 colors, got {}",
+            palette.len()
+        ));
+    }
+    Ok(())
+}
+
 pub struct GifWriter {
     buffer: Vec<u8>,
 }
 
+impl Default for GifWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl GifWriter {
     pub fn new() -> Self {
         GifWriter { buffer: Vec::new() }
@@ -218,55 +1045,39 @@ impl GifWriter {
         &self.buffer
     }
 
-    fn calculate_min_code_size(global_palette: Option<&[[u8; 3]]>, local_palette: Option<&[[u8; 3]]>) -> u16 {
-        let mut palette_size = 256u16;
+    pub fn calculate_min_code_size(global_palette: Option<&[[u8; 3]]>, local_palette: Option<&[[u8; 3]]>) -> u8 {
+        let mut palette_size = 256usize;
         if let Some(global_palette) = global_palette {
-            palette_size = global_palette.len() as u16;
+            palette_size = global_palette.len();
         }
 
         if let Some(local_palette) = local_palette {
-            palette_size = local_palette.len() as u16;
+            palette_size = local_palette.len();
         }
 
-        let mut min_code_size = 1;
+        // Bits needed for the largest index, GIF doesn't allow less than 2
+        let mut min_code_size = 2;
         while (1 << min_code_size) < palette_size {
             min_code_size += 1;
         }
 
-        min_code_size + 1 // Add 1 to account for clear code and EOI code
+        min_code_size
     }
 
     pub fn encode_interlaced_data(&mut self, data: &[u8], width: u16, height: u16) -> Vec<u8> {
-        let mut interlaced_data = Vec::new();
-
-        // We need to iterate over the rows in interlaced order
-        let mut row_indices = Vec::new();
-
-        // First pass (rows 0, 2, 4, 6, ...)
-        for i in (0..height).step_by(8) {
-            row_indices.push(i);
-        }
-
-        // Second pass (rows 1, 3, 5, 7, ...)
-        for i in (1..height).step_by(8) {
-            row_indices.push(i);
-        }
-
-        // Third pass (rows 4, 5, 6, 7, ...)
-        for i in (2..height).step_by(4) {
-            row_indices.push(i);
-        }
-
-        // Fourth pass (rows 6, 7, ...)
-        for i in (3..height).step_by(4) {
-            row_indices.push(i);
-        }
+        let width = width as usize;
+        let mut interlaced_data = Vec::with_capacity(width * height as usize);
 
         // Encode the image using the interlaced row order
-        for row in row_indices {
-            let row_start = (row * width) as usize;
-            let row_end = row_start + width as usize;
-            interlaced_data.extend_from_slice(&data[row_start..row_end]);
+        for row in interlaced_rows(height) {
+            let row_start = row * width;
+            let row_end = row_start + width;
+
+            // Missing pixels are written as index 0 so the row count still matches the descriptor
+            match data.get(row_start..row_end) {
+                Some(row) => interlaced_data.extend_from_slice(row),
+                None => interlaced_data.resize(interlaced_data.len() + width, 0),
+            }
         }
 
         interlaced_data
@@ -274,14 +1085,15 @@ impl GifWriter {
 
     pub fn write_gif_header(
         &mut self,
+        version: GifVersion,
         width: u16,
         height: u16,
         background_index: u8,
         global_palette: Option<&[[u8; 3]]>,
-        loop_count: Option<u16>,
+        screen: &ScreenOptions,
     ) {
         // GIF signature + version
-        self.buffer.extend_from_slice(b"GIF89a");
+        self.buffer.extend_from_slice(version.signature());
 
         // Logical Screen Descriptor (LSD)
         self.buffer.extend_from_slice(&width.to_le_bytes());
@@ -291,8 +1103,13 @@ impl GifWriter {
         let mut packed_fields = 0u8;
         if let Some(palette) = global_palette {
             packed_fields |= 0b1000_0000; // Set GCT flag
-            let gct_size = ((palette.len() as u8).trailing_zeros() - 1) as u8;
-            packed_fields |= gct_size & 0b0000_0111; // Store GCT size
+            packed_fields |= Self::color_table_size_field(palette.len()); // Store GCT size
+        }
+
+        packed_fields |= (screen.color_resolution.clamp(1, 8) - 1) << 4;
+
+        if screen.sort_flag {
+            packed_fields |= 0b0000_1000; // Set the sort flag
         }
 
         self.buffer.push(packed_fields);
@@ -301,34 +1118,78 @@ impl GifWriter {
         self.buffer.push(background_index);
 
         // Pixel Aspect Ratio (0 = default aspect ratio)
-        self.buffer.push(0);
+        self.buffer.push(screen.pixel_aspect_ratio);
 
         // Write global palette if present
         if let Some(palette) = global_palette {
-            for color in palette {
-                self.buffer.extend_from_slice(color);
-            }
+            self.write_color_table(palette);
         }
+    }
+
+    // Number of entries a color table of `len` colors takes on disk (a power of two, at least 2)
+    pub fn color_table_len(len: usize) -> usize {
+        len.clamp(2, 256).next_power_of_two()
+    }
 
-        // Write loop count if this is an animated GIF
-        if let Some(loop_count) = loop_count {
-            // Netscape Extensions (looping behaviour)
-            self.buffer.push(0x21); // Exntesion Introducer
-            self.buffer.push(0xFF); // Application Extension Label
-            self.buffer.push(0x0B); // Block Size
-            self.buffer.extend_from_slice(b"NETSCAPE2.0");
-            self.buffer.push(0x03); // Subblock size
-            self.buffer.push(0x01); // Loop type (1 = loop)
-            self.buffer.extend_from_slice(&loop_count.to_le_bytes()); // Loop count
-            self.buffer.push(0x00); // Block terminator
+    // 3-bit size field: the table holds 2^(field + 1) entries
+    fn color_table_size_field(len: usize) -> u8 {
+        (Self::color_table_len(len).trailing_zeros() - 1) as u8
+    }
+
+    // Write the palette, padded with black up to the size declared in the packed fields
+    fn write_color_table(&mut self, palette: &[[u8; 3]]) {
+        for color in palette.iter().take(256) {
+            self.buffer.extend_from_slice(color);
+        }
+
+        for _ in palette.len()..Self::color_table_len(palette.len()) {
+            self.buffer.extend_from_slice(&[0, 0, 0]);
         }
     }
 
+    fn loop_extension(looping: &LoopConfig) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        let Some(loop_count) = looping.count.to_disk() else {
+            return bytes;
+        };
+
+        // Netscape Extensions (looping behaviour)
+        bytes.push(0x21); // Exntesion Introducer
+        bytes.push(0xFF); // Application Extension Label
+        bytes.push(0x0B); // Block Size
+        bytes.extend_from_slice(looping.application.identifier());
+        bytes.push(0x03); // Subblock size
+        bytes.push(0x01); // Loop sub-block id
+        bytes.extend_from_slice(&loop_count.to_le_bytes()); // Loop count
+
+        if let Some(buffer_size) = looping.buffer_size {
+            bytes.push(0x05); // Subblock size
+            bytes.push(0x02); // Buffering sub-block id
+            bytes.extend_from_slice(&buffer_size.to_le_bytes()); // Bytes to buffer
+        }
+
+        bytes.push(0x00); // Block terminator
+        bytes
+    }
+
+    pub fn write_loop_extension(&mut self, looping: &LoopConfig) {
+        let bytes = Self::loop_extension(looping);
+        self.buffer.extend_from_slice(&bytes);
+    }
+
+    // Loop extensions must precede the first image, so this splices one into already written output
+    pub fn insert_loop_extension(&mut self, offset: usize, looping: &LoopConfig) {
+        let bytes = Self::loop_extension(looping);
+        self.buffer.splice(offset..offset, bytes);
+    }
+
     pub fn write_graphic_control_exension(
         &mut self,
         disposal_method: DisposalMethod,
         delay: u16,
         transparent_color_index: Option<u8>,
+        user_input: bool,
     ) {
         self.buffer.push(0x21); // Extension Introducer
         self.buffer.push(0xF9); // Graphic Control Label
@@ -343,6 +1204,10 @@ impl GifWriter {
             DisposalMethod::Previous => packed_fields |= 0b0000_1100,
         }
 
+        if user_input {
+            packed_fields |= 0b0000_0010;
+        }
+
         if transparent_color_index.is_some() {
             packed_fields |= 0b0000_0001;
         }
@@ -359,6 +1224,7 @@ impl GifWriter {
         self.buffer.push(0x00);
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn write_image_descriptor(
         &mut self,
         left: u16,
@@ -367,6 +1233,7 @@ impl GifWriter {
         height: u16,
         global_palette: Option<&[[u8; 3]]>,
         local_palette: Option<&[[u8; 3]]>,
+        local_palette_sorted: bool,
         is_interlaced: bool,
     ) {
         self.buffer.push(0x2C); // Image Separator
@@ -383,30 +1250,59 @@ impl GifWriter {
         let mut packed_fields = 0u8;
         if let Some(palette) = local_palette {
             packed_fields |= 0b1000_0000; // Set LCT flag
-            let lct_size = ((palette.len() as u8).next_power_of_two().trailing_zeros() - 1) as u8;
-            packed_fields |= lct_size & 0b0000_0111; // Store LCT size
+            packed_fields |= Self::color_table_size_field(palette.len()); // Store LCT size
         }
 
         if is_interlaced {
             packed_fields |= 0b0100_0000; // Set the interlace flag
         }
 
+        if local_palette.is_some() && local_palette_sorted {
+            packed_fields |= 0b0010_0000; // Set the sort flag
+        }
+
         self.buffer.push(packed_fields);
 
         // Write local palette if present
         if let Some(palette) = local_palette {
-            for color in palette {
-                self.buffer.extend_from_slice(color);
-            }
+            self.write_color_table(palette);
         }
 
         // Calculate and write the LZW minimum code size
-        let min_code_size = Self::calculate_min_code_size( global_palette, local_palette);
-        self.buffer.extend_from_slice(&min_code_size.to_le_bytes());
+        let min_code_size = Self::calculate_min_code_size(global_palette, local_palette);
+        self.buffer.push(min_code_size);
+    }
+
+    pub fn write_image_data(&mut self, compressed_data: &[u8]) {
+        // GIF stores image data in blocks (each max 255 bytes)
+        for chunk in compressed_data.chunks(255) {
+            // Block size
+            self.buffer.push(chunk.len() as u8);
+            self.buffer.extend_from_slice(chunk);
+        }
+
+        // Block terminator
+        self.buffer.push(0x00);
+    }
+
+    // Rewrite the signature once the stream shows which version it needs
+    pub fn set_version(&mut self, version: GifVersion) {
+        if self.buffer.len() >= 6 {
+            self.buffer[..6].copy_from_slice(version.signature());
+        }
     }
 
-    pub fn write_frame_trailer(&mut self) {
-        // Frame Trailer
+    pub fn write_comment_extension(&mut self, text: &[u8]) {
+        self.buffer.push(0x21); // Extension Introducer
+        self.buffer.push(0xFE); // Comment Label
+
+        // Comment data is split in sub-blocks (each max 255 bytes)
+        for chunk in text.chunks(255) {
+            self.buffer.push(chunk.len() as u8);
+            self.buffer.extend_from_slice(chunk);
+        }
+
+        // Block Terminator
         self.buffer.push(0x00);
     }
 
@@ -425,43 +1321,559 @@ mod tests {
     #[test]
     fn test_single_frame_gif() -> Result<(), String> {
         // Create encoder
-        let mut encoder = GifEncoderState {
-            state: EncoderState::Idle,
-            writer: GifWriter {
-                buffer: Vec::new(),
-            },
-            lzw_encoder: LzwEncoder::new(2),
-            frame_count: 0,
-            width: 0,
-            height: 0,
-            loop_count: None,
-            is_interlaced: false,
-            compressed_buffer: Vec::new(),
-        };
+        let mut encoder = GifEncoderState::new();
 
-        // Create buffer of red pixels with 100x100 dimensions
-        let mut buffer = Vec::new();
-        for _ in 0..100 * 100 {
-            buffer.push(255);
-            buffer.push(0);
-            buffer.push(0);
-        }
+        // Create buffer of red pixels (index 0 of the palette) with 100x100 dimensions
+        let buffer = vec![0u8; 100 * 100];
 
         // Start processing the GIF
-        encoder.process_event(GifEvent::StartGif { width: 100u16, height: 100u16, global_palette: Some(vec![[255, 0, 0], [0, 0, 255]].into()), background_color_index: 0, loop_count: Some(0) })?;
-        encoder.process_event(GifEvent::StartFrame { delay: 0, disposal_method: DisposalMethod::None, global_palette: Some(vec![[255, 0, 0], [0, 0, 255]].into()), local_palette: None, transparent_color_index: None, is_interlaced: false })?;
+        encoder.process_event(GifEvent::StartGif { width: 100u16, height: 100u16, global_palette: Some(vec![[255, 0, 0], [0, 0, 255]].into()), background_color_index: 0, looping: LoopConfig::infinite(), version: None, screen: ScreenOptions::default() })?;
+        encoder.process_event(GifEvent::StartFrame { delay: 0, disposal_method: DisposalMethod::None, global_palette: Some(vec![[255, 0, 0], [0, 0, 255]].into()), local_palette: None, local_palette_sorted: false, transparent_color_index: None, user_input: false, is_interlaced: false, rect: None })?;
         encoder.process_event(GifEvent::WriteImageChunk { data: buffer.into() })?;
         encoder.process_event(GifEvent::FlushFrame)?;
         encoder.process_event(GifEvent::EndFrame)?;
         encoder.process_event(GifEvent::EndGif)?;
 
+        crate::validate::assert_valid(encoder.get_encoded_data());
+
         let file = std::fs::File::create("single_frame.gif").map_err(|err| err.to_string())?;
         let mut writer = std::io::BufWriter::new(file);
 
-        let _= writer.write(&encoder.writer.get_encoded_data());
+        let _= writer.write(encoder.writer.get_encoded_data());
 
         let _ = writer.flush();
 
         Ok(())
     }
+
+    fn encode_frames(frame_count: usize, looping: LoopConfig) -> Result<Vec<u8>, String> {
+        let mut encoder = GifEncoderState::new();
+        let palette = vec![[0, 0, 0], [255, 255, 255]];
+
+        encoder.process_event(GifEvent::StartGif { width: 2, height: 2, global_palette: Some(palette.into()), background_color_index: 0, looping, version: None, screen: ScreenOptions::default() })?;
+        for _ in 0..frame_count {
+            encoder.process_event(GifEvent::StartFrame { delay: 10, disposal_method: DisposalMethod::None, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: None, user_input: false, is_interlaced: false, rect: None })?;
+            encoder.process_event(GifEvent::WriteImageChunk { data: vec![0, 1, 1, 0].into() })?;
+            encoder.process_event(GifEvent::FlushFrame)?;
+            encoder.process_event(GifEvent::EndFrame)?;
+        }
+        encoder.process_event(GifEvent::EndGif)?;
+
+        crate::validate::assert_valid(encoder.get_encoded_data());
+        Ok(encoder.get_encoded_data().to_vec())
+    }
+
+    fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        haystack.windows(needle.len()).position(|window| window == needle)
+    }
+
+    #[test]
+    fn test_loop_count_disk_mapping() {
+        assert_eq!(LoopCount::Once.to_disk(), None);
+        assert_eq!(LoopCount::Repeat(0).to_disk(), None);
+        assert_eq!(LoopCount::Infinite.to_disk(), Some(0));
+        assert_eq!(LoopCount::Repeat(3).to_disk(), Some(3));
+
+        assert_eq!(LoopCount::from_disk(None), LoopCount::Once);
+        assert_eq!(LoopCount::from_disk(Some(0)), LoopCount::Infinite);
+        assert_eq!(LoopCount::from_disk(Some(3)), LoopCount::Repeat(3));
+    }
+
+    #[test]
+    fn test_single_frame_has_no_loop_extension() -> Result<(), String> {
+        let data = encode_frames(1, LoopConfig::infinite())?;
+        assert_eq!(find(&data, b"NETSCAPE2.0"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_play_once_has_no_loop_extension() -> Result<(), String> {
+        let data = encode_frames(3, LoopConfig::once())?;
+        assert_eq!(find(&data, b"NETSCAPE2.0"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_loop_extension_precedes_first_frame() -> Result<(), String> {
+        let data = encode_frames(2, LoopConfig::repeat(5))?;
+
+        // Header (6) + logical screen descriptor (7) + two color table entries (6)
+        let offset = 19;
+        assert_eq!(&data[offset..offset + 3], &[0x21, 0xFF, 0x0B]);
+        assert_eq!(&data[offset + 3..offset + 14], b"NETSCAPE2.0");
+        assert_eq!(&data[offset + 14..offset + 19], &[0x03, 0x01, 5, 0, 0x00]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_animexts_with_buffering_hint() -> Result<(), String> {
+        let looping = LoopConfig::infinite()
+            .with_application(LoopApplication::AnimExts)
+            .with_buffer_size(0x0001_0203);
+        let data = encode_frames(2, looping)?;
+
+        let offset = find(&data, b"ANIMEXTS1.0").ok_or("missing ANIMEXTS1.0 extension")?;
+        assert_eq!(
+            &data[offset + 11..offset + 22],
+            &[0x03, 0x01, 0, 0, 0x05, 0x02, 0x03, 0x02, 0x01, 0x00, 0x00]
+        );
+        assert_eq!(find(&data, b"NETSCAPE2.0"), None);
+        Ok(())
+    }
+
+    fn encode_still(version: Option<GifVersion>, delay: u16, comment: Option<&[u8]>) -> Result<Vec<u8>, String> {
+        let mut encoder = GifEncoderState::new();
+        let palette = vec![[0, 0, 0], [255, 255, 255]];
+
+        encoder.process_event(GifEvent::StartGif { width: 2, height: 2, global_palette: Some(palette.into()), background_color_index: 0, looping: LoopConfig::once(), version, screen: ScreenOptions::default() })?;
+        if let Some(text) = comment {
+            encoder.process_event(GifEvent::Comment { text: text.into() })?;
+        }
+        encoder.process_event(GifEvent::StartFrame { delay, disposal_method: DisposalMethod::None, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: None, user_input: false, is_interlaced: false, rect: None })?;
+        encoder.process_event(GifEvent::WriteImageChunk { data: vec![0, 1, 1, 0].into() })?;
+        encoder.process_event(GifEvent::EndFrame)?;
+        encoder.process_event(GifEvent::EndGif)?;
+
+        crate::validate::assert_valid(encoder.get_encoded_data());
+        Ok(encoder.get_encoded_data().to_vec())
+    }
+
+    #[test]
+    fn test_plain_stream_is_gif87a() -> Result<(), String> {
+        let data = encode_still(None, 0, None)?;
+        assert_eq!(&data[..6], b"GIF87a");
+        assert_eq!(find(&data, &[0x21, 0xF9]), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_version_resolution() -> Result<(), String> {
+        let mut encoder = GifEncoderState::new();
+
+        encoder.process_event(GifEvent::StartGif { width: 1, height: 1, global_palette: Some(vec![[0, 0, 0], [255, 255, 255]].into()), background_color_index: 0, looping: LoopConfig::once(), version: None, screen: ScreenOptions::default() })?;
+        assert_eq!(encoder.version(), None);
+
+        encoder.process_event(GifEvent::Comment { text: b"hello".as_slice().into() })?;
+        assert_eq!(encoder.version(), Some(GifVersion::Gif89a));
+        Ok(())
+    }
+
+    #[test]
+    fn test_extensions_select_gif89a() -> Result<(), String> {
+        assert_eq!(&encode_still(None, 10, None)?[..6], b"GIF89a");
+        assert_eq!(&encode_still(None, 0, Some(b"hello"))?[..6], b"GIF89a");
+        assert_eq!(&encode_frames(2, LoopConfig::infinite())?[..6], b"GIF89a");
+        Ok(())
+    }
+
+    #[test]
+    fn test_forced_version() -> Result<(), String> {
+        assert_eq!(&encode_still(Some(GifVersion::Gif89a), 0, None)?[..6], b"GIF89a");
+        assert_eq!(&encode_still(Some(GifVersion::Gif87a), 0, None)?[..6], b"GIF87a");
+
+        assert!(encode_still(Some(GifVersion::Gif87a), 10, None).is_err());
+        assert!(encode_still(Some(GifVersion::Gif87a), 0, Some(b"hello")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_forced_gif87a_rejects_loop_extension() -> Result<(), String> {
+        let mut encoder = GifEncoderState::new();
+
+        let result = encoder.process_event(GifEvent::StartGif { width: 1, height: 1, global_palette: Some(vec![[0, 0, 0], [255, 255, 255]].into()), background_color_index: 0, looping: LoopConfig::infinite(), version: Some(GifVersion::Gif87a), screen: ScreenOptions::default() });
+        assert_eq!(result, Err("Loop extension requires GIF89a".to_string()));
+        assert!(encoder.get_encoded_data().is_empty());
+
+        // Nothing was written, so the stream can still start over as GIF87a without looping
+        encoder.process_event(GifEvent::StartGif { width: 1, height: 1, global_palette: Some(vec![[0, 0, 0], [255, 255, 255]].into()), background_color_index: 0, looping: LoopConfig::once(), version: Some(GifVersion::Gif87a), screen: ScreenOptions::default() })?;
+        encoder.process_event(GifEvent::StartFrame { delay: 0, disposal_method: DisposalMethod::None, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: None, user_input: false, is_interlaced: false, rect: None })?;
+        encoder.process_event(GifEvent::WriteImageChunk { data: vec![0].into() })?;
+        encoder.process_event(GifEvent::EndFrame)?;
+        encoder.process_event(GifEvent::EndGif)?;
+        assert_eq!(&encoder.get_encoded_data()[..6], b"GIF87a");
+        Ok(())
+    }
+
+    #[test]
+    fn test_comment_extension_sub_blocks() {
+        let mut writer = GifWriter::new();
+        writer.write_comment_extension(&[b'a'; 300]);
+
+        let data = writer.get_encoded_data();
+        assert_eq!(&data[..3], &[0x21, 0xFE, 255]);
+        assert_eq!(data[3 + 255], 45);
+        assert_eq!(data.len(), 2 + 1 + 255 + 1 + 45 + 1);
+        assert_eq!(data[data.len() - 1], 0x00);
+    }
+
+    #[test]
+    fn test_logical_screen_descriptor_fields() {
+        let mut writer = GifWriter::new();
+        let palette = vec![[1, 2, 3]; 256];
+        let screen = ScreenOptions { color_resolution: 5, sort_flag: true, ..Default::default() }.with_aspect_ratio(0.5);
+        writer.write_gif_header(GifVersion::Gif89a, 4, 3, 7, Some(&palette), &screen);
+
+        let data = writer.get_encoded_data();
+        assert_eq!(&data[6..10], &[4, 0, 3, 0]);
+        assert_eq!(data[10], 0b1100_1111);
+        assert_eq!(data[11], 7);
+        assert_eq!(data[12], 17);
+        assert_eq!(data.len(), 13 + 256 * 3);
+        assert_eq!(screen.aspect_ratio(), Some(0.5));
+        assert_eq!(ScreenOptions::default().with_aspect_ratio(1.0).pixel_aspect_ratio, 0);
+    }
+
+    #[test]
+    fn test_color_tables_are_padded() {
+        let mut writer = GifWriter::new();
+        let palette = vec![[255, 0, 0], [0, 255, 0], [0, 0, 255]];
+        writer.write_gif_header(GifVersion::Gif89a, 1, 1, 0, Some(&palette), &ScreenOptions::default());
+
+        let data = writer.get_encoded_data();
+        assert_eq!(data[10] & 0b0000_0111, 1);
+        assert_eq!(data.len(), 13 + 4 * 3);
+        assert_eq!(&data[22..25], &[0, 0, 0]);
+
+        let mut writer = GifWriter::new();
+        writer.write_image_descriptor(0, 0, 1, 1, None, Some(&[[9, 9, 9]]), false, false);
+
+        let data = writer.get_encoded_data();
+        assert_eq!(data[9], 0b1000_0000);
+        assert_eq!(&data[10..16], &[9, 9, 9, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_color_table_len() {
+        assert_eq!(GifWriter::color_table_len(1), 2);
+        assert_eq!(GifWriter::color_table_len(2), 2);
+        assert_eq!(GifWriter::color_table_len(5), 8);
+        assert_eq!(GifWriter::color_table_len(129), 256);
+        assert_eq!(GifWriter::color_table_len(256), 256);
+    }
+
+    #[test]
+    fn test_out_of_range_indices_are_rejected() -> Result<(), String> {
+        for coalesce in [false, true] {
+            let mut encoder = GifEncoderState::new();
+            if coalesce {
+                encoder = encoder.with_frame_coalescing(0);
+            }
+
+            encoder.process_event(GifEvent::StartGif { width: 2, height: 1, global_palette: Some(vec![[0, 0, 0], [255, 255, 255]].into()), background_color_index: 0, looping: LoopConfig::once(), version: None, screen: ScreenOptions::default() })?;
+            encoder.process_event(GifEvent::StartFrame { delay: 0, disposal_method: DisposalMethod::None, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: None, user_input: false, is_interlaced: false, rect: None })?;
+            let result = encoder.process_event(GifEvent::WriteImageChunk { data: vec![0, 9].into() });
+            assert_eq!(result, Err("Color index 9 is outside the 2 color palette".to_string()));
+
+            // The local palette is the one that counts
+            encoder.process_event(GifEvent::WriteImageChunk { data: vec![0, 1].into() })?;
+            encoder.process_event(GifEvent::EndFrame)?;
+            encoder.process_event(GifEvent::StartFrame { delay: 0, disposal_method: DisposalMethod::None, global_palette: None, local_palette: Some(vec![[0, 0, 0]; 4].into()), local_palette_sorted: false, transparent_color_index: None, user_input: false, is_interlaced: false, rect: None })?;
+            encoder.process_event(GifEvent::WriteImageChunk { data: vec![3, 2].into() })?;
+            assert!(encoder.process_event(GifEvent::WriteImageChunk { data: vec![4].into() }).is_err());
+            encoder.process_event(GifEvent::EndFrame)?;
+            encoder.process_event(GifEvent::EndGif)?;
+
+            crate::validate::assert_valid(encoder.get_encoded_data());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_screen_options_are_rejected() {
+        let mut encoder = GifEncoderState::new();
+        let result = encoder.process_event(GifEvent::StartGif { width: 1, height: 1, global_palette: Some(vec![[0, 0, 0]; 257].into()), background_color_index: 0, looping: LoopConfig::once(), version: None, screen: ScreenOptions::default() });
+        assert!(result.is_err());
+
+        let mut encoder = GifEncoderState::new();
+        let screen = ScreenOptions { color_resolution: 9, ..Default::default() };
+        let result = encoder.process_event(GifEvent::StartGif { width: 1, height: 1, global_palette: None, background_color_index: 0, looping: LoopConfig::once(), version: None, screen });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_frequency_sorted_palettes_set_sort_flags() -> Result<(), String> {
+        use crate::quantization::{quantize, QuantizeOptions};
+
+        let mut pixels = Vec::new();
+        for color in [[9, 9, 9], [1, 1, 1], [1, 1, 1], [5, 5, 5]] {
+            pixels.extend_from_slice(&color);
+        }
+        let options = QuantizeOptions { sort_by_frequency: true, ..Default::default() };
+        let image = quantize(&pixels, 2, &options)?;
+
+        let mut encoder = GifEncoderState::new();
+        let screen = ScreenOptions { sort_flag: image.sorted, ..Default::default() };
+        encoder.process_event(GifEvent::StartGif { width: 2, height: 2, global_palette: Some(image.palette.as_slice().into()), background_color_index: 0, looping: LoopConfig::once(), version: None, screen })?;
+
+        let header_len = 13 + 4 * 3;
+        assert_eq!(encoder.get_encoded_data()[10] & 0b0000_1000, 0b0000_1000);
+        assert_eq!(&encoder.get_encoded_data()[13..16], &[1, 1, 1]);
+
+        encoder.process_event(GifEvent::StartFrame { delay: 0, disposal_method: DisposalMethod::None, global_palette: None, local_palette: Some(image.palette.as_slice().into()), local_palette_sorted: image.sorted, transparent_color_index: None, user_input: false, is_interlaced: false, rect: None })?;
+        assert_eq!(encoder.get_encoded_data()[header_len + 9], 0b1010_0001);
+        Ok(())
+    }
+
+    #[test]
+    fn test_interlaced_row_order() {
+        let rows: Vec<usize> = interlaced_rows(17).collect();
+        assert_eq!(rows, vec![0, 8, 16, 4, 12, 2, 6, 10, 14, 1, 3, 5, 7, 9, 11, 13, 15]);
+
+        for height in 1..=17u16 {
+            let mut rows: Vec<usize> = interlaced_rows(height).collect();
+            rows.sort();
+            assert_eq!(rows, (0..height as usize).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_interlaced_data_large_frame() {
+        // 300 * 300 overflows u16 row offsets
+        let (width, height) = (300u16, 300u16);
+        let data: Vec<u8> = (0..width as usize * height as usize).map(|i| (i / width as usize) as u8).collect();
+
+        let interlaced = GifWriter::new().encode_interlaced_data(&data, width, height);
+        assert_eq!(interlaced.len(), data.len());
+        assert_eq!(interlaced[width as usize], 8);
+    }
+
+    #[test]
+    fn test_take_ready_output() -> Result<(), String> {
+        let mut encoder = GifEncoderState::new();
+        let mut output = Vec::new();
+        let palette = vec![[0, 0, 0], [255, 255, 255]];
+
+        encoder.process_event(GifEvent::StartGif { width: 2, height: 2, global_palette: Some(palette.into()), background_color_index: 0, looping: LoopConfig::infinite(), version: None, screen: ScreenOptions::default() })?;
+
+        // The version isn't known yet
+        assert!(encoder.take_ready_output().is_empty());
+
+        for delay in [10, 20] {
+            encoder.process_event(GifEvent::StartFrame { delay, disposal_method: DisposalMethod::None, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: None, user_input: false, is_interlaced: false, rect: None })?;
+            encoder.process_event(GifEvent::WriteImageChunk { data: vec![0, 1, 1, 0].into() })?;
+            encoder.process_event(GifEvent::EndFrame)?;
+
+            output.extend(encoder.take_ready_output());
+            if delay == 10 {
+                // Only the header is ready until the loop extension is in place
+                assert_eq!(output.len(), 19);
+            } else {
+                assert!(encoder.get_encoded_data().is_empty());
+            }
+        }
+        encoder.process_event(GifEvent::EndGif)?;
+        output.extend(encoder.take_ready_output());
+
+        assert_eq!(output, encode_frames_with_delays(&[10, 20])?);
+        Ok(())
+    }
+
+    fn encode_frames_with_delays(delays: &[u16]) -> Result<Vec<u8>, String> {
+        let mut encoder = GifEncoderState::new();
+        let palette = vec![[0, 0, 0], [255, 255, 255]];
+
+        encoder.process_event(GifEvent::StartGif { width: 2, height: 2, global_palette: Some(palette.into()), background_color_index: 0, looping: LoopConfig::infinite(), version: None, screen: ScreenOptions::default() })?;
+        for &delay in delays {
+            encoder.process_event(GifEvent::StartFrame { delay, disposal_method: DisposalMethod::None, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: None, user_input: false, is_interlaced: false, rect: None })?;
+            encoder.process_event(GifEvent::WriteImageChunk { data: vec![0, 1, 1, 0].into() })?;
+            encoder.process_event(GifEvent::EndFrame)?;
+        }
+        encoder.process_event(GifEvent::EndGif)?;
+
+        crate::validate::assert_valid(encoder.get_encoded_data());
+        Ok(encoder.get_encoded_data().to_vec())
+    }
+
+    #[test]
+    fn test_write_ready_output() -> Result<(), String> {
+        let mut encoder = GifEncoderState::new();
+        let mut output: Vec<u8> = Vec::new();
+        let palette = vec![[0, 0, 0], [255, 255, 255]];
+
+        encoder.process_event(GifEvent::StartGif { width: 2, height: 2, global_palette: Some(palette.into()), background_color_index: 0, looping: LoopConfig::infinite(), version: None, screen: ScreenOptions::default() })?;
+        for delay in [10, 20] {
+            encoder.process_event(GifEvent::StartFrame { delay, disposal_method: DisposalMethod::None, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: None, user_input: false, is_interlaced: false, rect: None })?;
+            encoder.process_event(GifEvent::WriteImageChunk { data: vec![0, 1, 1, 0].into() })?;
+            encoder.process_event(GifEvent::EndFrame)?;
+            encoder.write_ready_output(&mut output)?;
+        }
+        encoder.process_event(GifEvent::EndGif)?;
+        encoder.write_ready_output(&mut output)?;
+
+        assert_eq!(output, encode_frames_with_delays(&[10, 20])?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lossy_level_shrinks_frames() -> Result<(), String> {
+        let palette: Vec<[u8; 3]> = (0..32u8).map(|i| [i * 8, i * 8, i * 8]).collect();
+        let pixels: Vec<u8> = (0..64 * 64u32).map(|i| ((i % 64 / 3) as u8 + (i * 7 % 5 == 0) as u8).min(31)).collect();
+
+        let mut outputs = Vec::new();
+        for level in [0, 30] {
+            let mut encoder = GifEncoderState::new().with_lossy_level(level);
+            encoder.process_event(GifEvent::StartGif { width: 64, height: 64, global_palette: Some(palette.as_slice().into()), background_color_index: 0, looping: LoopConfig::once(), version: None, screen: ScreenOptions::default() })?;
+            encoder.process_event(GifEvent::StartFrame { delay: 0, disposal_method: DisposalMethod::None, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: None, user_input: false, is_interlaced: false, rect: None })?;
+            encoder.process_event(GifEvent::WriteImageChunk { data: pixels.as_slice().into() })?;
+            encoder.process_event(GifEvent::EndFrame)?;
+            encoder.process_event(GifEvent::EndGif)?;
+
+            let stats = encoder.lossy_stats();
+            crate::validate::assert_valid(encoder.get_encoded_data());
+            outputs.push((encoder.get_encoded_data().to_vec(), stats));
+        }
+
+        let (lossless, lossless_stats) = &outputs[0];
+        let (lossy, stats) = &outputs[1];
+        assert_eq!(*lossless_stats, LossyStats::default());
+        assert!(lossy.len() < lossless.len());
+        assert!(stats.lossy_size < stats.lossless_size && stats.substituted_pixels > 0);
+        assert_eq!(crate::decoder::decode(lossy)?.frames[0].indices.len(), 64 * 64);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lossy_level_rejects_compressed_data() -> Result<(), String> {
+        for coalesce in [false, true] {
+            let mut encoder = GifEncoderState::new().with_lossy_level(30);
+            if coalesce {
+                encoder = encoder.with_frame_coalescing(0);
+            }
+            encoder.process_event(GifEvent::StartGif { width: 1, height: 1, global_palette: Some(vec![[0, 0, 0], [255, 255, 255]].into()), background_color_index: 0, looping: LoopConfig::once(), version: None, screen: ScreenOptions::default() })?;
+            encoder.process_event(GifEvent::StartFrame { delay: 0, disposal_method: DisposalMethod::None, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: None, user_input: false, is_interlaced: false, rect: None })?;
+            assert_eq!(
+                encoder.process_event(GifEvent::WriteCompressedData { data: vec![0x44, 0x01].into() }),
+                Err("Precompressed data can't be written with a lossy level".to_string())
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_frame_rect() -> Result<(), String> {
+        let mut encoder = GifEncoderState::new();
+        encoder.process_event(GifEvent::StartGif { width: 6, height: 4, global_palette: Some(vec![[0, 0, 0], [255, 255, 255]].into()), background_color_index: 0, looping: LoopConfig::once(), version: None, screen: ScreenOptions::default() })?;
+
+        let outside = GifEvent::StartFrame { delay: 0, disposal_method: DisposalMethod::None, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: None, user_input: false, is_interlaced: false, rect: Some(FrameRect::new(4, 0, 3, 1)) };
+        assert!(encoder.process_event(outside).is_err());
+
+        encoder.process_event(GifEvent::StartFrame { delay: 0, disposal_method: DisposalMethod::None, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: None, user_input: false, is_interlaced: true, rect: Some(FrameRect::new(2, 1, 3, 3)) })?;
+        encoder.process_event(GifEvent::WriteImageChunk { data: vec![0, 1, 0, 1, 1, 1, 0, 0, 1].into() })?;
+        encoder.process_event(GifEvent::EndFrame)?;
+        encoder.process_event(GifEvent::EndGif)?;
+
+        crate::validate::assert_valid(encoder.get_encoded_data());
+        let gif = crate::decoder::decode(encoder.get_encoded_data())?;
+        assert_eq!(gif.frames[0].rect(), FrameRect::new(2, 1, 3, 3));
+        assert_eq!(gif.frames[0].indices, vec![0, 1, 0, 1, 1, 1, 0, 0, 1]);
+        Ok(())
+    }
+
+    // Delay, disposal method, transparent index, user input and pixels of a 2x2 frame
+    type TestFrame = (u16, DisposalMethod, Option<u8>, bool, [u8; 4]);
+
+    fn encode_coalesced(threshold: u8, frames: &[TestFrame]) -> Result<(crate::decoder::DecodedGif, usize), String> {
+        let mut encoder = GifEncoderState::new().with_frame_coalescing(threshold);
+        encoder.process_event(GifEvent::StartGif { width: 2, height: 2, global_palette: Some(vec![[0, 0, 0], [255, 255, 255], [250, 250, 250], [255, 0, 0]].into()), background_color_index: 0, looping: LoopConfig::infinite(), version: None, screen: ScreenOptions::default() })?;
+        for &(delay, disposal_method, transparent_color_index, user_input, pixels) in frames {
+            encoder.process_event(GifEvent::StartFrame { delay, disposal_method, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index, user_input, is_interlaced: false, rect: None })?;
+            encoder.process_event(GifEvent::WriteImageChunk { data: pixels.to_vec().into() })?;
+            encoder.process_event(GifEvent::EndFrame)?;
+        }
+        encoder.process_event(GifEvent::EndGif)?;
+
+        crate::validate::assert_valid(encoder.get_encoded_data());
+        Ok((crate::decoder::decode(encoder.get_encoded_data())?, encoder.coalesced_frames()))
+    }
+
+    #[test]
+    fn test_frame_coalescing() -> Result<(), String> {
+        let none = DisposalMethod::None;
+        let (gif, coalesced) = encode_coalesced(0, &[(10, none, None, false, [0, 1, 1, 0]), (10, none, None, false, [0, 1, 1, 0]), (15, none, None, false, [0, 1, 1, 0]), (10, none, None, false, [1, 1, 1, 0]), (7, none, None, false, [1, 1, 1, 0])])?;
+        assert_eq!(coalesced, 3);
+        assert_eq!(gif.frames.iter().map(|frame| (frame.delay, frame.indices.clone())).collect::<Vec<_>>(), vec![(35, vec![0, 1, 1, 0]), (17, vec![1, 1, 1, 0])]);
+
+        // A frame without delay gets a graphic control extension once repeats add to it
+        let (gif, _) = encode_coalesced(0, &[(0, none, None, false, [3, 3, 3, 3]), (4, none, None, false, [3, 3, 3, 3])])?;
+        assert_eq!((gif.frames.len(), gif.frames[0].delay), (1, 4));
+
+        // Near repeats only merge within the threshold
+        let frames = [(5, none, None, false, [0, 1, 1, 0]), (5, none, None, false, [0, 2, 2, 0])];
+        assert_eq!(encode_coalesced(0, &frames)?.0.frames.len(), 2);
+        assert_eq!(encode_coalesced(5, &frames)?.0.frames.len(), 1);
+        assert_eq!(encode_coalesced(5, &[(u16::MAX, none, None, false, [0; 4]), (5, none, None, false, [0; 4])])?.0.frames.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_frame_coalescing_keeps_visible_changes() -> Result<(), String> {
+        // Clearing transparent frames to the background shows what was under them again
+        let background = DisposalMethod::Background;
+        let (gif, _) = encode_coalesced(0, &[(5, background, Some(0), false, [0, 1, 1, 0]), (5, background, Some(0), false, [0, 1, 1, 0])])?;
+        assert_eq!(gif.frames.len(), 2);
+        let (gif, _) = encode_coalesced(0, &[(5, background, None, false, [0, 1, 1, 0]), (5, background, None, false, [0, 1, 1, 0])])?;
+        assert_eq!(gif.frames.len(), 1);
+
+        // Frames waiting for user input, or with other disposal, are kept apart
+        let (gif, _) = encode_coalesced(0, &[(5, DisposalMethod::None, None, true, [0; 4]), (5, DisposalMethod::None, None, false, [0; 4]), (5, DisposalMethod::Previous, None, false, [0; 4])])?;
+        assert_eq!(gif.frames.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_frame_coalescing_compressed_and_comments() -> Result<(), String> {
+        let mut lzw_encoder = LzwEncoder::new(2);
+        lzw_encoder.encode_chunk(&[1, 0, 0, 1]);
+        lzw_encoder.finalize();
+        let compressed = lzw_encoder.get_encoded_data().to_vec();
+
+        let mut encoder = GifEncoderState::new().with_frame_coalescing(0);
+        encoder.process_event(GifEvent::StartGif { width: 2, height: 2, global_palette: Some(vec![[0, 0, 0], [255, 255, 255]].into()), background_color_index: 0, looping: LoopConfig::once(), version: None, screen: ScreenOptions::default() })?;
+        encoder.process_event(GifEvent::StartFrame { delay: 3, disposal_method: DisposalMethod::None, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: None, user_input: false, is_interlaced: false, rect: None })?;
+        encoder.process_event(GifEvent::WriteCompressedData { data: compressed.as_slice().into() })?;
+        encoder.process_event(GifEvent::EndFrame)?;
+        assert!(encoder.process_event(GifEvent::EndFrame).is_err());
+        encoder.process_event(GifEvent::StartFrame { delay: 3, disposal_method: DisposalMethod::None, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: None, user_input: false, is_interlaced: false, rect: None })?;
+        encoder.process_event(GifEvent::WriteImageChunk { data: vec![1, 0, 0, 1].into() })?;
+        assert!(encoder.process_event(GifEvent::Comment { text: b"inside".as_slice().into() }).is_err());
+        encoder.process_event(GifEvent::EndFrame)?;
+        encoder.process_event(GifEvent::Comment { text: b"between".as_slice().into() })?;
+        encoder.process_event(GifEvent::StartFrame { delay: 3, disposal_method: DisposalMethod::None, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: None, user_input: false, is_interlaced: false, rect: None })?;
+        encoder.process_event(GifEvent::WriteImageChunk { data: vec![1, 0, 0, 1].into() })?;
+        encoder.process_event(GifEvent::EndFrame)?;
+        encoder.process_event(GifEvent::EndGif)?;
+
+        // The comment sits between the first frame and the third, which can't extend it anymore
+        let gif = crate::decoder::decode(encoder.get_encoded_data())?;
+        assert_eq!(gif.frames.iter().map(|frame| frame.delay).collect::<Vec<_>>(), vec![6, 3]);
+        assert_eq!(gif.comments, vec![b"between".to_vec()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_frame_coalescing_fails_on_the_causing_event() -> Result<(), String> {
+        let start_frame = |delay| GifEvent::StartFrame { delay, disposal_method: DisposalMethod::None, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: None, user_input: false, is_interlaced: false, rect: None };
+        let mut encoder = GifEncoderState::new().with_frame_coalescing(0);
+        encoder.process_event(GifEvent::StartGif { width: 2, height: 2, global_palette: Some(vec![[0, 0, 0], [255, 255, 255]].into()), background_color_index: 0, looping: LoopConfig::once(), version: Some(GifVersion::Gif87a), screen: ScreenOptions::default() })?;
+        encoder.process_event(start_frame(0))?;
+        encoder.process_event(GifEvent::WriteImageChunk { data: vec![1, 0, 0, 1].into() })?;
+        encoder.process_event(GifEvent::EndFrame)?;
+
+        // A repeat with a delay would need a graphic control extension on the held back frame
+        assert_eq!(encoder.process_event(start_frame(5)), Err("Graphic control extension requires GIF89a".to_string()));
+
+        encoder.process_event(start_frame(0))?;
+        encoder.process_event(GifEvent::WriteImageChunk { data: vec![1, 0, 0, 1].into() })?;
+        assert_eq!(encoder.process_event(GifEvent::WriteCompressedData { data: vec![0x44, 0x01].into() }), Err("Frame already has uncompressed data".to_string()));
+        encoder.process_event(GifEvent::FlushFrame)?;
+        assert!(encoder.process_event(GifEvent::WriteImageChunk { data: vec![1].into() }).is_err());
+        encoder.process_event(GifEvent::EndFrame)?;
+        encoder.process_event(GifEvent::EndGif)?;
+
+        crate::validate::assert_valid(encoder.get_encoded_data());
+        assert_eq!(&encoder.get_encoded_data()[..6], b"GIF87a");
+        assert_eq!(crate::decoder::decode(encoder.get_encoded_data())?.frames.len(), 1);
+        Ok(())
+    }
 }
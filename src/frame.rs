@@ -0,0 +1,457 @@
+// MIT License
+// Copyright (c) 2025 Gianluca Cannata <gcannata23@gmail.com>
+//
+// av-gif - A GIF encoder written in Rust
+use std::borrow::Cow;
+
+use crate::encoder::{DisposalMethod, GifEncoder, GifEvent, GifWriter};
+use crate::lzw::LzwEncoder;
+use crate::palette::Palette;
+use crate::quantization::{
+    build_palette, opaque_pixels, quantize, quantize_rgba, remap_rgb, remap_rgba, QuantizeOptions,
+    QuantizedImage,
+};
+use crate::resize::{resample, Resize};
+use crate::transform::{transform_pixels, Transform};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Rgb,  // 3 bytes per pixel
+    Rgba, // 4 bytes per pixel, alpha decides transparency
+}
+
+impl PixelFormat {
+    pub fn channels(self) -> usize {
+        match self {
+            PixelFormat::Rgb => 3,
+            PixelFormat::Rgba => 4,
+        }
+    }
+}
+
+// A truecolor frame, quantized to a local palette when written
+#[derive(Debug, Clone)]
+pub struct Frame<'a> {
+    pub width: u16,
+    pub height: u16,
+    pub format: PixelFormat,
+    pub pixels: Cow<'a, [u8]>,
+    pub delay: u16,
+    pub disposal_method: DisposalMethod,
+    pub user_input: bool,
+    pub is_interlaced: bool,
+}
+
+impl<'a> Frame<'a> {
+    pub fn new(width: u16, height: u16, format: PixelFormat, pixels: impl Into<Cow<'a, [u8]>>) -> Self {
+        Frame {
+            width,
+            height,
+            format,
+            pixels: pixels.into(),
+            delay: 0,
+            disposal_method: DisposalMethod::None,
+            user_input: false,
+            is_interlaced: false,
+        }
+    }
+
+    pub fn rgb(width: u16, height: u16, pixels: impl Into<Cow<'a, [u8]>>) -> Self {
+        Self::new(width, height, PixelFormat::Rgb, pixels)
+    }
+
+    pub fn rgba(width: u16, height: u16, pixels: impl Into<Cow<'a, [u8]>>) -> Self {
+        Self::new(width, height, PixelFormat::Rgba, pixels)
+    }
+
+    pub fn with_delay(mut self, delay: u16) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub fn with_disposal_method(mut self, disposal_method: DisposalMethod) -> Self {
+        self.disposal_method = disposal_method;
+        self
+    }
+
+    // Resampled copy of the frame; timing and disposal are kept. Resize every frame the
+    // same way and start the GIF at `resize.size_for` of the source size.
+    pub fn resized(&self, resize: &Resize) -> Result<Frame<'static>, String> {
+        self.check_size()?;
+
+        let (width, height) = resize.size_for(self.width, self.height)?;
+        let pixels = resample(
+            &self.pixels,
+            (self.width as usize, self.height as usize),
+            self.format.channels(),
+            (width as usize, height as usize),
+            resize.filter,
+        );
+
+        Ok(Frame {
+            width,
+            height,
+            format: self.format,
+            pixels: pixels.into(),
+            delay: self.delay,
+            disposal_method: self.disposal_method,
+            user_input: self.user_input,
+            is_interlaced: self.is_interlaced,
+        })
+    }
+
+    // Cropped, rotated or flipped copy of the frame
+    pub fn transformed(&self, transform: &Transform) -> Result<Frame<'static>, String> {
+        let (pixels, width, height) =
+            transform_pixels(&self.pixels, self.width, self.height, self.format.channels(), transform)?;
+
+        Ok(Frame {
+            width,
+            height,
+            format: self.format,
+            pixels: pixels.into(),
+            delay: self.delay,
+            disposal_method: self.disposal_method,
+            user_input: self.user_input,
+            is_interlaced: self.is_interlaced,
+        })
+    }
+
+    fn check_size(&self) -> Result<(), String> {
+        let expected = self.width as usize * self.height as usize * self.format.channels();
+        if self.pixels.len() != expected {
+            return Err(format!(
+                "Frame of {}x{} needs {} bytes of pixel data, got {}",
+                self.width,
+                self.height,
+                expected,
+                self.pixels.len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+// A frame quantized and LZW-compressed ahead of time, ready to be written in order
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressedFrame {
+    pub palette: Vec<[u8; 3]>,
+    pub palette_sorted: bool,
+    pub transparent_index: Option<u8>,
+    pub data: Vec<u8>, // LZW data for the frame's local palette
+    pub delay: u16,
+    pub disposal_method: DisposalMethod,
+    pub user_input: bool,
+    pub is_interlaced: bool,
+}
+
+fn quantize_frame(frame: &Frame, options: &QuantizeOptions) -> Result<QuantizedImage, String> {
+    frame.check_size()?;
+
+    match frame.format {
+        PixelFormat::Rgb => quantize(&frame.pixels, frame.width as usize, options),
+        PixelFormat::Rgba => quantize_rgba(&frame.pixels, frame.width as usize, options),
+    }
+}
+
+// Quantize and compress a frame without touching any encoder, so frames can be
+// prepared on other threads. The result goes out with `write_compressed_frame`.
+pub fn compress_frame(frame: &Frame, options: &QuantizeOptions) -> Result<CompressedFrame, String> {
+    let image = quantize_frame(frame, options)?;
+
+    let mut indices = image.indices;
+    if frame.is_interlaced {
+        indices = GifWriter::new().encode_interlaced_data(&indices, frame.width, frame.height);
+    }
+
+    // Must match the minimum code size the image descriptor gets for this palette
+    let min_code_size = GifWriter::calculate_min_code_size(None, Some(&image.palette));
+    let mut lzw_encoder = LzwEncoder::new(min_code_size);
+    lzw_encoder.encode_chunk(&indices);
+    lzw_encoder.finalize();
+
+    Ok(CompressedFrame {
+        palette: image.palette,
+        palette_sorted: image.sorted,
+        transparent_index: image.transparent_index,
+        data: lzw_encoder.get_encoded_data().to_vec(),
+        delay: frame.delay,
+        disposal_method: frame.disposal_method,
+        user_input: frame.user_input,
+        is_interlaced: frame.is_interlaced,
+    })
+}
+
+pub fn write_compressed_frame<E: GifEncoder + ?Sized>(
+    encoder: &mut E,
+    frame: &CompressedFrame,
+) -> Result<(), String> {
+    encoder.process_event(GifEvent::StartFrame {
+        delay: frame.delay,
+        disposal_method: frame.disposal_method,
+        global_palette: None,
+        local_palette: Some(frame.palette.as_slice().into()),
+        local_palette_sorted: frame.palette_sorted,
+        transparent_color_index: frame.transparent_index,
+        user_input: frame.user_input,
+        is_interlaced: frame.is_interlaced,
+        rect: None,
+    })?;
+    encoder.process_event(GifEvent::WriteCompressedData {
+        data: frame.data.as_slice().into(),
+    })?;
+    encoder.process_event(GifEvent::EndFrame)
+}
+
+// Quantize a frame and send it through `encoder` as StartFrame / WriteImageChunk / EndFrame.
+// Transparent RGBA pixels set the frame's transparent color index automatically.
+pub fn write_frame<E: GifEncoder + ?Sized>(
+    encoder: &mut E,
+    frame: &Frame,
+    options: &QuantizeOptions,
+) -> Result<(), String> {
+    let image = quantize_frame(frame, options)?;
+
+    encoder.process_event(GifEvent::StartFrame {
+        delay: frame.delay,
+        disposal_method: frame.disposal_method,
+        global_palette: None,
+        local_palette: Some(image.palette.into()),
+        local_palette_sorted: image.sorted,
+        transparent_color_index: image.transparent_index,
+        user_input: frame.user_input,
+        is_interlaced: frame.is_interlaced,
+        rect: None,
+    })?;
+    encoder.process_event(GifEvent::WriteImageChunk {
+        data: image.indices.into(),
+    })?;
+    encoder.process_event(GifEvent::EndFrame)
+}
+
+// One palette for every frame, to send as StartGif's global palette. When any frame has
+// transparent pixels, a last entry is reserved for them.
+pub fn build_global_palette(frames: &[Frame], options: &QuantizeOptions) -> Result<Palette, String> {
+    let mut pixels = Vec::new();
+    let mut has_transparency = false;
+
+    for frame in frames {
+        frame.check_size()?;
+
+        match frame.format {
+            PixelFormat::Rgb => pixels.extend_from_slice(&frame.pixels),
+            PixelFormat::Rgba => {
+                let opaque = opaque_pixels(&frame.pixels, frame.width as usize, options)?;
+                has_transparency |= opaque.len() / 3 < frame.pixels.len() / 4;
+                pixels.extend(opaque);
+            }
+        }
+    }
+
+    if !has_transparency {
+        return Ok(Palette::new(build_palette(&pixels, options)?)?.with_color_space(options.color_space));
+    }
+
+    if options.max_colors < 2 {
+        return Err("Transparency needs room for at least 2 palette colors".to_string());
+    }
+
+    let reduced = QuantizeOptions {
+        max_colors: options.max_colors - 1,
+        ..options.clone()
+    };
+    Ok(Palette::new(build_palette(&pixels, &reduced)?)?
+        .with_transparent_slot()?
+        .with_color_space(options.color_space))
+}
+
+// Like `write_frame`, mapping the frame to the global palette from `build_global_palette`
+// instead of giving it a local one
+pub fn write_frame_with_palette<E: GifEncoder + ?Sized>(
+    encoder: &mut E,
+    frame: &Frame,
+    palette: &Palette,
+    options: &QuantizeOptions,
+) -> Result<(), String> {
+    frame.check_size()?;
+
+    let width = frame.width as usize;
+    let indices = match frame.format {
+        PixelFormat::Rgb => remap_rgb(&frame.pixels, width, palette, options)?,
+        PixelFormat::Rgba => remap_rgba(&frame.pixels, width, palette, options)?,
+    };
+
+    // Opaque frames skip the transparency flag, which may save their GCE
+    let transparent_color_index = palette.transparent_index().filter(|index| indices.contains(index));
+
+    encoder.process_event(GifEvent::StartFrame {
+        delay: frame.delay,
+        disposal_method: frame.disposal_method,
+        global_palette: None,
+        local_palette: None,
+        local_palette_sorted: false,
+        transparent_color_index,
+        user_input: frame.user_input,
+        is_interlaced: frame.is_interlaced,
+        rect: None,
+    })?;
+    encoder.process_event(GifEvent::WriteImageChunk {
+        data: indices.into(),
+    })?;
+    encoder.process_event(GifEvent::EndFrame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::decode;
+    use crate::encoder::{FrameRect, GifEncoderState, LoopConfig, ScreenOptions};
+    use crate::resize::ResizeFilter;
+
+    #[test]
+    fn test_rgba_frame_sets_transparency() -> Result<(), String> {
+        let mut encoder = GifEncoderState::new();
+        let pixels = [
+            255, 0, 0, 255, 0, 0, 0, 0, //
+            0, 0, 0, 0, 0, 0, 255, 255,
+        ];
+
+        encoder.process_event(GifEvent::StartGif { width: 2, height: 2, global_palette: None, background_color_index: 0, looping: LoopConfig::once(), version: None, screen: ScreenOptions::default() })?;
+        write_frame(&mut encoder, &Frame::rgba(2, 2, &pixels[..]).with_delay(5), &QuantizeOptions::default())?;
+        encoder.process_event(GifEvent::EndGif)?;
+
+        crate::validate::assert_valid(encoder.get_encoded_data());
+        let gif = decode(encoder.get_encoded_data())?;
+        let frame = &gif.frames[0];
+        let palette = frame.local_palette.as_ref().ok_or("missing local palette")?;
+
+        assert_eq!(frame.delay, 5);
+        assert_eq!(frame.transparent_color_index, Some(2));
+        assert_eq!(frame.indices, vec![0, 2, 2, 1]);
+        assert_eq!(&palette[..2], &[[255, 0, 0], [0, 0, 255]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_opaque_frame_has_no_transparency() -> Result<(), String> {
+        let mut encoder = GifEncoderState::new();
+
+        encoder.process_event(GifEvent::StartGif { width: 1, height: 2, global_palette: None, background_color_index: 0, looping: LoopConfig::once(), version: None, screen: ScreenOptions::default() })?;
+        write_frame(&mut encoder, &Frame::rgb(1, 2, &[1, 2, 3, 4, 5, 6][..]), &QuantizeOptions::default())?;
+        encoder.process_event(GifEvent::EndGif)?;
+
+        crate::validate::assert_valid(encoder.get_encoded_data());
+        let gif = decode(encoder.get_encoded_data())?;
+        assert_eq!(gif.version, crate::encoder::GifVersion::Gif87a);
+        assert_eq!(gif.frames[0].transparent_color_index, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_frame_size_mismatch() {
+        let mut encoder = GifEncoderState::new();
+        let result = write_frame(&mut encoder, &Frame::rgba(2, 2, &[0u8; 12][..]), &QuantizeOptions::default());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_compressed_frame_matches_write_frame() -> Result<(), String> {
+        let pixels: Vec<u8> = (0..6 * 5).flat_map(|i| [(i * 40) as u8, (i * 3) as u8, 7, if i % 4 == 0 { 0 } else { 255 }]).collect();
+        let options = QuantizeOptions::default();
+
+        let mut outputs = Vec::new();
+        for precompressed in [false, true] {
+            let mut encoder = GifEncoderState::new();
+            let mut frame = Frame::rgba(6, 5, pixels.as_slice()).with_delay(4);
+            frame.is_interlaced = true;
+
+            encoder.process_event(GifEvent::StartGif { width: 6, height: 5, global_palette: None, background_color_index: 0, looping: LoopConfig::once(), version: None, screen: ScreenOptions::default() })?;
+            if precompressed {
+                write_compressed_frame(&mut encoder, &compress_frame(&frame, &options)?)?;
+            } else {
+                write_frame(&mut encoder, &frame, &options)?;
+            }
+            encoder.process_event(GifEvent::EndGif)?;
+
+            crate::validate::assert_valid(encoder.get_encoded_data());
+            outputs.push(encoder.get_encoded_data().to_vec());
+        }
+
+        assert_eq!(outputs[0], outputs[1]);
+        assert_eq!(decode(&outputs[1])?.frames[0].indices.len(), 30);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mixing_compressed_and_raw_data_fails() -> Result<(), String> {
+        let mut encoder = GifEncoderState::new();
+
+        encoder.process_event(GifEvent::StartGif { width: 1, height: 1, global_palette: Some(vec![[0, 0, 0], [255, 255, 255]].into()), background_color_index: 0, looping: LoopConfig::once(), version: None, screen: ScreenOptions::default() })?;
+        encoder.process_event(GifEvent::StartFrame { delay: 0, disposal_method: DisposalMethod::None, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: None, user_input: false, is_interlaced: false, rect: None })?;
+        encoder.process_event(GifEvent::WriteImageChunk { data: vec![0].into() })?;
+
+        assert!(encoder.process_event(GifEvent::WriteCompressedData { data: vec![0x44, 0x01].into() }).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_frames_share_global_palette() -> Result<(), String> {
+        let frames = [
+            Frame::rgb(2, 1, &[255, 0, 0, 0, 255, 0][..]),
+            Frame::rgba(2, 1, &[0, 255, 0, 255, 0, 0, 0, 0][..]),
+        ];
+        let options = QuantizeOptions::default();
+        let palette = build_global_palette(&frames, &options)?;
+        assert_eq!(palette.colors(), &[[255, 0, 0], [0, 255, 0], [0, 0, 0]]);
+        assert_eq!(palette.transparent_index(), Some(2));
+
+        let mut encoder = GifEncoderState::new();
+        encoder.process_event(GifEvent::StartGif { width: 2, height: 1, global_palette: Some(palette.colors().into()), background_color_index: 0, looping: LoopConfig::infinite(), version: None, screen: ScreenOptions::default() })?;
+        for frame in &frames {
+            write_frame_with_palette(&mut encoder, frame, &palette, &options)?;
+        }
+        encoder.process_event(GifEvent::EndGif)?;
+
+        crate::validate::assert_valid(encoder.get_encoded_data());
+        let gif = decode(encoder.get_encoded_data())?;
+        assert!(gif.frames.iter().all(|frame| frame.local_palette.is_none()));
+        assert_eq!(gif.frames[0].transparent_color_index, None);
+        assert_eq!(gif.frames[0].indices, vec![0, 1]);
+        assert_eq!(gif.frames[1].transparent_color_index, Some(2));
+        assert_eq!(gif.frames[1].indices, vec![1, 2]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resized_frame_sets_screen_size() -> Result<(), String> {
+        let frame = Frame::rgb(8, 4, [200, 10, 10].repeat(32)).with_delay(7).with_disposal_method(DisposalMethod::Background);
+        let resize = Resize::fit(4).with_filter(ResizeFilter::Bilinear);
+        let resized = frame.resized(&resize)?;
+        assert_eq!((resized.width, resized.height), resize.size_for(frame.width, frame.height)?);
+        assert_eq!((resized.width, resized.height, resized.delay, resized.disposal_method), (4, 2, 7, DisposalMethod::Background));
+        assert_eq!(&resized.pixels[..], &[200, 10, 10].repeat(8)[..]);
+
+        let mut encoder = GifEncoderState::new();
+        encoder.process_event(GifEvent::StartGif { width: resized.width, height: resized.height, global_palette: None, background_color_index: 0, looping: LoopConfig::once(), version: None, screen: ScreenOptions::default() })?;
+        write_frame(&mut encoder, &resized, &QuantizeOptions::default())?;
+        encoder.process_event(GifEvent::EndGif)?;
+        crate::validate::assert_valid(encoder.get_encoded_data());
+
+        assert!(Frame::rgb(2, 2, vec![0; 5]).resized(&resize).is_err());
+        assert!(Frame::rgb(0, 0, vec![]).resized(&Resize::to(4, 4)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_transformed_frame() -> Result<(), String> {
+        let frame = Frame::rgba(2, 1, vec![1, 2, 3, 255, 4, 5, 6, 0]).with_delay(3);
+        let rotated = frame.transformed(&Transform::Rotate90)?;
+        assert_eq!((rotated.width, rotated.height, rotated.format, rotated.delay), (1, 2, PixelFormat::Rgba, 3));
+        assert_eq!(&rotated.pixels[..], &[1, 2, 3, 255, 4, 5, 6, 0]);
+
+        let cropped = frame.transformed(&Transform::Crop(FrameRect::new(1, 0, 1, 1)))?;
+        assert_eq!(&cropped.pixels[..], &[4, 5, 6, 0]);
+        assert!(Frame::rgb(2, 1, vec![0; 5]).transformed(&Transform::FlipVertical).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,420 @@
+// MIT License
+// Copyright (c) 2025 Gianluca Cannata <gcannata23@gmail.com>
+//
+// av-gif - A GIF encoder written in Rust
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::decoder::{disposal_method_from_bits, Reader};
+use crate::encoder::DisposalMethod;
+
+// One block of a GIF byte stream, `len` bytes starting at `offset`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub offset: usize,
+    pub len: usize,
+    pub kind: BlockKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockKind {
+    Header {
+        signature: String, // "GIF87a" or "GIF89a"
+    },
+    LogicalScreenDescriptor {
+        width: u16,
+        height: u16,
+        global_color_table: Option<usize>, // Number of entries
+        color_resolution: u8,
+        sort_flag: bool,
+        background_color_index: u8,
+        pixel_aspect_ratio: u8,
+    },
+    GlobalColorTable {
+        entries: usize,
+    },
+    GraphicControlExtension {
+        disposal_method: DisposalMethod,
+        user_input: bool,
+        transparent_color_index: Option<u8>,
+        delay: u16,
+    },
+    ApplicationExtension {
+        identifier: String,      // Application identifier and authentication code
+        loop_count: Option<u16>, // NETSCAPE2.0 / ANIMEXTS1.0 loop sub-block
+        sub_blocks: usize,       // Data sub-blocks after the identifier
+        data_len: usize,
+    },
+    CommentExtension {
+        text: String,
+        sub_blocks: usize,
+    },
+    PlainTextExtension {
+        sub_blocks: usize,
+        data_len: usize,
+    },
+    UnknownExtension {
+        label: u8,
+        sub_blocks: usize,
+        data_len: usize,
+    },
+    ImageDescriptor {
+        left: u16,
+        top: u16,
+        width: u16,
+        height: u16,
+        interlaced: bool,
+        local_color_table: Option<usize>,
+        sort_flag: bool,
+    },
+    LocalColorTable {
+        entries: usize,
+    },
+    ImageData {
+        min_code_size: u8,
+        sub_blocks: usize,
+        data_len: usize, // Compressed bytes, without the sub-block size bytes
+    },
+    Trailer,
+}
+
+// Every block found, in file order. Parsing stops at the first malformed block and
+// `error` says why, so the blocks before it can still be looked at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inspection {
+    pub blocks: Vec<Block>,
+    pub error: Option<String>,
+    pub trailing_bytes: usize, // Data after the trailer
+}
+
+pub fn inspect(data: &[u8]) -> Inspection {
+    let mut reader = Reader { data, position: 0 };
+    let mut blocks = Vec::new();
+
+    let error = inspect_blocks(&mut reader, &mut blocks).err();
+    let trailing_bytes = match blocks.last() {
+        Some(Block { kind: BlockKind::Trailer, .. }) => data.len() - reader.position,
+        _ => 0,
+    };
+
+    Inspection {
+        blocks,
+        error,
+        trailing_bytes,
+    }
+}
+
+fn inspect_blocks(reader: &mut Reader, blocks: &mut Vec<Block>) -> Result<(), String> {
+    let mut push = |reader: &Reader, offset: usize, kind: BlockKind| {
+        blocks.push(Block {
+            offset,
+            len: reader.position - offset,
+            kind,
+        })
+    };
+
+    let signature = reader.bytes(6)?;
+    if !matches!(signature, b"GIF87a" | b"GIF89a") {
+        return Err("Not a GIF file".to_string());
+    }
+    push(reader, 0, BlockKind::Header { signature: String::from_utf8_lossy(signature).into_owned() });
+
+    let offset = reader.position;
+    let width = reader.u16()?;
+    let height = reader.u16()?;
+    let packed_fields = reader.u8()?;
+    let background_color_index = reader.u8()?;
+    let pixel_aspect_ratio = reader.u8()?;
+    let global_color_table = color_table_entries(packed_fields);
+    push(reader, offset, BlockKind::LogicalScreenDescriptor {
+        width,
+        height,
+        global_color_table,
+        color_resolution: ((packed_fields >> 4) & 0b0000_0111) + 1,
+        sort_flag: packed_fields & 0b0000_1000 != 0,
+        background_color_index,
+        pixel_aspect_ratio,
+    });
+
+    if let Some(entries) = global_color_table {
+        let offset = reader.position;
+        reader.bytes(entries * 3)?;
+        push(reader, offset, BlockKind::GlobalColorTable { entries });
+    }
+
+    loop {
+        let offset = reader.position;
+        let kind = match reader.u8()? {
+            // Extension Introducer
+            0x21 => match reader.u8()? {
+                0xF9 => graphic_control(reader)?,
+                0xFE => {
+                    let start = reader.position;
+                    let (sub_blocks, _) = reader.skip_sub_blocks()?;
+                    let text = Reader { data: reader.data, position: start }.sub_blocks()?;
+                    BlockKind::CommentExtension { text: String::from_utf8_lossy(&text).into_owned(), sub_blocks }
+                }
+                0xFF => application(reader)?,
+                0x01 => {
+                    let (sub_blocks, data_len) = reader.skip_sub_blocks()?;
+                    BlockKind::PlainTextExtension { sub_blocks, data_len }
+                }
+                label => {
+                    let (sub_blocks, data_len) = reader.skip_sub_blocks()?;
+                    BlockKind::UnknownExtension { label, sub_blocks, data_len }
+                }
+            },
+
+            // Image Separator, followed by the color table and image data blocks
+            0x2C => {
+                let left = reader.u16()?;
+                let top = reader.u16()?;
+                let width = reader.u16()?;
+                let height = reader.u16()?;
+                let packed_fields = reader.u8()?;
+                let local_color_table = color_table_entries(packed_fields);
+                push(reader, offset, BlockKind::ImageDescriptor {
+                    left,
+                    top,
+                    width,
+                    height,
+                    interlaced: packed_fields & 0b0100_0000 != 0,
+                    local_color_table,
+                    sort_flag: packed_fields & 0b0010_0000 != 0,
+                });
+
+                if let Some(entries) = local_color_table {
+                    let offset = reader.position;
+                    reader.bytes(entries * 3)?;
+                    push(reader, offset, BlockKind::LocalColorTable { entries });
+                }
+
+                let offset = reader.position;
+                let min_code_size = reader.u8()?;
+                let (sub_blocks, data_len) = reader.skip_sub_blocks()?;
+                push(reader, offset, BlockKind::ImageData { min_code_size, sub_blocks, data_len });
+                continue;
+            }
+
+            // GIF Trailer
+            0x3B => {
+                push(reader, offset, BlockKind::Trailer);
+                return Ok(());
+            }
+
+            byte => return Err(format!("Unexpected block introducer 0x{:02X} at offset {}", byte, offset)),
+        };
+        push(reader, offset, kind);
+    }
+}
+
+fn color_table_entries(packed_fields: u8) -> Option<usize> {
+    (packed_fields & 0b1000_0000 != 0).then(|| 2usize << (packed_fields & 0b0000_0111))
+}
+
+fn graphic_control(reader: &mut Reader) -> Result<BlockKind, String> {
+    let body = reader.sub_blocks()?;
+    if body.len() < 4 {
+        return Err("Graphic control extension is too short".to_string());
+    }
+
+    Ok(BlockKind::GraphicControlExtension {
+        disposal_method: disposal_method_from_bits(body[0] >> 2),
+        user_input: body[0] & 0b0000_0010 != 0,
+        transparent_color_index: (body[0] & 0b0000_0001 != 0).then_some(body[3]),
+        delay: u16::from_le_bytes([body[1], body[2]]),
+    })
+}
+
+fn application(reader: &mut Reader) -> Result<BlockKind, String> {
+    let len = reader.u8()? as usize;
+    let identifier = String::from_utf8_lossy(reader.bytes(len)?).into_owned();
+
+    let start = reader.position;
+    let (sub_blocks, data_len) = reader.skip_sub_blocks()?;
+
+    // Loop sub-block: size 3, id 1, then the count
+    let data = &reader.data[start..reader.position];
+    let loop_count = match (identifier.as_str(), data) {
+        ("NETSCAPE2.0" | "ANIMEXTS1.0", [3, 1, low, high, ..]) => Some(u16::from_le_bytes([*low, *high])),
+        _ => None,
+    };
+
+    Ok(BlockKind::ApplicationExtension {
+        identifier,
+        loop_count,
+        sub_blocks,
+        data_len,
+    })
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:>8} {:>6}  {}", self.offset, self.len, self.kind)
+    }
+}
+
+impl fmt::Display for BlockKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockKind::Header { signature } => write!(f, "Header {}", signature),
+            BlockKind::LogicalScreenDescriptor {
+                width,
+                height,
+                global_color_table,
+                color_resolution,
+                sort_flag,
+                background_color_index,
+                pixel_aspect_ratio,
+            } => {
+                write!(f, "Logical Screen Descriptor {}x{}", width, height)?;
+                if let Some(entries) = global_color_table {
+                    write!(f, ", global color table of {}{}", entries, if *sort_flag { " sorted" } else { "" })?;
+                }
+                write!(
+                    f,
+                    ", color resolution {}, background {}, aspect {}",
+                    color_resolution, background_color_index, pixel_aspect_ratio
+                )
+            }
+            BlockKind::GlobalColorTable { entries } => write!(f, "Global Color Table, {} entries", entries),
+            BlockKind::GraphicControlExtension {
+                disposal_method,
+                user_input,
+                transparent_color_index,
+                delay,
+            } => {
+                write!(f, "Graphic Control Extension, delay {}, disposal {:?}", delay, disposal_method)?;
+                if let Some(index) = transparent_color_index {
+                    write!(f, ", transparent {}", index)?;
+                }
+                if *user_input {
+                    write!(f, ", user input")?;
+                }
+                Ok(())
+            }
+            BlockKind::ApplicationExtension {
+                identifier,
+                loop_count,
+                sub_blocks,
+                data_len,
+            } => {
+                write!(f, "Application Extension {:?}", identifier)?;
+                if let Some(count) = loop_count {
+                    write!(f, ", loop count {}", count)?;
+                }
+                write!(f, ", {} sub-blocks, {} bytes", sub_blocks, data_len)
+            }
+            BlockKind::CommentExtension { text, sub_blocks } => {
+                write!(f, "Comment Extension {:?}, {} sub-blocks", text, sub_blocks)
+            }
+            BlockKind::PlainTextExtension { sub_blocks, data_len } => {
+                write!(f, "Plain Text Extension, {} sub-blocks, {} bytes", sub_blocks, data_len)
+            }
+            BlockKind::UnknownExtension {
+                label,
+                sub_blocks,
+                data_len,
+            } => write!(
+                f,
+                "Extension 0x{:02X}, {} sub-blocks, {} bytes",
+                label, sub_blocks, data_len
+            ),
+            BlockKind::ImageDescriptor {
+                left,
+                top,
+                width,
+                height,
+                interlaced,
+                local_color_table,
+                sort_flag,
+            } => {
+                write!(f, "Image Descriptor {}x{} at {},{}", width, height, left, top)?;
+                if let Some(entries) = local_color_table {
+                    write!(f, ", local color table of {}{}", entries, if *sort_flag { " sorted" } else { "" })?;
+                }
+                if *interlaced {
+                    write!(f, ", interlaced")?;
+                }
+                Ok(())
+            }
+            BlockKind::LocalColorTable { entries } => write!(f, "Local Color Table, {} entries", entries),
+            BlockKind::ImageData {
+                min_code_size,
+                sub_blocks,
+                data_len,
+            } => write!(
+                f,
+                "Image Data, LZW minimum code size {}, {} sub-blocks, {} bytes",
+                min_code_size, sub_blocks, data_len
+            ),
+            BlockKind::Trailer => write!(f, "Trailer"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::{GifEncoder, GifEncoderState, GifEvent, LoopConfig, ScreenOptions};
+
+    fn encode() -> Result<Vec<u8>, String> {
+        let mut encoder = GifEncoderState::new();
+        encoder.process_event(GifEvent::StartGif { width: 2, height: 2, global_palette: Some(vec![[0, 0, 0], [255, 255, 255]].into()), background_color_index: 0, looping: LoopConfig::infinite(), version: None, screen: ScreenOptions::default() })?;
+        encoder.process_event(GifEvent::Comment { text: b"hello".as_slice().into() })?;
+        encoder.process_event(GifEvent::StartFrame { delay: 5, disposal_method: DisposalMethod::Background, global_palette: None, local_palette: Some(vec![[255, 0, 0], [0, 0, 255]].into()), local_palette_sorted: false, transparent_color_index: Some(1), user_input: false, is_interlaced: true, rect: None })?;
+        encoder.process_event(GifEvent::WriteImageChunk { data: vec![0, 1, 1, 0].into() })?;
+        encoder.process_event(GifEvent::EndFrame)?;
+        encoder.process_event(GifEvent::StartFrame { delay: 5, disposal_method: DisposalMethod::None, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: None, user_input: true, is_interlaced: false, rect: None })?;
+        encoder.process_event(GifEvent::WriteImageChunk { data: vec![1, 1, 0, 0].into() })?;
+        encoder.process_event(GifEvent::EndFrame)?;
+        encoder.process_event(GifEvent::EndGif)?;
+        Ok(encoder.get_encoded_data().to_vec())
+    }
+
+    #[test]
+    fn test_inspect_encoder_output() -> Result<(), String> {
+        let data = encode()?;
+        let inspection = inspect(&data);
+        assert_eq!(inspection.error, None);
+        assert_eq!(inspection.trailing_bytes, 0);
+
+        let kinds: Vec<_> = inspection.blocks.iter().map(|block| &block.kind).collect();
+        assert_eq!(kinds[0], &BlockKind::Header { signature: "GIF89a".to_string() });
+        assert!(matches!(kinds[1], BlockKind::LogicalScreenDescriptor { width: 2, height: 2, global_color_table: Some(2), .. }));
+        assert_eq!(kinds[2], &BlockKind::GlobalColorTable { entries: 2 });
+        assert!(kinds.contains(&&BlockKind::ApplicationExtension { identifier: "NETSCAPE2.0".to_string(), loop_count: Some(0), sub_blocks: 1, data_len: 3 }));
+        assert!(kinds.contains(&&BlockKind::CommentExtension { text: "hello".to_string(), sub_blocks: 1 }));
+        assert!(kinds.contains(&&BlockKind::GraphicControlExtension { disposal_method: DisposalMethod::Background, user_input: false, transparent_color_index: Some(1), delay: 5 }));
+        assert!(kinds.iter().any(|kind| matches!(kind, BlockKind::ImageDescriptor { interlaced: true, local_color_table: Some(2), .. })));
+        assert!(kinds.contains(&&BlockKind::LocalColorTable { entries: 2 }));
+        assert!(kinds.iter().any(|kind| matches!(kind, BlockKind::ImageData { min_code_size: 2, sub_blocks: 1, .. })));
+        assert_eq!(kinds.last(), Some(&&BlockKind::Trailer));
+
+        // Blocks cover the whole file without gaps
+        let mut offset = 0;
+        for block in &inspection.blocks {
+            assert_eq!(block.offset, offset);
+            offset += block.len;
+        }
+        assert_eq!(offset, data.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_inspect_damaged_data() -> Result<(), String> {
+        let mut data = encode()?;
+        data.extend_from_slice(b"junk");
+        assert_eq!(inspect(&data).trailing_bytes, 4);
+
+        let truncated = inspect(&data[..data.len() - 10]);
+        assert!(truncated.error.is_some());
+        assert!(truncated.blocks.len() > 3);
+
+        assert_eq!(inspect(b"PNG").error, Some("Unexpected end of data at offset 0".to_string()));
+        assert_eq!(inspect(b"GIF90a").error, Some("Not a GIF file".to_string()));
+        Ok(())
+    }
+}
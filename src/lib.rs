@@ -1,5 +1,30 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
 extern crate weezl;
 
+#[cfg(feature = "tokio")]
+pub mod async_encoder;
+pub mod decoder;
 pub mod encoder;
+#[cfg(feature = "std")]
+pub mod frame;
+pub mod inspect;
 pub mod lzw;
+#[cfg(feature = "std")]
+pub mod netpbm;
+pub mod optimize;
+#[cfg(feature = "std")]
+pub mod palette;
+#[cfg(feature = "rayon")]
+pub mod parallel;
+#[cfg(feature = "std")]
 pub mod quantization;
+pub mod replay;
+#[cfg(feature = "std")]
+pub mod resize;
+pub mod timing;
+pub mod transform;
+pub mod validate;
+#[cfg(feature = "std")]
+pub mod y4m;
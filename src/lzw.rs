@@ -1,81 +1,275 @@
-use std::collections::HashMap;
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
 
-pub struct LzwEncoder {
-    code_size: u8,                     // Number of bits per code
-    clear_code: u16,                   // 256
-    end_of_stream_code: u16,           // 257
-    next_code: u16,                    // Next available dictionary index
-    dictionary: HashMap<Vec<u8>, u16>, // LZW dictionary
-    current_sequence: Vec<u8>,         // Current sequence being encoded
-    output: Vec<u8>,                   // Encoded data
-    bit_buffer: u32,                   // Buffer for packing bits
-    bit_count: u32,                     // Number of bits in the current bit buffer
+// Open addressing hash table from (prefix code, next pixel) to code. A prime size a bit
+// above the 4096 possible codes keeps probes short without needing std's HashMap.
+const TABLE_SIZE: usize = 5003;
+
+struct CodeTable {
+    keys: Vec<u32>, // (prefix << 8 | pixel) + 1, 0 marks an empty slot
+    codes: Vec<u16>,
 }
 
-impl LzwEncoder {
-    pub fn new(code_size: u8) -> Self {
-        let mut dictionary = HashMap::new();
+impl CodeTable {
+    fn new() -> Self {
+        CodeTable {
+            keys: vec![0; TABLE_SIZE],
+            codes: vec![0; TABLE_SIZE],
+        }
+    }
+
+    // Slot holding the key, or the empty slot where it would go
+    fn slot(&self, key: u32) -> usize {
+        let mut slot = key as usize % TABLE_SIZE;
+        let step = 1 + key as usize % (TABLE_SIZE - 2);
 
-        // Initialize dictionary with single-byte values
-        for i in 0u16..=255 {
-            dictionary.insert(vec![i as u8], i);
+        while self.keys[slot] != 0 && self.keys[slot] != key {
+            slot = (slot + step) % TABLE_SIZE;
         }
+        slot
+    }
+
+    fn get(&self, prefix: u16, pixel: u8) -> Option<u16> {
+        let key = Self::key(prefix, pixel);
+        let slot = self.slot(key);
+        (self.keys[slot] == key).then(|| self.codes[slot])
+    }
+
+    fn insert(&mut self, prefix: u16, pixel: u8, code: u16) {
+        let key = Self::key(prefix, pixel);
+        let slot = self.slot(key);
+        self.keys[slot] = key;
+        self.codes[slot] = code;
+    }
+
+    fn clear(&mut self) {
+        self.keys.fill(0);
+    }
+
+    fn key(prefix: u16, pixel: u8) -> u32 {
+        ((prefix as u32) << 8 | pixel as u32) + 1
+    }
+}
+
+// Outcome of lossy encoding, compared against what lossless encoding would have produced
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LossyStats {
+    pub pixels: u64,
+    pub substituted_pixels: u64, // Pixels replaced by a similar color to extend a match
+    pub lossless_size: usize,    // Bytes of LZW data without substitutions
+    pub lossy_size: usize,       // Bytes of LZW data actually written
+}
+
+impl LossyStats {
+    pub fn saved_bytes(&self) -> usize {
+        self.lossless_size.saturating_sub(self.lossy_size)
+    }
+
+    // Fraction of the lossless size saved, 0.0 when nothing was encoded
+    pub fn reduction(&self) -> f32 {
+        if self.lossless_size == 0 {
+            return 0.0;
+        }
+        self.saved_bytes() as f32 / self.lossless_size as f32
+    }
+
+    pub fn add(&mut self, other: &LossyStats) {
+        self.pixels += other.pixels;
+        self.substituted_pixels += other.substituted_pixels;
+        self.lossless_size += other.lossless_size;
+        self.lossy_size += other.lossy_size;
+    }
+}
+
+struct Lossy {
+    level: u16,
+    // For every palette index, the other indices within `level`, closest first
+    similar: Vec<Vec<u8>>,
+    // Lossless encoder fed the same pixels, to measure the size reduction
+    baseline: Box<LzwEncoder>,
+    pixels: u64,
+    substituted_pixels: u64,
+}
+
+impl Lossy {
+    fn new(level: u16, palette: &[[u8; 3]], transparent_index: Option<u8>, min_code_size: u8) -> Self {
+        // Compare squared distances, scaled like `perceptual_distance`
+        let limit = level as u64 * level as u64 * 256;
+        let is_transparent = |index: usize| Some(index as u8) == transparent_index;
+
+        let similar = (0..palette.len())
+            .map(|index| {
+                if is_transparent(index) {
+                    return Vec::new();
+                }
+
+                let mut candidates: Vec<(u64, u8)> = palette
+                    .iter()
+                    .enumerate()
+                    .filter(|&(other, _)| other != index && !is_transparent(other))
+                    .map(|(other, &color)| (perceptual_distance(palette[index], color), other as u8))
+                    .filter(|&(distance, _)| distance <= limit)
+                    .collect();
+                candidates.sort();
+                candidates.into_iter().map(|(_, other)| other).collect()
+            })
+            .collect();
+
+        Lossy {
+            level,
+            similar,
+            baseline: Box::new(LzwEncoder::new(min_code_size)),
+            pixels: 0,
+            substituted_pixels: 0,
+        }
+    }
+}
+
+// Squared "redmean" color distance, times 256: a cheap RGB metric weighted the way the eye
+// is more sensitive to green and to red in reddish colors
+fn perceptual_distance(a: [u8; 3], b: [u8; 3]) -> u64 {
+    let mean_red = (a[0] as u64 + b[0] as u64) / 2;
+    let difference = |channel: usize| {
+        let difference = a[channel] as i64 - b[channel] as i64;
+        (difference * difference) as u64
+    };
+
+    (512 + mean_red) * difference(0) + 1024 * difference(1) + (767 - mean_red) * difference(2)
+}
+
+pub struct LzwEncoder {
+    min_code_size: u8,                   // LZW minimum code size written before the image data
+    code_size: u8,                       // Number of bits per code
+    clear_code: u16,                     // 1 << min_code_size
+    end_of_stream_code: u16,             // clear_code + 1
+    next_code: u16,                      // Next available dictionary index
+    dictionary: CodeTable,               // LZW dictionary: (prefix code, next pixel) -> code
+    current_code: Option<u16>,           // Code of the sequence being encoded
+    output: Vec<u8>,                     // Encoded data
+    bit_buffer: u32,                     // Buffer for packing bits
+    bit_count: u32,                      // Number of bits in the current bit buffer
+    lossy: Option<Lossy>,                // Set when matches may be extended with similar colors
+}
+
+impl LzwEncoder {
+    pub fn new(min_code_size: u8) -> Self {
+        // GIF needs at least 2 bits, and codes can't grow past 12 bits
+        let min_code_size = min_code_size.clamp(2, 8);
+        let clear_code = 1 << min_code_size;
 
         Self {
-            code_size: code_size,
-            clear_code: 256,
-            end_of_stream_code: 257,
-            next_code: 258,
-            dictionary,
-            current_sequence: Vec::new(),
+            min_code_size,
+            code_size: min_code_size + 1,
+            clear_code,
+            end_of_stream_code: clear_code + 1,
+            next_code: clear_code + 2,
+            dictionary: CodeTable::new(),
+            current_code: None,
             output: Vec::new(),
             bit_buffer: 0,
             bit_count: 0,
+            lossy: None,
         }
     }
 
+    // Lossy mode in the style of gifsicle's --lossy: when a match can't be extended with
+    // the next pixel, a palette color within `level` of it (by perceptual distance) may be
+    // used instead if that continues the match. Level 0 stays lossless. The transparent
+    // index is never swapped in or out.
+    pub fn with_lossy(mut self, level: u16, palette: &[[u8; 3]], transparent_index: Option<u8>) -> Self {
+        self.lossy = (level > 0).then(|| Lossy::new(level, palette, transparent_index, self.min_code_size));
+        self
+    }
+
+    pub fn lossy_level(&self) -> u16 {
+        self.lossy.as_ref().map_or(0, |lossy| lossy.level)
+    }
+
+    // Size reduction so far, None for lossless encoders. Sizes are final after `finalize`.
+    pub fn lossy_stats(&self) -> Option<LossyStats> {
+        self.lossy.as_ref().map(|lossy| LossyStats {
+            pixels: lossy.pixels,
+            substituted_pixels: lossy.substituted_pixels,
+            lossless_size: lossy.baseline.output.len(),
+            lossy_size: self.output.len(),
+        })
+    }
+
+    pub fn min_code_size(&self) -> u8 {
+        self.min_code_size
+    }
+
     pub fn encode_chunk(&mut self, chunk: &[u8]) {
         // Write clear code at the start of the image data
-        if self.output.is_empty() {
+        if self.output.is_empty() && self.bit_count == 0 {
             self.write_code(self.clear_code);
         }
 
+        if let Some(lossy) = self.lossy.as_mut() {
+            lossy.baseline.encode_chunk(chunk);
+            lossy.pixels += chunk.len() as u64;
+        }
+
         for &pixel in chunk {
-            let mut extended_sequence = self.current_sequence.clone();
-            extended_sequence.push(pixel);
+            let Some(current_code) = self.current_code else {
+                self.current_code = Some(pixel as u16);
+                continue;
+            };
 
-            if self.dictionary.contains_key(&extended_sequence) {
-                self.current_sequence = extended_sequence;
-            } else {
-                let code = self.dictionary[&self.current_sequence];
-                self.write_code(code);
-
-                if self.next_code < 4096 {
-                    self.dictionary.insert(extended_sequence, self.next_code);
-                    self.next_code += 1;
-
-                    // Increase code size before writing the next code
-                    if self.next_code == (1 << self.code_size) - 1 && self.code_size < 12 {
-                        self.code_size += 1;
-                    }
-
-                    let code = self.dictionary[&self.current_sequence];
-                    self.write_code(code);
-                } else {
-                    // Reset dictionary when full
-                    self.write_code(self.clear_code);
-                    self.reset_dictionary();
-                }
+            if let Some(code) = self.dictionary.get(current_code, pixel) {
+                self.current_code = Some(code);
+                continue;
+            }
 
-                self.current_sequence.clear();
-                self.current_sequence.push(pixel);
+            if let Some(code) = self.lossy_match(current_code, pixel) {
+                self.current_code = Some(code);
+                continue;
             }
+
+            self.write_code(current_code);
+
+            if self.next_code < 4096 {
+                self.dictionary.insert(current_code, pixel, self.next_code);
+                self.next_code += 1;
+
+                // Increase code size once the next code no longer fits
+                if self.next_code > (1 << self.code_size) && self.code_size < 12 {
+                    self.code_size += 1;
+                }
+            } else {
+                // Reset dictionary when full
+                self.write_code(self.clear_code);
+                self.reset_dictionary();
+            }
+
+            self.current_code = Some(pixel as u16);
         }
     }
 
+    // Code continuing `current_code` with the most similar substitute for `pixel`
+    fn lossy_match(&mut self, current_code: u16, pixel: u8) -> Option<u16> {
+        let lossy = self.lossy.as_mut()?;
+        let code = lossy
+            .similar
+            .get(pixel as usize)?
+            .iter()
+            .find_map(|&substitute| self.dictionary.get(current_code, substitute))?;
+
+        lossy.substituted_pixels += 1;
+        Some(code)
+    }
+
     pub fn finalize(&mut self) {
-        if !self.current_sequence.is_empty() {
-            let code = self.dictionary[&self.current_sequence];
+        if let Some(lossy) = self.lossy.as_mut() {
+            lossy.baseline.finalize();
+        }
+
+        if self.output.is_empty() && self.bit_count == 0 {
+            self.write_code(self.clear_code);
+        }
+
+        if let Some(code) = self.current_code.take() {
             self.write_code(code);
         }
         self.write_code(self.end_of_stream_code);
@@ -107,20 +301,23 @@ impl LzwEncoder {
     fn reset_dictionary(&mut self) {
         self.dictionary.clear();
 
-        for i in 0u16..=255 {
-            self.dictionary.insert(vec![i as u8], i);
-        }
-
-        self.next_code = 258;
-        self.code_size = 9;
-        self.current_sequence.clear();
+        self.next_code = self.clear_code + 2;
+        self.code_size = self.min_code_size + 1;
     }
 
     pub fn reset(&mut self) {
         self.reset_dictionary();
 
+        self.current_code = None;
+        self.output.clear();
         self.bit_buffer = 0;
         self.bit_count = 0;
+
+        if let Some(lossy) = self.lossy.as_mut() {
+            lossy.baseline.reset();
+            lossy.pixels = 0;
+            lossy.substituted_pixels = 0;
+        }
     }
 
     pub fn get_encoded_data(&self) -> &[u8] {
@@ -148,4 +345,110 @@ mod tests {
         // Assert that the encoded data is not the same as the input data
         assert_ne!(encoded_data, chunk);
     }
+
+    fn round_trip(min_code_size: u8, data: &[u8]) -> Vec<u8> {
+        let mut encoder = LzwEncoder::new(min_code_size);
+        for chunk in data.chunks(1000) {
+            encoder.encode_chunk(chunk);
+        }
+        encoder.finalize();
+
+        weezl::decode::Decoder::new(weezl::BitOrder::Lsb, encoder.min_code_size())
+            .decode(encoder.get_encoded_data())
+            .expect("encoded data should decode")
+    }
+
+    #[test]
+    fn test_round_trip_small_palette() {
+        let data: Vec<u8> = (0..5000u32).map(|i| ((i * 7 + i / 13) % 4) as u8).collect();
+        assert_eq!(round_trip(2, &data), data);
+    }
+
+    #[test]
+    fn test_round_trip_fills_dictionary() {
+        // Pseudo-random data keeps adding codes until the dictionary resets
+        let mut state = 12345u32;
+        let data: Vec<u8> = (0..100_000)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect();
+        assert_eq!(round_trip(8, &data), data);
+    }
+
+    #[test]
+    fn test_empty_input() {
+        assert_eq!(round_trip(2, &[]), Vec::<u8>::new());
+    }
+
+    // 64 close grays, and pixels that wander between neighboring shades
+    fn noisy_gray() -> (Vec<[u8; 3]>, Vec<u8>) {
+        let palette: Vec<[u8; 3]> = (0..64u8).map(|i| [i * 4, i * 4, i * 4]).collect();
+        let mut state = 7u32;
+        let data = (0..20_000u32)
+            .map(|i| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                ((i / 50 % 60) + (state >> 16) % 3) as u8
+            })
+            .collect();
+        (palette, data)
+    }
+
+    fn encode(encoder: LzwEncoder, data: &[u8]) -> LzwEncoder {
+        let mut encoder = encoder;
+        encoder.encode_chunk(data);
+        encoder.finalize();
+        encoder
+    }
+
+    fn decode(encoder: &LzwEncoder) -> Vec<u8> {
+        weezl::decode::Decoder::new(weezl::BitOrder::Lsb, encoder.min_code_size())
+            .decode(encoder.get_encoded_data())
+            .expect("encoded data should decode")
+    }
+
+    #[test]
+    fn test_lossy_level_zero_is_lossless() {
+        let (palette, data) = noisy_gray();
+        let lossless = encode(LzwEncoder::new(6), &data);
+        let level_zero = encode(LzwEncoder::new(6).with_lossy(0, &palette, None), &data);
+
+        assert_eq!(level_zero.get_encoded_data(), lossless.get_encoded_data());
+        assert_eq!(level_zero.lossy_stats(), None);
+    }
+
+    #[test]
+    fn test_lossy_shrinks_within_error() {
+        let (palette, data) = noisy_gray();
+        let lossless = encode(LzwEncoder::new(6), &data);
+        let lossy = encode(LzwEncoder::new(6).with_lossy(20, &palette, None), &data);
+
+        let stats = lossy.lossy_stats().expect("lossy encoder has stats");
+        assert_eq!(stats.pixels, data.len() as u64);
+        assert_eq!(stats.lossless_size, lossless.get_encoded_data().len());
+        assert_eq!(stats.lossy_size, lossy.get_encoded_data().len());
+        assert!(stats.substituted_pixels > 0);
+        assert!(stats.reduction() > 0.1, "reduction {}", stats.reduction());
+
+        let decoded = decode(&lossy);
+        assert_eq!(decoded.len(), data.len());
+        for (&original, &written) in data.iter().zip(&decoded) {
+            let distance = perceptual_distance(palette[original as usize], palette[written as usize]);
+            assert!(distance <= 20 * 20 * 256);
+        }
+    }
+
+    #[test]
+    fn test_lossy_keeps_transparent_pixels() {
+        let (palette, mut data) = noisy_gray();
+        for pixel in data.iter_mut().step_by(7) {
+            *pixel = 10;
+        }
+
+        let lossy = encode(LzwEncoder::new(6).with_lossy(40, &palette, Some(10)), &data);
+        for (&original, &written) in data.iter().zip(&decode(&lossy)) {
+            assert_eq!(original == 10, written == 10);
+        }
+    }
 }
@@ -0,0 +1,634 @@
+// MIT License
+// Copyright (c) 2025 Gianluca Cannata <gcannata23@gmail.com>
+//
+// av-gif - A GIF encoder written in Rust
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::process::ExitCode;
+
+use av_gif::decoder::decode;
+use av_gif::encoder::{DisposalMethod, FrameRect, GifEncoder, GifEncoderState, GifEvent, LoopConfig, ScreenOptions};
+use av_gif::frame::{build_global_palette, write_frame, write_frame_with_palette, Frame, PixelFormat};
+use av_gif::inspect::inspect as inspect_gif;
+use av_gif::netpbm::NetpbmReader;
+use av_gif::optimize::optimize as optimize_gif;
+use av_gif::palette::Palette;
+use av_gif::quantization::{ColorSpace, Dither, QuantizeOptions, Quantizer};
+use av_gif::replay::replay;
+use av_gif::resize::{Resize, ResizeFilter, ResizeTarget};
+use av_gif::timing::{FrameTimer, BROWSER_MIN_DELAY};
+use av_gif::transform::{transform_gif, Transform};
+use av_gif::y4m::{ColorMatrix, Y4mReader};
+
+// Delay for inputs without timing of their own
+const DEFAULT_DELAY: u16 = 10;
+
+const USAGE: &str = "\
+Usage: av-gif encode [OPTIONS] [INPUT...]
+       av-gif optimize [-o OUTPUT] [INPUT]
+       av-gif inspect [INPUT]
+       av-gif transform [TRANSFORMS] [-o OUTPUT] [INPUT]
+
+Builds a GIF from plain or binary PGM and PPM, PAM images (RGB_ALPHA PAM for
+transparency) or YUV4MPEG2 video. Each input may hold several concatenated
+images; with no input or \"-\" they are read from standard input. y4m frames
+keep the stream's timing unless --delay or --fps is given.
+
+Options:
+  -o, --output FILE        Write the GIF to FILE instead of standard output
+  -d, --delay CS           Delay of every frame in centiseconds (default 10)
+  -r, --fps RATE           Frame rate as N or N/D, overrides --delay
+  -l, --loop COUNT         infinite, once, or the number of repeats (default infinite)
+  -c, --colors N           Palette size, 2 to 256 (default 256)
+  -q, --quantizer NAME     median-cut or octree (default median-cut)
+      --dither NAME        none or floyd-steinberg (default none)
+      --color-space NAME   srgb, linear, lab or oklab, for building palettes and
+                           matching colors to them (default srgb)
+  -p, --palette MODE       local (one palette per frame) or global (default local)
+      --fixed-palette NAME Map every frame to web-safe, grayscale (--colors levels),
+                           black-white, or a .act, .gpl or .pal palette file
+      --alpha-threshold N  Alpha below N is transparent (default 128)
+      --lossy LEVEL        Lossy LZW, larger levels allow bigger color errors (default 0)
+      --coalesce N         Merge repeated frames, differing by at most N per color
+                           channel, into one longer frame (0 for exact repeats)
+      --matrix NAME        bt601 or bt709, for y4m input (default bt601)
+  -s, --size WxH           Resize every frame to W by H pixels
+  -m, --max-size N         Shrink frames so neither side is over N, keeping their aspect
+      --filter NAME        nearest, bilinear or lanczos3, for resizing (default lanczos3)
+      --crop WxH+X+Y       Keep the W by H area at X,Y
+      --rotate DEGREES     Rotate clockwise by 90, 180 or 270
+      --flip AXIS          Mirror horizontal (left to right) or vertical (top to bottom)
+      --interlace          Write interlaced frames
+  -h, --help               Show this help
+
+optimize rewrites a GIF (\"-\" or no input for standard input) as small as it can
+while keeping every frame pixel-identical. inspect lists the blocks of a GIF with
+their offsets and sizes in bytes. transform applies --crop, --rotate and --flip,
+in the order given, to an existing GIF without requantizing it.
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PaletteMode {
+    Local,  // Every frame gets its own palette
+    Global, // One palette built from all frames, which are read up front
+    Fixed,  // A built-in or imported palette, given as the global palette
+}
+
+#[derive(Debug)]
+struct EncodeOptions {
+    inputs: Vec<String>,
+    output: Option<String>,
+    delay: Option<u16>, // Overrides the input's own timing
+    frame_rate: Option<(u32, u32)>,
+    looping: LoopConfig,
+    quantize: QuantizeOptions,
+    palette_mode: PaletteMode,
+    fixed_palette: Option<String>, // Palette name or file, for PaletteMode::Fixed
+    matrix: ColorMatrix,
+    lossy_level: u16,
+    interlace: bool,
+    resize: Option<ResizeTarget>,
+    filter: ResizeFilter,
+    transforms: Vec<Transform>, // Applied in order, before resizing
+    coalesce: Option<u8>,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            inputs: Vec::new(),
+            output: None,
+            delay: None,
+            frame_rate: None,
+            looping: LoopConfig::infinite(),
+            quantize: QuantizeOptions::default(),
+            palette_mode: PaletteMode::Local,
+            fixed_palette: None,
+            matrix: ColorMatrix::Bt601,
+            lossy_level: 0,
+            interlace: false,
+            resize: None,
+            filter: ResizeFilter::Lanczos3,
+            transforms: Vec::new(),
+            coalesce: None,
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("av-gif: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    match args.first().map(String::as_str) {
+        Some("encode") => encode(&parse_encode_args(&args[1..])?),
+        Some("optimize") => optimize(&args[1..]),
+        Some("inspect") => inspect(&args[1..]),
+        Some("transform") => transform(&args[1..]),
+        Some("-h" | "--help") | None => {
+            print!("{}", USAGE);
+            Ok(())
+        }
+        Some(command) => Err(format!("Unknown command {}, see --help", command)),
+    }
+}
+
+fn parse_encode_args(args: &[String]) -> Result<EncodeOptions, String> {
+    let mut options = EncodeOptions::default();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+
+        match arg.as_str() {
+            "-o" | "--output" => options.output = Some(value()?.clone()),
+            "-d" | "--delay" => options.delay = Some(parse_number(value()?)?),
+            "-r" | "--fps" => options.frame_rate = Some(parse_frame_rate(value()?)?),
+            "-l" | "--loop" => {
+                options.looping = match value()?.as_str() {
+                    "infinite" => LoopConfig::infinite(),
+                    "once" => LoopConfig::once(),
+                    count => LoopConfig::repeat(parse_number(count)?),
+                }
+            }
+            "-c" | "--colors" => options.quantize.max_colors = parse_number(value()?)?,
+            "-q" | "--quantizer" => {
+                options.quantize.quantizer = match value()?.as_str() {
+                    "median-cut" => Quantizer::MedianCut,
+                    "octree" => Quantizer::Octree,
+                    name => return Err(format!("Unknown quantizer {}", name)),
+                }
+            }
+            "--dither" => {
+                options.quantize.dither = match value()?.as_str() {
+                    "none" => Dither::None,
+                    "floyd-steinberg" => Dither::FloydSteinberg,
+                    name => return Err(format!("Unknown dither {}", name)),
+                }
+            }
+            "--color-space" => {
+                options.quantize.color_space = match value()?.as_str() {
+                    "srgb" => ColorSpace::Srgb,
+                    "linear" => ColorSpace::LinearRgb,
+                    "lab" => ColorSpace::Lab,
+                    "oklab" => ColorSpace::Oklab,
+                    name => return Err(format!("Unknown color space {}", name)),
+                }
+            }
+            "-p" | "--palette" => {
+                options.palette_mode = match value()?.as_str() {
+                    "local" => PaletteMode::Local,
+                    "global" => PaletteMode::Global,
+                    mode => return Err(format!("Unknown palette mode {}", mode)),
+                }
+            }
+            "--fixed-palette" => {
+                options.palette_mode = PaletteMode::Fixed;
+                options.fixed_palette = Some(value()?.clone());
+            }
+            "--matrix" => {
+                options.matrix = match value()?.as_str() {
+                    "bt601" => ColorMatrix::Bt601,
+                    "bt709" => ColorMatrix::Bt709,
+                    name => return Err(format!("Unknown color matrix {}", name)),
+                }
+            }
+            "-s" | "--size" => {
+                let size = value()?;
+                let (width, height) = size.split_once('x').ok_or(format!("Invalid size {}, expected WxH", size))?;
+                options.resize = Some(ResizeTarget::Exact(parse_number(width)?, parse_number(height)?));
+            }
+            "-m" | "--max-size" => options.resize = Some(ResizeTarget::Fit(parse_number(value()?)?)),
+            "--filter" => {
+                options.filter = match value()?.as_str() {
+                    "nearest" => ResizeFilter::Nearest,
+                    "bilinear" => ResizeFilter::Bilinear,
+                    "lanczos3" => ResizeFilter::Lanczos3,
+                    name => return Err(format!("Unknown filter {}", name)),
+                }
+            }
+            "--crop" | "--rotate" | "--flip" => options.transforms.push(parse_transform(arg, value()?)?),
+            "--lossy" => options.lossy_level = parse_number(value()?)?,
+            "--coalesce" => options.coalesce = Some(parse_number(value()?)?),
+            "--alpha-threshold" => options.quantize.alpha_threshold = parse_number(value()?)?,
+            "--interlace" => options.interlace = true,
+            "-" => options.inputs.push(arg.clone()),
+            flag if flag.starts_with('-') => return Err(format!("Unknown option {}", flag)),
+            _ => options.inputs.push(arg.clone()),
+        }
+    }
+
+    if !(2..=256).contains(&options.quantize.max_colors) {
+        return Err("Palette size must be between 2 and 256 colors".to_string());
+    }
+
+    Ok(options)
+}
+
+fn parse_transform(flag: &str, value: &str) -> Result<Transform, String> {
+    let transform = match (flag, value) {
+        ("--rotate", "90") => Transform::Rotate90,
+        ("--rotate", "180") => Transform::Rotate180,
+        ("--rotate", "270") => Transform::Rotate270,
+        ("--flip", "horizontal") => Transform::FlipHorizontal,
+        ("--flip", "vertical") => Transform::FlipVertical,
+        ("--crop", geometry) => {
+            let invalid = || format!("Invalid crop {}, expected WxH+X+Y", geometry);
+            let (size, offset) = geometry.split_once('+').ok_or_else(invalid)?;
+            let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+            let (left, top) = offset.split_once('+').ok_or_else(invalid)?;
+            Transform::Crop(FrameRect::new(
+                parse_number(left)?,
+                parse_number(top)?,
+                parse_number(width)?,
+                parse_number(height)?,
+            ))
+        }
+        (flag, value) => return Err(format!("Invalid value {} for {}", value, flag)),
+    };
+    Ok(transform)
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid number {}", value))
+}
+
+fn parse_frame_rate(value: &str) -> Result<(u32, u32), String> {
+    match value.split_once('/') {
+        Some((numerator, denominator)) => Ok((parse_number(numerator)?, parse_number(denominator)?)),
+        None => Ok((parse_number(value)?, 1)),
+    }
+}
+
+type Frames = Box<dyn Iterator<Item = Result<Frame<'static>, String>>>;
+
+// Frames from every input in order
+fn read_frames(inputs: &[String], matrix: ColorMatrix) -> impl Iterator<Item = Result<Frame<'static>, String>> {
+    let stdin = ["-".to_string()];
+    let inputs = if inputs.is_empty() { &stdin[..] } else { inputs }.to_vec();
+
+    inputs.into_iter().flat_map(move |input| {
+        let frames = match open_input(&input, matrix) {
+            Ok(frames) => frames,
+            Err(err) => Box::new(std::iter::once(Err(err))),
+        };
+        frames.map(move |frame| frame.map_err(|err| format!("{}: {}", input, err)))
+    })
+}
+
+// Netpbm or y4m reader for an input, told apart by the y4m signature
+fn open_input(input: &str, matrix: ColorMatrix) -> Result<Frames, String> {
+    let mut reader: Box<dyn BufRead> = match input {
+        "-" => Box::new(io::stdin().lock()),
+        path => Box::new(BufReader::new(File::open(path).map_err(|err| err.to_string())?)),
+    };
+
+    let is_y4m = reader
+        .fill_buf()
+        .map_err(|err| err.to_string())?
+        .starts_with(b"YUV4MPEG2");
+
+    Ok(match is_y4m {
+        true => Box::new(
+            Y4mReader::new(reader)?
+                .with_color_matrix(matrix)
+                .with_min_delay(BROWSER_MIN_DELAY),
+        ),
+        false => Box::new(NetpbmReader::new(reader)),
+    })
+}
+
+fn encode(options: &EncodeOptions) -> Result<(), String> {
+    let Some(path) = options.output.as_deref().filter(|&path| path != "-") else {
+        return encode_to(options, Box::new(io::stdout().lock()));
+    };
+
+    // Written next to the output and renamed once complete, so a failed run neither
+    // leaves a partial GIF behind nor clobbers an existing one
+    let partial = format!("{}.part", path);
+    let file = File::create(&partial).map_err(|err| format!("{}: {}", partial, err))?;
+    match encode_to(options, Box::new(file)) {
+        Ok(()) => std::fs::rename(&partial, path).map_err(|err| format!("{}: {}", path, err)),
+        Err(err) => {
+            let _ = std::fs::remove_file(&partial);
+            Err(err)
+        }
+    }
+}
+
+fn encode_to(options: &EncodeOptions, output: Box<dyn Write>) -> Result<(), String> {
+    let mut output = BufWriter::new(output);
+
+    let mut timer = match options.frame_rate {
+        Some((numerator, denominator)) => {
+            Some(FrameTimer::with_frame_rate(numerator, denominator)?.with_browser_min_delay())
+        }
+        None => None,
+    };
+
+    let resize = options.resize.map(|target| Resize {
+        target,
+        filter: options.filter,
+    });
+
+    let mut frames = read_frames(&options.inputs, options.matrix).map(|frame| {
+        let mut frame = frame?;
+        for transform in &options.transforms {
+            frame = frame.transformed(transform)?;
+        }
+        // Before quantization, so the screen size comes from the resized first frame
+        if let Some(resize) = &resize {
+            frame = frame.resized(resize)?;
+        }
+        frame.delay = match (timer.as_mut(), options.delay) {
+            (Some(timer), _) => timer.next_delay()?,
+            (None, Some(delay)) => delay,
+            (None, None) if frame.delay == 0 => DEFAULT_DELAY,
+            (None, None) => frame.delay,
+        };
+        // Transparent areas must show the background, not the previous frame
+        if frame.format == PixelFormat::Rgba {
+            frame.disposal_method = DisposalMethod::Background;
+        }
+        frame.is_interlaced = options.interlace;
+        Ok::<_, String>(frame)
+    });
+
+    let mut encoder = GifEncoderState::new().with_lossy_level(options.lossy_level);
+    if let Some(threshold) = options.coalesce {
+        encoder = encoder.with_frame_coalescing(threshold);
+    }
+    let mut count = 0;
+
+    match options.palette_mode {
+        PaletteMode::Local | PaletteMode::Fixed => {
+            let first = frames.next().ok_or("No input images")??;
+            // Frames are streamed, so RGBA input keeps a transparent slot for any of them
+            let transparent = first.format == PixelFormat::Rgba;
+            let palette = match &options.fixed_palette {
+                Some(name) if options.palette_mode == PaletteMode::Fixed => Some(fixed_palette(name, options, transparent)?),
+                _ => None,
+            };
+            let (width, height) = (first.width, first.height);
+            encoder.process_event(start_gif(width, height, palette.as_ref().map(Palette::colors), options))?;
+
+            for frame in std::iter::once(Ok(first)).chain(frames) {
+                let frame = frame?;
+                check_frame_size(&frame, width, height, count)?;
+                match &palette {
+                    Some(palette) => write_frame_with_palette(&mut encoder, &frame, palette, &options.quantize)?,
+                    None => write_frame(&mut encoder, &frame, &options.quantize)?,
+                }
+                encoder.write_ready_output(&mut output)?;
+                count += 1;
+            }
+        }
+        PaletteMode::Global => {
+            let frames = frames.collect::<Result<Vec<_>, _>>()?;
+            let first = frames.first().ok_or("No input images")?;
+            let (width, height) = (first.width, first.height);
+            for (index, frame) in frames.iter().enumerate() {
+                check_frame_size(frame, width, height, index)?;
+            }
+
+            let palette = build_global_palette(&frames, &options.quantize)?;
+            encoder.process_event(start_gif(width, height, Some(palette.colors()), options))?;
+
+            for frame in &frames {
+                write_frame_with_palette(&mut encoder, frame, &palette, &options.quantize)?;
+                encoder.write_ready_output(&mut output)?;
+                count += 1;
+            }
+        }
+    }
+
+    encoder.process_event(GifEvent::EndGif)?;
+    encoder.write_ready_output(&mut output)?;
+    output.flush().map_err(|err| err.to_string())?;
+
+    eprintln!("av-gif: wrote {} frame{}", count, if count == 1 { "" } else { "s" });
+    if options.coalesce.is_some() {
+        eprintln!("av-gif: {} repeated frames merged into longer delays", encoder.coalesced_frames());
+    }
+    if options.lossy_level > 0 {
+        let stats = encoder.lossy_stats();
+        eprintln!(
+            "av-gif: lossy LZW saved {} bytes ({:.1}%), {} pixels changed",
+            stats.saved_bytes(),
+            stats.reduction() * 100.0,
+            stats.substituted_pixels
+        );
+    }
+    Ok(())
+}
+
+// A built-in palette by name, or one read from a file by its extension. `transparent`
+// input gets a transparent slot when the palette has room and none of its own.
+fn fixed_palette(name: &str, options: &EncodeOptions, transparent: bool) -> Result<Palette, String> {
+    let palette = match name {
+        "web-safe" => Palette::web_safe(),
+        "grayscale" => Palette::grayscale(options.quantize.max_colors)?,
+        "black-white" => Palette::black_and_white(),
+        path => {
+            let data = std::fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+            let text = || std::str::from_utf8(&data).map_err(|_| format!("{}: not a text palette", path));
+            match path.rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase()).as_deref() {
+                Some("act") => Palette::from_act(&data),
+                Some("gpl") => Palette::from_gpl(text()?),
+                Some("pal") => Palette::from_jasc_pal(text()?),
+                _ => Err("Palette files must be .act, .gpl or .pal".to_string()),
+            }
+            .map_err(|err| format!("{}: {}", path, err))?
+        }
+    };
+
+    // Frames only use the slot when they have transparent pixels
+    let palette = match transparent && palette.transparent_index().is_none() && palette.len() < 256 {
+        true => palette.with_transparent_slot()?,
+        false => palette,
+    };
+    Ok(palette.with_color_space(options.quantize.color_space))
+}
+
+fn optimize(args: &[String]) -> Result<(), String> {
+    let (mut input, mut output) = (None, None);
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(args.next().ok_or("Missing value for --output")?.as_str()),
+            "-" => input = Some("-"),
+            flag if flag.starts_with('-') => return Err(format!("Unknown option {}", flag)),
+            path if input.is_none() => input = Some(path),
+            _ => return Err("optimize takes a single input".to_string()),
+        }
+    }
+
+    let data = read_gif(input)?;
+
+    let optimized = optimize_gif(&data)?;
+    write_gif(output, &optimized)?;
+
+    eprintln!("av-gif: {} -> {} bytes", data.len(), optimized.len());
+    Ok(())
+}
+
+fn inspect(args: &[String]) -> Result<(), String> {
+    let input = match args {
+        [] => None,
+        [input] if input == "-" || !input.starts_with('-') => Some(input.as_str()),
+        _ => return Err("inspect takes a single input".to_string()),
+    };
+
+    let data = read_gif(input)?;
+    let inspection = inspect_gif(&data);
+
+    println!("  offset length  block");
+    for block in &inspection.blocks {
+        println!("{}", block);
+    }
+    if inspection.trailing_bytes > 0 {
+        println!("{} bytes after the trailer", inspection.trailing_bytes);
+    }
+
+    match inspection.error {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+fn transform(args: &[String]) -> Result<(), String> {
+    let (mut input, mut output) = (None, None);
+    let mut transforms = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(value()?.as_str()),
+            "--crop" | "--rotate" | "--flip" => transforms.push(parse_transform(arg, value()?)?),
+            "-" => input = Some("-"),
+            flag if flag.starts_with('-') => return Err(format!("Unknown option {}", flag)),
+            path if input.is_none() => input = Some(path),
+            _ => return Err("transform takes a single input".to_string()),
+        }
+    }
+
+    let mut gif = decode(&read_gif(input)?)?;
+    for transform in &transforms {
+        gif = transform_gif(&gif, transform)?;
+    }
+
+    let mut encoder = GifEncoderState::new();
+    replay(&mut encoder, gif.events())?;
+    write_gif(output, encoder.get_encoded_data())
+}
+
+// Whole GIF file, or standard input for "-" and no input
+fn read_gif(input: Option<&str>) -> Result<Vec<u8>, String> {
+    match input {
+        Some("-") | None => {
+            let mut data = Vec::new();
+            io::stdin().read_to_end(&mut data).map_err(|err| err.to_string())?;
+            Ok(data)
+        }
+        Some(path) => std::fs::read(path).map_err(|err| format!("{}: {}", path, err)),
+    }
+}
+
+// Write a whole GIF to a file, or standard output for "-" and no output
+fn write_gif(output: Option<&str>, data: &[u8]) -> Result<(), String> {
+    match output {
+        Some("-") | None => io::stdout().write_all(data).map_err(|err| err.to_string()),
+        Some(path) => std::fs::write(path, data).map_err(|err| format!("{}: {}", path, err)),
+    }
+}
+
+fn start_gif<'a>(width: u16, height: u16, palette: Option<&'a [[u8; 3]]>, options: &EncodeOptions) -> GifEvent<'a> {
+    GifEvent::StartGif {
+        width,
+        height,
+        global_palette: palette.map(Into::into),
+        background_color_index: 0,
+        looping: options.looping.clone(),
+        version: None,
+        screen: ScreenOptions::default(),
+    }
+}
+
+// Frames are written at the screen origin, so they must all cover the whole screen
+fn check_frame_size(frame: &Frame, width: u16, height: u16, index: usize) -> Result<(), String> {
+    if (frame.width, frame.height) != (width, height) {
+        return Err(format!(
+            "Image {} is {}x{}, expected {}x{} like the first one",
+            index + 1,
+            frame.width,
+            frame.height,
+            width,
+            height
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_parse_encode_args() -> Result<(), String> {
+        let options = parse_encode_args(&args("-r 30000/1001 --loop 3 -c 64 -q octree --dither floyd-steinberg --color-space oklab -p global --matrix bt709 -s 320x200 --filter bilinear -o out.gif a.ppm -"))?;
+
+        assert_eq!(options.frame_rate, Some((30000, 1001)));
+        assert_eq!(options.looping, LoopConfig::repeat(3));
+        assert_eq!(options.quantize.max_colors, 64);
+        assert_eq!(options.quantize.quantizer, Quantizer::Octree);
+        assert_eq!(options.quantize.dither, Dither::FloydSteinberg);
+        assert_eq!(options.quantize.color_space, ColorSpace::Oklab);
+        assert_eq!(options.palette_mode, PaletteMode::Global);
+        assert_eq!(options.matrix, ColorMatrix::Bt709);
+        assert_eq!((options.resize, options.filter), (Some(ResizeTarget::Exact(320, 200)), ResizeFilter::Bilinear));
+        assert_eq!(parse_encode_args(&args("-m 480"))?.resize, Some(ResizeTarget::Fit(480)));
+        assert_eq!(parse_encode_args(&args("--crop 20x10+4+2 --rotate 270 --flip vertical"))?.transforms, vec![Transform::Crop(FrameRect::new(4, 2, 20, 10)), Transform::Rotate270, Transform::FlipVertical]);
+        assert_eq!(options.delay, None);
+        assert_eq!(options.coalesce, None);
+        assert_eq!(parse_encode_args(&args("--coalesce 4"))?.coalesce, Some(4));
+
+        let fixed = parse_encode_args(&args("-c 16 --fixed-palette grayscale"))?;
+        assert_eq!((fixed.palette_mode, fixed.fixed_palette.as_deref()), (PaletteMode::Fixed, Some("grayscale")));
+        let palette = fixed_palette("grayscale", &fixed, true)?;
+        assert_eq!((palette.len(), palette.transparent_index()), (17, Some(16)));
+        let palette = fixed_palette("grayscale", &fixed, false)?;
+        assert_eq!((palette.len(), palette.transparent_index()), (16, None));
+        assert_eq!(options.output.as_deref(), Some("out.gif"));
+        assert_eq!(options.inputs, vec!["a.ppm", "-"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_args() {
+        assert!(parse_encode_args(&args("--colors 1")).is_err());
+        assert!(parse_encode_args(&args("--quantizer neuquant")).is_err());
+        assert!(parse_encode_args(&args("--delay")).is_err());
+        assert!(parse_encode_args(&args("--bogus")).is_err());
+        assert!(parse_encode_args(&args("--size 320")).is_err());
+        assert!(parse_encode_args(&args("--filter cubic")).is_err());
+        assert!(parse_encode_args(&args("--rotate 45")).is_err());
+        assert!(parse_encode_args(&args("--crop 20x10")).is_err());
+        assert!(fixed_palette("palette.bmp", &EncodeOptions::default(), false).is_err());
+        assert!(run(&args("decode")).is_err());
+    }
+}
@@ -0,0 +1,330 @@
+// MIT License
+// Copyright (c) 2025 Gianluca Cannata <gcannata23@gmail.com>
+//
+// av-gif - A GIF encoder written in Rust
+use std::io::{BufRead, Read};
+
+use crate::frame::{Frame, PixelFormat};
+
+// Reads PGM (P2 plain, P5 binary), PPM (P3 plain, P6 binary) and PAM (P7) images from a
+// stream. Images may be concatenated, as written by `ffmpeg -f image2pipe -c:v ppm` or
+// `pamfile`-style tools. Samples above 8 bits are scaled down, grayscale is expanded to RGB.
+pub struct NetpbmReader<R> {
+    reader: R,
+}
+
+impl<R: BufRead> NetpbmReader<R> {
+    pub fn new(reader: R) -> Self {
+        NetpbmReader { reader }
+    }
+
+    // Next image, or None at the end of the stream
+    pub fn read_frame(&mut self) -> Result<Option<Frame<'static>>, String> {
+        self.skip_whitespace()?;
+        if self.peek()?.is_none() {
+            return Ok(None);
+        }
+
+        let magic = [self.byte()?, self.byte()?];
+        let (header, plain) = match &magic {
+            b"P2" => (self.read_pnm_header(1, true)?, true),
+            b"P3" => (self.read_pnm_header(3, true)?, true),
+            b"P5" => (self.read_pnm_header(1, false)?, false),
+            b"P6" => (self.read_pnm_header(3, false)?, false),
+            b"P7" => (self.read_pam_header()?, false),
+            _ => return Err(format!("Unsupported Netpbm format {:?}", String::from_utf8_lossy(&magic))),
+        };
+
+        let (width, height) = header.size()?;
+        let sample_count = width as usize * height as usize * header.depth;
+        let samples = match plain {
+            true => self.read_plain_samples(sample_count, header.maxval)?,
+            false => self.read_binary_samples(sample_count, header.maxval)?,
+        };
+
+        let (format, pixels) = match header.depth {
+            1 => (PixelFormat::Rgb, samples.iter().flat_map(|&gray| [gray; 3]).collect()),
+            2 => (
+                PixelFormat::Rgba,
+                samples
+                    .chunks_exact(2)
+                    .flat_map(|sample| [sample[0], sample[0], sample[0], sample[1]])
+                    .collect(),
+            ),
+            3 => (PixelFormat::Rgb, samples),
+            _ => (PixelFormat::Rgba, samples),
+        };
+
+        Ok(Some(Frame::new(width, height, format, pixels)))
+    }
+
+    // PGM and PPM: width, height and maxval, then a single whitespace byte before binary
+    // samples. Plain samples are whitespace-separated numbers, read like header fields.
+    fn read_pnm_header(&mut self, depth: usize, plain: bool) -> Result<Header, String> {
+        let width = self.number()?;
+        let height = self.number()?;
+        let maxval = self.number()?;
+
+        if !plain {
+            match self.byte()? {
+                byte if byte.is_ascii_whitespace() => {}
+                _ => return Err("Missing whitespace after the header".to_string()),
+            }
+        }
+
+        Header::new(width, height, depth, maxval)
+    }
+
+    // Big-endian when maxval needs two bytes. The raster grows with the data actually read,
+    // so a header claiming a huge image can't make us allocate it up front.
+    fn read_binary_samples(&mut self, count: usize, maxval: u32) -> Result<Vec<u8>, String> {
+        let sample_size = if maxval > 255 { 2 } else { 1 };
+        let expected = count * sample_size;
+        let mut raster = Vec::new();
+        self.reader
+            .by_ref()
+            .take(expected as u64)
+            .read_to_end(&mut raster)
+            .map_err(|err| format!("Truncated image data: {}", err))?;
+        if raster.len() < expected {
+            return Err(format!("Truncated image data: {} of {} bytes", raster.len(), expected));
+        }
+
+        Ok(match sample_size {
+            1 if maxval == 255 => raster,
+            1 => raster.iter().map(|&sample| scale(sample as u32, maxval)).collect(),
+            _ => raster
+                .chunks_exact(2)
+                .map(|sample| scale(u16::from_be_bytes([sample[0], sample[1]]) as u32, maxval))
+                .collect(),
+        })
+    }
+
+    fn read_plain_samples(&mut self, count: usize, maxval: u32) -> Result<Vec<u8>, String> {
+        (0..count)
+            .map(|_| match self.number() {
+                Ok(sample) if sample <= maxval => Ok(scale(sample, maxval)),
+                Ok(sample) => Err(format!("Sample {} is above the maxval of {}", sample, maxval)),
+                Err(_) => Err("Truncated image data".to_string()),
+            })
+            .collect()
+    }
+
+    // PAM: keyword lines up to ENDHDR
+    fn read_pam_header(&mut self) -> Result<Header, String> {
+        let (mut width, mut height, mut depth, mut maxval) = (None, None, None, None);
+        let mut tuple_type = String::new();
+
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).map_err(|err| err.to_string())? == 0 {
+                return Err("PAM header has no ENDHDR".to_string());
+            }
+
+            let mut words = line.split_whitespace();
+            let Some(keyword) = words.next().filter(|keyword| !keyword.starts_with('#')) else {
+                continue;
+            };
+            let mut value = || -> Result<u32, String> {
+                words
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .ok_or(format!("Invalid PAM {} value", keyword))
+            };
+
+            match keyword {
+                "WIDTH" => width = Some(value()?),
+                "HEIGHT" => height = Some(value()?),
+                "DEPTH" => depth = Some(value()?),
+                "MAXVAL" => maxval = Some(value()?),
+                "TUPLTYPE" => tuple_type = words.collect::<Vec<_>>().join(" "),
+                "ENDHDR" => break,
+                _ => return Err(format!("Unknown PAM header keyword {}", keyword)),
+            }
+        }
+
+        let depth = depth.ok_or("PAM header has no DEPTH")?;
+        let expected = match tuple_type.as_str() {
+            "GRAYSCALE" | "BLACKANDWHITE" => 1,
+            "GRAYSCALE_ALPHA" | "BLACKANDWHITE_ALPHA" => 2,
+            "RGB" => 3,
+            "RGB_ALPHA" => 4,
+            _ => depth, // No or unknown tuple type, go by depth
+        };
+        if depth != expected || !(1..=4).contains(&depth) {
+            return Err(format!("Unsupported PAM depth {} for tuple type {:?}", depth, tuple_type));
+        }
+
+        Header::new(
+            width.ok_or("PAM header has no WIDTH")?,
+            height.ok_or("PAM header has no HEIGHT")?,
+            depth as usize,
+            maxval.ok_or("PAM header has no MAXVAL")?,
+        )
+    }
+
+    // Decimal header field, skipping whitespace and comments before it
+    fn number(&mut self) -> Result<u32, String> {
+        self.skip_whitespace()?;
+
+        let mut value: u32 = 0;
+        let mut digits = 0;
+        while let Some(byte) = self.peek()?.filter(u8::is_ascii_digit) {
+            self.reader.consume(1);
+            value = value
+                .checked_mul(10)
+                .and_then(|value| value.checked_add((byte - b'0') as u32))
+                .ok_or("Header value out of range")?;
+            digits += 1;
+        }
+
+        if digits == 0 {
+            return Err("Expected a number in the header".to_string());
+        }
+        Ok(value)
+    }
+
+    fn skip_whitespace(&mut self) -> Result<(), String> {
+        while let Some(byte) = self.peek()? {
+            if byte == b'#' {
+                let mut comment = Vec::new();
+                self.reader
+                    .read_until(b'\n', &mut comment)
+                    .map_err(|err| err.to_string())?;
+            } else if byte.is_ascii_whitespace() {
+                self.reader.consume(1);
+            } else {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>, String> {
+        let buffer = self.reader.fill_buf().map_err(|err| err.to_string())?;
+        Ok(buffer.first().copied())
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        let byte = self.peek()?.ok_or("Unexpected end of header")?;
+        self.reader.consume(1);
+        Ok(byte)
+    }
+}
+
+impl<R: BufRead> Iterator for NetpbmReader<R> {
+    type Item = Result<Frame<'static>, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
+
+struct Header {
+    width: u32,
+    height: u32,
+    depth: usize, // Samples per pixel
+    maxval: u32,
+}
+
+impl Header {
+    fn new(width: u32, height: u32, depth: usize, maxval: u32) -> Result<Self, String> {
+        if !(1..=65535).contains(&maxval) {
+            return Err(format!("Invalid maxval {}", maxval));
+        }
+        Ok(Header {
+            width,
+            height,
+            depth,
+            maxval,
+        })
+    }
+
+    fn size(&self) -> Result<(u16, u16), String> {
+        match (u16::try_from(self.width), u16::try_from(self.height)) {
+            (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+            _ => Err(format!(
+                "Image size {}x{} doesn't fit a GIF",
+                self.width, self.height
+            )),
+        }
+    }
+}
+
+// Rescale a sample from 0..=maxval to 0..=255, rounding to nearest
+fn scale(sample: u32, maxval: u32) -> u8 {
+    ((sample.min(maxval) * 255 * 2 + maxval) / (maxval * 2)) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(data: &[u8]) -> Result<Vec<Frame<'static>>, String> {
+        NetpbmReader::new(data).collect()
+    }
+
+    #[test]
+    fn test_ppm_stream() -> Result<(), String> {
+        let mut data = b"P6\n# comment\n2 1\n255\n".to_vec();
+        data.extend_from_slice(&[1, 2, 3, 4, 5, 6]);
+        data.extend_from_slice(b"P6 1 1 255 ");
+        data.extend_from_slice(&[7, 8, 9]);
+
+        let frames = read_all(&data)?;
+        assert_eq!(frames.len(), 2);
+        assert_eq!((frames[0].width, frames[0].height, frames[0].format), (2, 1, PixelFormat::Rgb));
+        assert_eq!(&frames[0].pixels[..], &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(&frames[1].pixels[..], &[7, 8, 9]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rgba_pam() -> Result<(), String> {
+        let mut data = b"P7\nWIDTH 2\nHEIGHT 1\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n".to_vec();
+        data.extend_from_slice(&[10, 20, 30, 0, 40, 50, 60, 255]);
+
+        let frames = read_all(&data)?;
+        assert_eq!(frames[0].format, PixelFormat::Rgba);
+        assert_eq!(&frames[0].pixels[..], &[10, 20, 30, 0, 40, 50, 60, 255]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_grayscale_and_deep_samples() -> Result<(), String> {
+        let mut data = b"P5 2 1 65535\n".to_vec();
+        data.extend_from_slice(&[0xff, 0xff, 0x80, 0x00]);
+        data.extend_from_slice(b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 2\nMAXVAL 15\nTUPLTYPE GRAYSCALE_ALPHA\nENDHDR\n");
+        data.extend_from_slice(&[15, 0]);
+
+        let frames = read_all(&data)?;
+        assert_eq!(&frames[0].pixels[..], &[255, 255, 255, 128, 128, 128]);
+        assert_eq!(&frames[1].pixels[..], &[255, 255, 255, 0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_plain_pgm_and_ppm() -> Result<(), String> {
+        let data = b"P3\n# plain\n2 1 255\n1 2 3\n4 5 6\nP2 2 1 15 15 0\n";
+
+        let frames = read_all(data)?;
+        assert_eq!(frames.len(), 2);
+        assert_eq!(&frames[0].pixels[..], &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(&frames[1].pixels[..], &[255, 255, 255, 0, 0, 0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        assert!(read_all(b"P3 1 1 255\n1 2").is_err());
+        assert!(read_all(b"P2 1 1 15\n16\n").is_err());
+        assert!(read_all(b"P6 2 2 255\n\x01\x02\x03").is_err());
+        assert!(read_all(b"P6 70000 1 255\n").is_err());
+        assert_eq!(
+            read_all(b"P7\nWIDTH 65535\nHEIGHT 65535\nDEPTH 4\nMAXVAL 65535\nTUPLTYPE RGB_ALPHA\nENDHDR\n\x01\x02").err(),
+            Some("Truncated image data: 2 of 34358689800 bytes".to_string())
+        );
+        assert!(read_all(b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 3\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n").is_err());
+        assert_eq!(read_all(b"  \n").map(|frames| frames.len()), Ok(0));
+    }
+}
@@ -0,0 +1,410 @@
+// MIT License
+// Copyright (c) 2025 Gianluca Cannata <gcannata23@gmail.com>
+//
+// av-gif - A GIF encoder written in Rust
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::decoder::{decode, render, Canvas, DecodedGif};
+use crate::encoder::{
+    DisposalMethod, FrameRect, GifEncoder, GifEncoderState, GifEvent, GifWriter, ScreenOptions,
+};
+use crate::lzw::LzwEncoder;
+
+// Re-encode a GIF as small as possible while showing exactly the same pixels at the same
+// times. Frames are recomposited, then written as the cropped change from what is already
+// on screen, with unchanged pixels turned transparent when that compresses better and
+// palettes cut down to the colors actually used. The output is checked by rendering it
+// again; the input comes back as is when it's already smaller, or when a frame would
+// need more than 256 colors.
+pub fn optimize(data: &[u8]) -> Result<Vec<u8>, String> {
+    let gif = decode(data)?;
+    let canvases = render(&gif)?;
+    if canvases.is_empty() {
+        return Ok(data.to_vec());
+    }
+
+    let frames = plan_frames(&gif, &canvases);
+
+    // Local palettes can be smaller per frame, a global one is only written once
+    let optimized = [true, false]
+        .into_iter()
+        .filter_map(|global| encode(&gif, &frames, global))
+        .filter(|output| renders_like(output, &gif, &canvases))
+        .min_by_key(Vec::len);
+
+    Ok(match optimized {
+        Some(output) if output.len() < data.len() => output,
+        _ => data.to_vec(),
+    })
+}
+
+// An output frame: the part of the screen to redraw and what to draw there
+struct PlannedFrame {
+    rect: FrameRect,
+    target: Vec<Option<[u8; 3]>>,  // Screen contents inside `rect` after the frame
+    changed: Vec<Option<[u8; 3]>>, // Same, with pixels already on screen left out
+    disposal_method: DisposalMethod,
+    delay: u16,
+    user_input: bool,
+}
+
+fn plan_frames(gif: &DecodedGif, canvases: &[Canvas]) -> Vec<PlannedFrame> {
+    let width = gif.width as usize;
+    let mut screen: Canvas = vec![None; canvases[0].len()];
+    let mut frames = Vec::with_capacity(canvases.len());
+
+    for (number, target) in canvases.iter().enumerate() {
+        // Only disposal can make pixels transparent again, so a frame must cover the
+        // pixels the next one clears and dispose to background
+        let cleared = canvases.get(number + 1).and_then(|next| {
+            bounding_box(width, (0..next.len()).filter(|&i| next[i].is_none() && target[i].is_some()))
+        });
+        let changed = bounding_box(width, (0..target.len()).filter(|&i| screen[i] != target[i]));
+
+        // GIF frames can't be empty, an unchanged screen still needs one pixel
+        let rect = union(changed, cleared).unwrap_or(FrameRect::new(0, 0, 1, 1));
+        let disposal_method = match cleared {
+            Some(_) => DisposalMethod::Background,
+            None => DisposalMethod::None,
+        };
+
+        let positions: Vec<usize> = rect_positions(width, rect).collect();
+        let frame = &gif.frames[number];
+        frames.push(PlannedFrame {
+            rect,
+            target: positions.iter().map(|&i| target[i]).collect(),
+            changed: positions
+                .iter()
+                .map(|&i| target[i].filter(|_| screen[i] != target[i]))
+                .collect(),
+            disposal_method,
+            delay: frame.delay,
+            user_input: frame.user_input,
+        });
+
+        screen.clone_from(target);
+        if disposal_method == DisposalMethod::Background {
+            for &i in &positions {
+                screen[i] = None;
+            }
+        }
+    }
+
+    frames
+}
+
+fn rect_positions(width: usize, rect: FrameRect) -> impl Iterator<Item = usize> {
+    let (left, top) = (rect.left as usize, rect.top as usize);
+    (top..top + rect.height as usize)
+        .flat_map(move |y| (left..left + rect.width as usize).map(move |x| y * width + x))
+}
+
+fn bounding_box(width: usize, positions: impl Iterator<Item = usize>) -> Option<FrameRect> {
+    let mut bounds: Option<(usize, usize, usize, usize)> = None;
+
+    for i in positions {
+        let (x, y) = (i % width, i / width);
+        let (left, top, right, bottom) = bounds.get_or_insert((x, y, x, y));
+        *left = (*left).min(x);
+        *top = (*top).min(y);
+        *right = (*right).max(x);
+        *bottom = (*bottom).max(y);
+    }
+
+    bounds.map(|(left, top, right, bottom)| {
+        FrameRect::new(
+            left as u16,
+            top as u16,
+            (right - left + 1) as u16,
+            (bottom - top + 1) as u16,
+        )
+    })
+}
+
+fn union(a: Option<FrameRect>, b: Option<FrameRect>) -> Option<FrameRect> {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            let left = a.left.min(b.left);
+            let top = a.top.min(b.top);
+            let right = (a.left + a.width).max(b.left + b.width);
+            let bottom = (a.top + a.height).max(b.top + b.height);
+            Some(FrameRect::new(left, top, right - left, bottom - top))
+        }
+        (a, b) => a.or(b),
+    }
+}
+
+// Colors ordered by decreasing use, plus a transparent slot at the end when there's room
+fn build_palette<'a>(pixels: impl Iterator<Item = &'a Option<[u8; 3]>>) -> Option<Palette> {
+    let mut counts: BTreeMap<[u8; 3], usize> = BTreeMap::new();
+    for color in pixels.flatten() {
+        *counts.entry(*color).or_default() += 1;
+    }
+    if counts.len() > 256 {
+        return None;
+    }
+
+    let mut colors: Vec<([u8; 3], usize)> = counts.into_iter().collect();
+    colors.sort_by_key(|&(_, count)| core::cmp::Reverse(count));
+
+    let mut palette: Vec<[u8; 3]> = colors.into_iter().map(|(color, _)| color).collect();
+    let lookup = palette
+        .iter()
+        .enumerate()
+        .map(|(index, &color)| (color, index as u8))
+        .collect();
+    let transparent_index = (palette.len() < 256).then(|| {
+        palette.push([0, 0, 0]);
+        palette.len() as u8 - 1
+    });
+
+    Some(Palette {
+        colors: palette,
+        lookup,
+        transparent_index,
+    })
+}
+
+struct Palette {
+    colors: Vec<[u8; 3]>,
+    lookup: BTreeMap<[u8; 3], u8>,
+    transparent_index: Option<u8>,
+}
+
+impl Palette {
+    // Palette indices for the pixels, None if they need a transparent slot there isn't
+    fn indices(&self, pixels: &[Option<[u8; 3]>]) -> Option<Vec<u8>> {
+        pixels
+            .iter()
+            .map(|pixel| match pixel {
+                Some(color) => self.lookup.get(color).copied(),
+                None => self.transparent_index,
+            })
+            .collect()
+    }
+
+    // Colors to write, leaving out the transparent slot when it isn't used
+    fn colors(&self, uses_transparency: bool) -> &[[u8; 3]] {
+        match (self.transparent_index, uses_transparency) {
+            (Some(index), false) => &self.colors[..index as usize],
+            _ => &self.colors,
+        }
+    }
+}
+
+// A frame's compressed data with the local palette and transparency it was compressed for
+struct CompressedFrame<'a> {
+    data: Vec<u8>,
+    local_palette: Option<&'a [[u8; 3]]>,
+    transparent_index: Option<u8>,
+}
+
+// Compress whichever of the frame's two pixel sets packs smaller
+fn compress<'a>(frame: &PlannedFrame, global_palette: Option<&Palette>, local_palette: Option<&'a Palette>) -> Option<CompressedFrame<'a>> {
+    let palette = local_palette.or(global_palette)?;
+
+    [&frame.changed, &frame.target]
+        .into_iter()
+        .filter_map(|pixels| {
+            let uses_transparency = pixels.iter().any(Option::is_none);
+            let indices = palette.indices(pixels)?;
+            let local_palette = local_palette.map(|palette| palette.colors(uses_transparency));
+
+            // Must match the minimum code size the image descriptor gets for these palettes
+            let min_code_size = GifWriter::calculate_min_code_size(
+                global_palette.map(|palette| palette.colors.as_slice()),
+                local_palette,
+            );
+            let mut lzw_encoder = LzwEncoder::new(min_code_size);
+            lzw_encoder.encode_chunk(&indices);
+            lzw_encoder.finalize();
+
+            Some(CompressedFrame {
+                data: lzw_encoder.get_encoded_data().to_vec(),
+                local_palette,
+                transparent_index: palette.transparent_index.filter(|_| uses_transparency),
+            })
+        })
+        .min_by_key(|compressed| compressed.data.len())
+}
+
+fn encode(gif: &DecodedGif, frames: &[PlannedFrame], global: bool) -> Option<Vec<u8>> {
+    let global_palette = match global {
+        true => Some(build_palette(frames.iter().flat_map(|frame| &frame.target))?),
+        false => None,
+    };
+
+    let mut encoder = GifEncoderState::new();
+    let result = (|| -> Result<(), String> {
+        encoder.process_event(GifEvent::StartGif {
+            width: gif.width,
+            height: gif.height,
+            global_palette: global_palette.as_ref().map(|palette| palette.colors.as_slice().into()),
+            background_color_index: 0,
+            looping: gif.looping.clone(),
+            version: None,
+            screen: ScreenOptions {
+                sort_flag: false,
+                ..gif.screen
+            },
+        })?;
+
+        for comment in &gif.comments {
+            encoder.process_event(GifEvent::Comment {
+                text: comment.as_slice().into(),
+            })?;
+        }
+
+        for frame in frames {
+            let local_palette = match global_palette {
+                Some(_) => None,
+                None => Some(build_palette(frame.target.iter()).ok_or("Frame has too many colors")?),
+            };
+            let compressed = compress(frame, global_palette.as_ref(), local_palette.as_ref())
+                .ok_or("Frame needs a transparent slot")?;
+
+            encoder.process_event(GifEvent::StartFrame {
+                delay: frame.delay,
+                disposal_method: frame.disposal_method,
+                global_palette: None,
+                local_palette: compressed.local_palette.map(Into::into),
+                local_palette_sorted: false,
+                transparent_color_index: compressed.transparent_index,
+                user_input: frame.user_input,
+                is_interlaced: false,
+                rect: Some(frame.rect),
+            })?;
+            encoder.process_event(GifEvent::WriteCompressedData {
+                data: compressed.data.into(),
+            })?;
+            encoder.process_event(GifEvent::EndFrame)?;
+        }
+
+        encoder.process_event(GifEvent::EndGif)
+    })();
+
+    result.ok()?;
+    Some(encoder.get_encoded_data().to_vec())
+}
+
+fn renders_like(output: &[u8], gif: &DecodedGif, canvases: &[Canvas]) -> bool {
+    let Ok(optimized) = decode(output) else {
+        return false;
+    };
+    let timing = |gif: &DecodedGif| -> Vec<(u16, bool)> {
+        gif.frames
+            .iter()
+            .map(|frame| (frame.delay, frame.user_input))
+            .collect()
+    };
+
+    timing(&optimized) == timing(gif) && render(&optimized).is_ok_and(|rendered| rendered == canvases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::LoopConfig;
+
+    // A badly encoded animation: full frames with a local 256-entry palette each, where
+    // only a small square moves over a two-color background
+    fn bloated_gif(frame_count: u16) -> Result<Vec<u8>, String> {
+        let mut palette: Vec<[u8; 3]> = (0..=255u8).map(|i| [i, 255 - i, i / 2]).collect();
+        palette[0] = [10, 10, 200];
+        palette[1] = [250, 250, 250];
+        palette[2] = [200, 20, 20];
+
+        let mut encoder = GifEncoderState::new();
+        encoder.process_event(GifEvent::StartGif { width: 40, height: 30, global_palette: None, background_color_index: 0, looping: LoopConfig::infinite(), version: None, screen: ScreenOptions::default() })?;
+        encoder.process_event(GifEvent::Comment { text: b"made badly"[..].into() })?;
+
+        for frame in 0..frame_count {
+            let pixels: Vec<u8> = (0..40 * 30u16)
+                .map(|i| {
+                    let (x, y) = (i % 40, i / 40);
+                    if (frame * 3..frame * 3 + 5).contains(&x) && (10..15).contains(&y) {
+                        2
+                    } else {
+                        (y >= 20) as u8
+                    }
+                })
+                .collect();
+
+            encoder.process_event(GifEvent::StartFrame { delay: 5 + frame, disposal_method: DisposalMethod::None, global_palette: None, local_palette: Some(palette.as_slice().into()), local_palette_sorted: false, transparent_color_index: None, user_input: false, is_interlaced: true, rect: None })?;
+            encoder.process_event(GifEvent::WriteImageChunk { data: pixels.into() })?;
+            encoder.process_event(GifEvent::EndFrame)?;
+        }
+        encoder.process_event(GifEvent::EndGif)?;
+
+        Ok(encoder.get_encoded_data().to_vec())
+    }
+
+    #[test]
+    fn test_optimize_shrinks_and_renders_identically() -> Result<(), String> {
+        let input = bloated_gif(8)?;
+        let output = optimize(&input)?;
+        crate::validate::assert_valid(&output);
+        assert!(output.len() * 2 < input.len(), "{} -> {} bytes", input.len(), output.len());
+
+        let (original, optimized) = (decode(&input)?, decode(&output)?);
+        assert_eq!(render(&optimized)?, render(&original)?);
+        assert_eq!(optimized.looping, original.looping);
+        assert_eq!(optimized.comments, original.comments);
+
+        // Later frames only cover the moving square
+        let second = &optimized.frames[1];
+        assert!(second.width < 10 && second.height == 5, "{:?}", second.rect());
+        Ok(())
+    }
+
+    #[test]
+    fn test_transparency_is_restored_with_disposal() -> Result<(), String> {
+        let palette = vec![[0, 0, 0], [255, 0, 0], [0, 0, 255]];
+        let mut encoder = GifEncoderState::new();
+
+        // Red square, then a blue one elsewhere while the red one disappears again
+        encoder.process_event(GifEvent::StartGif { width: 8, height: 8, global_palette: Some(palette.into()), background_color_index: 0, looping: LoopConfig::infinite(), version: None, screen: ScreenOptions::default() })?;
+        encoder.process_event(GifEvent::StartFrame { delay: 10, disposal_method: DisposalMethod::Previous, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: Some(0), user_input: false, is_interlaced: false, rect: Some(FrameRect::new(1, 1, 2, 2)) })?;
+        encoder.process_event(GifEvent::WriteImageChunk { data: vec![1, 1, 1, 0].into() })?;
+        encoder.process_event(GifEvent::EndFrame)?;
+        encoder.process_event(GifEvent::StartFrame { delay: 10, disposal_method: DisposalMethod::Background, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: Some(0), user_input: true, is_interlaced: false, rect: None })?;
+        encoder.process_event(GifEvent::WriteImageChunk { data: (0..64).map(|i| if i == 63 { 2 } else { 0 }).collect::<Vec<u8>>().into() })?;
+        encoder.process_event(GifEvent::EndFrame)?;
+        encoder.process_event(GifEvent::StartFrame { delay: 20, disposal_method: DisposalMethod::None, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: Some(0), user_input: false, is_interlaced: false, rect: Some(FrameRect::new(0, 0, 1, 1)) })?;
+        encoder.process_event(GifEvent::WriteImageChunk { data: vec![0].into() })?;
+        encoder.process_event(GifEvent::EndFrame)?;
+        encoder.process_event(GifEvent::EndGif)?;
+
+        let input = encoder.get_encoded_data().to_vec();
+        let (original, canvases) = {
+            let gif = decode(&input)?;
+            let canvases = render(&gif)?;
+            (gif, canvases)
+        };
+        assert_eq!(canvases[2], vec![None; 64]);
+
+        let frames = plan_frames(&original, &canvases);
+        let output = encode(&original, &frames, true).ok_or("encoding failed")?;
+        assert!(renders_like(&output, &original, &canvases));
+
+        let optimized = decode(&output)?;
+        assert_eq!(optimized.frames[0].disposal_method, DisposalMethod::Background);
+        assert!(optimized.frames[1].user_input);
+        Ok(())
+    }
+
+    #[test]
+    fn test_small_input_is_kept() -> Result<(), String> {
+        let input = bloated_gif(1)?;
+        let output = optimize(&input)?;
+        crate::validate::assert_valid(&output);
+        assert!(output.len() <= input.len());
+        assert_eq!(render(&decode(&output)?)?, render(&decode(&input)?)?);
+
+        assert!(optimize(b"not a gif").is_err());
+        Ok(())
+    }
+}
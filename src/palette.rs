@@ -0,0 +1,482 @@
+// MIT License
+// Copyright (c) 2025 Gianluca Cannata <gcannata23@gmail.com>
+//
+// av-gif - A GIF encoder written in Rust
+use std::borrow::Cow;
+
+use crate::encoder::GifWriter;
+use crate::quantization::ColorSpace;
+
+// Up to 256 colors, optionally with one of them reserved for transparent pixels. Nearest
+// color lookups go through a k-d tree over the opaque entries, placed by their coordinates
+// in the palette's color space, and never return the transparent slot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    colors: Vec<[u8; 3]>,
+    transparent_index: Option<u8>,
+    color_space: ColorSpace,
+    tree: Vec<Node>,
+}
+
+// Opaque palette entry, laid out as an implicit k-d tree: the node of a range sits in
+// its middle, with the entries before it on the low side of `axis` and the ones after
+// it on the high side
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Node {
+    point: [f32; 3],
+    index: u8,
+    axis: u8,
+}
+
+impl Palette {
+    pub fn new(colors: Vec<[u8; 3]>) -> Result<Self, String> {
+        if colors.len() > 256 {
+            return Err(format!("Palette has {} colors, the limit is 256", colors.len()));
+        }
+
+        Ok(Self::from_colors(colors))
+    }
+
+    // At most 256 colors, checked by the caller
+    fn from_colors(colors: Vec<[u8; 3]>) -> Self {
+        let mut palette = Palette {
+            colors,
+            transparent_index: None,
+            color_space: ColorSpace::Srgb,
+            tree: Vec::new(),
+        };
+        palette.build_tree();
+        palette
+    }
+
+    // Append a slot for transparent pixels
+    pub fn with_transparent_slot(mut self) -> Result<Self, String> {
+        if self.colors.len() >= 256 {
+            return Err("Palette is full, there's no room for a transparent slot".to_string());
+        }
+
+        self.colors.push([0, 0, 0]);
+        let index = self.colors.len() as u8 - 1;
+        self.with_transparent_index(index)
+    }
+
+    // Reserve an existing entry for transparent pixels
+    pub fn with_transparent_index(mut self, index: u8) -> Result<Self, String> {
+        if index as usize >= self.colors.len() {
+            return Err(format!("Transparent index {} is outside the palette", index));
+        }
+
+        self.transparent_index = Some(index);
+        self.build_tree();
+        Ok(self)
+    }
+
+    // Compare colors in `color_space` when looking for the nearest one
+    pub fn with_color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = color_space;
+        self.build_tree();
+        self
+    }
+
+    pub fn colors(&self) -> &[[u8; 3]] {
+        &self.colors
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
+    pub fn transparent_index(&self) -> Option<u8> {
+        self.transparent_index
+    }
+
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    // Entries the palette takes as a color table on disk, a power of two
+    pub fn padded_len(&self) -> usize {
+        GifWriter::color_table_len(self.colors.len())
+    }
+
+    // The colors padded with black up to `padded_len`
+    pub fn padded_colors(&self) -> Vec<[u8; 3]> {
+        let mut colors = self.colors.clone();
+        colors.resize(self.padded_len(), [0, 0, 0]);
+        colors
+    }
+
+    // Index of the opaque entry closest to `color`, the lowest one on ties. A palette
+    // without opaque entries gives its transparent slot, or 0 when empty.
+    pub fn nearest(&self, color: [u8; 3]) -> u8 {
+        let mut best = (f32::INFINITY, self.transparent_index.unwrap_or(0));
+        search(&self.tree, self.color_space.from_srgb(color), &mut best);
+        best.1
+    }
+
+    fn build_tree(&mut self) {
+        self.tree = self
+            .colors
+            .iter()
+            .enumerate()
+            .filter(|&(index, _)| Some(index as u8) != self.transparent_index)
+            .map(|(index, &color)| Node {
+                point: self.color_space.from_srgb(color),
+                index: index as u8,
+                axis: 0,
+            })
+            .collect();
+        build(&mut self.tree);
+    }
+}
+
+// Fixed palettes
+impl Palette {
+    // The 216 colors of the 6x6x6 web-safe cube, steps of 51 per channel
+    pub fn web_safe() -> Self {
+        let steps = (0..6).map(|step| step * 51);
+        let colors = steps
+            .clone()
+            .flat_map(|r| steps.clone().flat_map(move |g| (0..6).map(move |b| [r, g, b * 51])))
+            .collect();
+        Self::from_colors(colors)
+    }
+
+    // `levels` evenly spaced grays from black to white
+    pub fn grayscale(levels: usize) -> Result<Self, String> {
+        if !(2..=256).contains(&levels) {
+            return Err("A grayscale ramp needs between 2 and 256 levels".to_string());
+        }
+
+        let colors = (0..levels)
+            .map(|level| {
+                let gray = ((level * 255 + (levels - 1) / 2) / (levels - 1)) as u8;
+                [gray; 3]
+            })
+            .collect();
+        Ok(Self::from_colors(colors))
+    }
+
+    pub fn black_and_white() -> Self {
+        Self::from_colors(vec![[0, 0, 0], [255, 255, 255]])
+    }
+}
+
+// Palette files: Adobe color tables (.act), GIMP palettes (.gpl) and JASC / Paint Shop
+// Pro palettes (.pal)
+impl Palette {
+    // 256 RGB triplets, optionally followed by the number of colors in use and the
+    // transparent index (0xFFFF for none), both big-endian
+    pub fn from_act(data: &[u8]) -> Result<Self, String> {
+        let (table, len, transparent_index) = match data.len() {
+            768 => (data, 256, None),
+            772 => {
+                let len = u16::from_be_bytes([data[768], data[769]]) as usize;
+                let transparent_index = u16::from_be_bytes([data[770], data[771]]);
+                // Some writers store 0 for a full table
+                let len = if len == 0 { 256 } else { len };
+                (&data[..768], len, (transparent_index != 0xFFFF).then_some(transparent_index))
+            }
+            len => return Err(format!("ACT palette is {} bytes, expected 768 or 772", len)),
+        };
+
+        if len > 256 {
+            return Err(format!("ACT palette declares {} colors", len));
+        }
+        let palette = Palette::new(table.chunks_exact(3).take(len).map(|rgb| [rgb[0], rgb[1], rgb[2]]).collect())?;
+        match transparent_index {
+            Some(index) if index > 255 => Err(format!("Transparent index {} is outside the palette", index)),
+            Some(index) => palette.with_transparent_index(index as u8),
+            None => Ok(palette),
+        }
+    }
+
+    // Always the 772 byte form, so the color count and transparent index survive
+    pub fn to_act(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = self.colors.iter().flatten().copied().collect();
+        data.resize(768, 0);
+        data.extend_from_slice(&(self.colors.len() as u16).to_be_bytes());
+        data.extend_from_slice(&self.transparent_index.map_or(0xFFFF, u16::from).to_be_bytes());
+        data
+    }
+
+    pub fn from_gpl(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines();
+        if lines.next().map(str::trim) != Some("GIMP Palette") {
+            return Err("Not a GIMP palette".to_string());
+        }
+
+        let mut colors = Vec::new();
+        for line in lines {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("Name:") || line.starts_with("Columns:") {
+                continue;
+            }
+            // Anything after the three channels is the color's name
+            colors.push(parse_rgb(line.split_whitespace().take(3), line)?);
+        }
+        Palette::new(colors)
+    }
+
+    pub fn to_gpl(&self, name: &str) -> String {
+        let mut text = format!("GIMP Palette\nName: {}\nColumns: 16\n#\n", name);
+        for (index, [r, g, b]) in self.colors.iter().enumerate() {
+            text.push_str(&format!("{:3} {:3} {:3}\tIndex {}\n", r, g, b, index));
+        }
+        text
+    }
+
+    pub fn from_jasc_pal(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().map(str::trim);
+        if lines.next() != Some("JASC-PAL") || lines.next() != Some("0100") {
+            return Err("Not a JASC palette".to_string());
+        }
+
+        let len: usize = lines
+            .next()
+            .and_then(|count| count.parse().ok())
+            .ok_or("JASC palette is missing its color count")?;
+        let colors = lines
+            .filter(|line| !line.is_empty())
+            .take(len)
+            .map(|line| parse_rgb(line.split_whitespace(), line))
+            .collect::<Result<Vec<_>, _>>()?;
+        if colors.len() != len {
+            return Err(format!("JASC palette declares {} colors but has {}", len, colors.len()));
+        }
+        Palette::new(colors)
+    }
+
+    // CRLF line endings, like Paint Shop Pro writes them
+    pub fn to_jasc_pal(&self) -> String {
+        let mut text = format!("JASC-PAL\r\n0100\r\n{}\r\n", self.colors.len());
+        for [r, g, b] in &self.colors {
+            text.push_str(&format!("{} {} {}\r\n", r, g, b));
+        }
+        text
+    }
+}
+
+fn parse_rgb<'a>(mut channels: impl Iterator<Item = &'a str>, line: &str) -> Result<[u8; 3], String> {
+    let mut color = [0u8; 3];
+    for value in &mut color {
+        *value = channels
+            .next()
+            .and_then(|channel| channel.parse().ok())
+            .ok_or_else(|| format!("Invalid palette color \"{}\"", line))?;
+    }
+    if channels.next().is_some() {
+        return Err(format!("Invalid palette color \"{}\"", line));
+    }
+    Ok(color)
+}
+
+// So palettes can go straight into StartGif and StartFrame
+impl<'a> From<&'a Palette> for Cow<'a, [[u8; 3]]> {
+    fn from(palette: &'a Palette) -> Self {
+        Cow::Borrowed(palette.colors())
+    }
+}
+
+impl From<Palette> for Cow<'static, [[u8; 3]]> {
+    fn from(palette: Palette) -> Self {
+        Cow::Owned(palette.colors)
+    }
+}
+
+// Split on the channel with the widest range at the median
+fn build(nodes: &mut [Node]) {
+    if nodes.is_empty() {
+        return;
+    }
+
+    let range = |axis: usize| {
+        let (min, max) = nodes.iter().fold((f32::MAX, f32::MIN), |(min, max), node| {
+            (min.min(node.point[axis]), max.max(node.point[axis]))
+        });
+        max - min
+    };
+    let axis = (0..3).max_by(|&a, &b| range(a).total_cmp(&range(b))).unwrap_or(0);
+    nodes.sort_unstable_by(|a, b| a.point[axis].total_cmp(&b.point[axis]).then(a.index.cmp(&b.index)));
+
+    let middle = nodes.len() / 2;
+    nodes[middle].axis = axis as u8;
+    let (low, rest) = nodes.split_at_mut(middle);
+    build(low);
+    build(&mut rest[1..]);
+}
+
+fn search(nodes: &[Node], point: [f32; 3], best: &mut (f32, u8)) {
+    if nodes.is_empty() {
+        return;
+    }
+
+    let middle = nodes.len() / 2;
+    let node = nodes[middle];
+    let distance = distance(node.point, point);
+    if distance < best.0 || (distance == best.0 && node.index < best.1) {
+        *best = (distance, node.index);
+    }
+
+    let axis = node.axis as usize;
+    let offset = point[axis] - node.point[axis];
+    let (near, far) = match offset < 0.0 {
+        true => (&nodes[..middle], &nodes[middle + 1..]),
+        false => (&nodes[middle + 1..], &nodes[..middle]),
+    };
+
+    search(near, point, best);
+    // Equal distances still count, a lower index may be on the other side
+    if offset * offset <= best.0 {
+        search(far, point, best);
+    }
+}
+
+// Squared Euclidean distance
+fn distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3).map(|axis| (a[axis] - b[axis]) * (a[axis] - b[axis])).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Linear scan the tree has to agree with
+    fn brute_force(palette: &Palette, color: [u8; 3]) -> u8 {
+        let space = palette.color_space();
+        let distance_to = |index: usize| distance(space.from_srgb(palette.colors()[index]), space.from_srgb(color));
+        (0..palette.len())
+            .filter(|&index| Some(index as u8) != palette.transparent_index())
+            .min_by(|&a, &b| distance_to(a).total_cmp(&distance_to(b)).then(a.cmp(&b)))
+            .unwrap_or(0) as u8
+    }
+
+    #[test]
+    fn test_nearest_matches_linear_search() -> Result<(), String> {
+        let mut seed = 12345u32;
+        let mut random = || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            [(seed >> 8) as u8, (seed >> 16) as u8, (seed >> 24) as u8]
+        };
+
+        for size in [1, 2, 7, 100, 256] {
+            // Coarse colors so there are plenty of duplicates and ties
+            let colors: Vec<[u8; 3]> = (0..size).map(|_| random().map(|value| value & 0xE0)).collect();
+            for space in [ColorSpace::Srgb, ColorSpace::LinearRgb, ColorSpace::Lab, ColorSpace::Oklab] {
+                let palette = Palette::new(colors.clone())?.with_color_space(space);
+                for _ in 0..200 {
+                    let color = random();
+                    assert_eq!(palette.nearest(color), brute_force(&palette, color), "{:?}, {} colors, {:?}", space, size, color);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_transparent_slot() -> Result<(), String> {
+        let palette = Palette::new(vec![[0, 0, 0], [255, 255, 255]])?.with_transparent_slot()?;
+        assert_eq!(palette.colors(), &[[0, 0, 0], [255, 255, 255], [0, 0, 0]]);
+        assert_eq!(palette.transparent_index(), Some(2));
+        assert_eq!(palette.nearest([0, 0, 0]), 0);
+
+        let palette = Palette::new(vec![[0, 0, 0], [255, 255, 255]])?.with_transparent_index(0)?;
+        assert_eq!(palette.nearest([0, 0, 0]), 1);
+        assert_eq!(Palette::new(vec![[9, 9, 9]])?.with_transparent_index(0)?.nearest([9, 9, 9]), 0);
+
+        assert!(Palette::new(vec![[0, 0, 0]; 256])?.with_transparent_slot().is_err());
+        assert!(Palette::new(vec![[0, 0, 0]; 2])?.with_transparent_index(2).is_err());
+        assert!(Palette::new(vec![[0, 0, 0]; 257]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_padding() -> Result<(), String> {
+        let palette = Palette::new(vec![[1, 2, 3]; 5])?;
+        assert_eq!(palette.padded_len(), 8);
+        assert_eq!(palette.padded_colors()[4..], [[1, 2, 3], [0, 0, 0], [0, 0, 0], [0, 0, 0]]);
+        assert_eq!(Palette::new(Vec::new())?.padded_len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fixed_palettes() -> Result<(), String> {
+        let web = Palette::web_safe();
+        assert_eq!(web.len(), 216);
+        assert_eq!((web.colors()[0], web.colors()[1], web.colors()[215]), ([0, 0, 0], [0, 0, 51], [255, 255, 255]));
+        assert_eq!(web.nearest([50, 100, 160]), web.colors().iter().position(|&color| color == [51, 102, 153]).unwrap() as u8);
+
+        assert_eq!(Palette::grayscale(5)?.colors(), &[[0; 3], [64; 3], [128; 3], [191; 3], [255; 3]]);
+        assert_eq!(Palette::grayscale(256)?.colors()[100], [100; 3]);
+        assert!(Palette::grayscale(1).is_err());
+        assert_eq!(Palette::black_and_white().nearest([100, 200, 100]), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_act() -> Result<(), String> {
+        let palette = Palette::new(vec![[1, 2, 3], [4, 5, 6], [7, 8, 9]])?.with_transparent_index(1)?;
+        let data = palette.to_act();
+        assert_eq!(data.len(), 772);
+        assert_eq!(data[768..], [0, 3, 0, 1]);
+        assert_eq!(Palette::from_act(&data)?, palette);
+
+        // The short form always holds 256 colors
+        let full = Palette::from_act(&data[..768])?;
+        assert_eq!((full.len(), full.transparent_index()), (256, None));
+        assert!(Palette::from_act(&data[..700]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_gpl() -> Result<(), String> {
+        let text = "GIMP Palette\nName: Brand\nColumns: 4\n# Primary colors\n255   0   0\tRed\n  0 128 255 Sky blue\n\n";
+        let palette = Palette::from_gpl(text)?;
+        assert_eq!(palette.colors(), &[[255, 0, 0], [0, 128, 255]]);
+        assert_eq!(Palette::from_gpl(&palette.to_gpl("Brand"))?, palette);
+
+        assert!(Palette::from_gpl("JASC-PAL\n").is_err());
+        assert!(Palette::from_gpl("GIMP Palette\n255 0\n").is_err());
+        assert!(Palette::from_gpl("GIMP Palette\n256 0 0\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_jasc_pal() -> Result<(), String> {
+        let palette = Palette::from_jasc_pal("JASC-PAL\n0100\n2\n10 20 30\n40 50 60\n")?;
+        assert_eq!(palette.colors(), &[[10, 20, 30], [40, 50, 60]]);
+
+        let text = palette.to_jasc_pal();
+        assert!(text.starts_with("JASC-PAL\r\n0100\r\n2\r\n"));
+        assert_eq!(Palette::from_jasc_pal(&text)?, palette);
+
+        assert!(Palette::from_jasc_pal("JASC-PAL\n0100\n3\n10 20 30\n").is_err());
+        assert!(Palette::from_jasc_pal("JASC-PAL\n0100\n1\n10 20 30 40\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_palette_in_events() -> Result<(), String> {
+        use crate::decoder::decode;
+        use crate::encoder::{DisposalMethod, GifEncoder, GifEncoderState, GifEvent, LoopConfig, ScreenOptions};
+
+        let global = Palette::web_safe();
+        let local = Palette::grayscale(4)?;
+        let mut encoder = GifEncoderState::new();
+        encoder.process_event(GifEvent::StartGif { width: 2, height: 1, global_palette: Some((&global).into()), background_color_index: 0, looping: LoopConfig::once(), version: None, screen: ScreenOptions::default() })?;
+        encoder.process_event(GifEvent::StartFrame { delay: 0, disposal_method: DisposalMethod::None, global_palette: None, local_palette: Some(local.clone().into()), local_palette_sorted: false, transparent_color_index: None, user_input: false, is_interlaced: false, rect: None })?;
+        encoder.process_event(GifEvent::WriteImageChunk { data: vec![0, 3].into() })?;
+        encoder.process_event(GifEvent::EndFrame)?;
+        encoder.process_event(GifEvent::EndGif)?;
+
+        crate::validate::assert_valid(encoder.get_encoded_data());
+        let gif = decode(encoder.get_encoded_data())?;
+        assert_eq!(gif.global_palette.as_deref(), Some(global.padded_colors().as_slice()));
+        assert_eq!(gif.frames[0].local_palette.as_deref(), Some(local.colors()));
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+// MIT License
+// Copyright (c) 2025 Gianluca Cannata <gcannata23@gmail.com>
+//
+// av-gif - A GIF encoder written in Rust
+use rayon::prelude::*;
+
+use crate::encoder::{GifEncoder, GifEvent};
+use crate::frame::{compress_frame, write_compressed_frame, Frame};
+use crate::quantization::QuantizeOptions;
+
+// Quantizes and compresses frames on the rayon thread pool, then writes them to the
+// wrapped encoder in the order they were pushed. At most `max_in_flight` frames are
+// held at once, which bounds memory use for long animations.
+pub struct ParallelEncoder<E> {
+    encoder: E,
+    options: QuantizeOptions,
+    max_in_flight: usize,
+    pending: Vec<Frame<'static>>,
+    frames_pushed: usize, // Including the pending ones, to number frames in errors
+}
+
+impl<E: GifEncoder> ParallelEncoder<E> {
+    pub fn new(encoder: E, options: QuantizeOptions, max_in_flight: usize) -> Self {
+        let max_in_flight = max_in_flight.max(1);
+
+        ParallelEncoder {
+            encoder,
+            options,
+            max_in_flight,
+            pending: Vec::with_capacity(max_in_flight),
+            frames_pushed: 0,
+        }
+    }
+
+    // Queue a frame, compressing the queued batch once it's full
+    pub fn push_frame(&mut self, frame: Frame<'static>) -> Result<(), String> {
+        self.pending.push(frame);
+        self.frames_pushed += 1;
+
+        if self.pending.len() >= self.max_in_flight {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    // Compress and write every queued frame. Frames are written in order up to the first
+    // one that fails; it and the rest of the batch are dropped, and the error says which.
+    pub fn flush(&mut self) -> Result<(), String> {
+        let frames = std::mem::take(&mut self.pending);
+        let first_number = self.frames_pushed - frames.len();
+
+        let compressed: Vec<_> = frames
+            .par_iter()
+            .map(|frame| compress_frame(frame, &self.options))
+            .collect();
+
+        for (position, frame) in compressed.into_iter().enumerate() {
+            if let Err(err) = frame.and_then(|frame| write_compressed_frame(&mut self.encoder, &frame)) {
+                let number = first_number + position;
+                return Err(match frames.len() - position - 1 {
+                    0 => format!("Frame {}: {}", number, err),
+                    dropped => format!("Frame {}: {} (frames {} to {} were dropped too)", number, err, number + 1, number + dropped),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Non-frame events (StartGif, Comment, EndGif) go through in order with queued frames
+    pub fn process_event(&mut self, event: GifEvent) -> Result<(), String> {
+        self.flush()?;
+        self.encoder.process_event(event)
+    }
+
+    pub fn get_ref(&self) -> &E {
+        &self.encoder
+    }
+
+    pub fn into_inner(mut self) -> Result<E, String> {
+        self.flush()?;
+        Ok(self.encoder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::{GifEncoderState, LoopConfig, ScreenOptions};
+    use crate::frame::write_frame;
+
+    fn frames() -> Vec<Frame<'static>> {
+        (0..7u32)
+            .map(|n| {
+                let pixels: Vec<u8> = (0..16 * 9u32).flat_map(|i| [(i * n) as u8, (i + n * 30) as u8, (n * 20) as u8]).collect();
+                Frame::rgb(16, 9, pixels).with_delay(n as u16 + 1)
+            })
+            .collect()
+    }
+
+    fn start_gif() -> GifEvent<'static> {
+        GifEvent::StartGif { width: 16, height: 9, global_palette: None, background_color_index: 0, looping: LoopConfig::infinite(), version: None, screen: ScreenOptions::default() }
+    }
+
+    #[test]
+    fn test_matches_serial_output() -> Result<(), String> {
+        let options = QuantizeOptions { max_colors: 32, ..Default::default() };
+
+        let mut serial = GifEncoderState::new();
+        serial.process_event(start_gif())?;
+        for frame in frames() {
+            write_frame(&mut serial, &frame, &options)?;
+        }
+        serial.process_event(GifEvent::EndGif)?;
+
+        for max_in_flight in [1, 3, 16] {
+            let mut parallel = ParallelEncoder::new(GifEncoderState::new(), options.clone(), max_in_flight);
+            parallel.process_event(start_gif())?;
+            for frame in frames() {
+                parallel.push_frame(frame)?;
+            }
+            parallel.process_event(GifEvent::EndGif)?;
+
+            let parallel = parallel.into_inner()?;
+            assert_eq!(parallel.get_encoded_data(), serial.get_encoded_data(), "max_in_flight {}", max_in_flight);
+        }
+        Ok(())
+    }
+
+    // Counts the frames that reach it
+    #[derive(Default)]
+    struct FrameCounter {
+        frames: usize,
+    }
+
+    impl GifEncoder for FrameCounter {
+        fn process_event<'a>(&mut self, event: GifEvent<'a>) -> Result<(), String> {
+            self.frames += matches!(event, GifEvent::EndFrame) as usize;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_in_flight_bound() -> Result<(), String> {
+        let mut parallel = ParallelEncoder::new(FrameCounter::default(), QuantizeOptions::default(), 2);
+        parallel.process_event(start_gif())?;
+
+        // Frames reach the encoder in pairs
+        for (pushed, frame) in (1..).zip(frames()) {
+            parallel.push_frame(frame)?;
+            assert_eq!(parallel.get_ref().frames, pushed - pushed % 2);
+        }
+        assert_eq!(parallel.into_inner()?.frames, frames().len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_frame_names_dropped_ones() -> Result<(), String> {
+        let mut parallel = ParallelEncoder::new(FrameCounter::default(), QuantizeOptions::default(), 4);
+        let mut batch = frames();
+        batch[1] = Frame::rgb(16, 9, vec![0; 5]);
+        for frame in batch.drain(..3) {
+            parallel.push_frame(frame)?;
+        }
+
+        assert_eq!(
+            parallel.push_frame(batch.remove(0)),
+            Err("Frame 1: Frame of 16x9 needs 432 bytes of pixel data, got 5 (frames 2 to 3 were dropped too)".to_string())
+        );
+        assert_eq!(parallel.get_ref().frames, 1);
+
+        // Later batches are numbered on from the dropped frames
+        parallel.push_frame(Frame::rgb(16, 9, vec![0; 5]))?;
+        assert_eq!(parallel.into_inner().err(), Some("Frame 4: Frame of 16x9 needs 432 bytes of pixel data, got 5".to_string()));
+        Ok(())
+    }
+}
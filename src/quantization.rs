@@ -1 +1,1012 @@
 // median cut, octree, neuquant
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::palette::Palette;
+
+// How RGBA pixels are reduced to GIF's 1-bit transparency
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlphaMode {
+    Threshold,      // Alpha below the threshold is transparent, the rest is opaque as is
+    Matte([u8; 3]), // Pixels at or above the threshold are blended over this color first
+    OrderedDither,  // 8x8 Bayer pattern turns partial alpha into a mix of opaque and transparent
+}
+
+// Algorithm used to pick the palette colors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quantizer {
+    MedianCut, // Split the color box with the widest range at its weighted median
+    Octree,    // Merge the least used leaves of an RGB octree
+}
+
+// How pixels are mapped to their palette colors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dither {
+    None,           // Nearest palette color
+    FloydSteinberg, // Nearest palette color, spreading the error over neighboring pixels
+}
+
+// Space colors are compared and averaged in, for both palette building and mapping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    Srgb,      // Gamma-encoded RGB as stored, plain Euclidean distance
+    LinearRgb, // RGB with the sRGB curve removed, so averages mix light like the eye sees it
+    Lab,       // CIELAB (D65), roughly perceptually uniform
+    Oklab,     // Björn Ottosson's Oklab, more uniform than CIELAB in blues and dark tones
+}
+
+impl ColorSpace {
+    // Coordinates of an sRGB color in this space
+    pub fn from_srgb(self, color: [u8; 3]) -> [f32; 3] {
+        if self == ColorSpace::Srgb {
+            return color.map(|value| value as f32);
+        }
+
+        let [r, g, b] = color.map(|value| srgb_to_linear(value as f32 / 255.0));
+        match self {
+            ColorSpace::Srgb | ColorSpace::LinearRgb => [r, g, b],
+            ColorSpace::Lab => {
+                let f = |t: f32| match t > LAB_EPSILON {
+                    true => t.cbrt(),
+                    false => (LAB_KAPPA * t + 16.0) / 116.0,
+                };
+                let [x, y, z] = multiply(&RGB_TO_XYZ, [r, g, b]);
+                let (fx, fy, fz) = (f(x / D65[0]), f(y / D65[1]), f(z / D65[2]));
+                [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+            }
+            ColorSpace::Oklab => multiply(&LMS_TO_OKLAB, multiply(&RGB_TO_LMS, [r, g, b]).map(f32::cbrt)),
+        }
+    }
+
+    // Nearest sRGB color to coordinates in this space
+    pub fn to_srgb(self, coordinates: [f32; 3]) -> [u8; 3] {
+        let linear = match self {
+            ColorSpace::Srgb => return coordinates.map(|value| value.round().clamp(0.0, 255.0) as u8),
+            ColorSpace::LinearRgb => coordinates,
+            ColorSpace::Lab => {
+                let [l, a, b] = coordinates;
+                let fy = (l + 16.0) / 116.0;
+                let f_inverse = |f: f32| match f * f * f > LAB_EPSILON {
+                    true => f * f * f,
+                    false => (116.0 * f - 16.0) / LAB_KAPPA,
+                };
+                let xyz = [f_inverse(fy + a / 500.0) * D65[0], f_inverse(fy) * D65[1], f_inverse(fy - b / 200.0) * D65[2]];
+                multiply(&XYZ_TO_RGB, xyz)
+            }
+            ColorSpace::Oklab => multiply(&LMS_TO_RGB, multiply(&OKLAB_TO_LMS, coordinates).map(|value| value * value * value)),
+        };
+
+        linear.map(|value| (linear_to_srgb(value.clamp(0.0, 1.0)) * 255.0).round() as u8)
+    }
+
+    // Coordinates scaled to roughly 0..=255 per axis, for the octree to split on
+    fn octree_key(self, coordinates: [f32; 3]) -> [u8; 3] {
+        let [x, y, z] = coordinates;
+        let scaled = match self {
+            ColorSpace::Srgb => [x, y, z],
+            ColorSpace::LinearRgb => [x * 255.0, y * 255.0, z * 255.0],
+            ColorSpace::Lab => [x * 2.55, y + 128.0, z + 128.0],
+            ColorSpace::Oklab => [x * 255.0, (y + 0.5) * 255.0, (z + 0.5) * 255.0],
+        };
+        scaled.map(|value| value.round().clamp(0.0, 255.0) as u8)
+    }
+}
+
+const LAB_EPSILON: f32 = 216.0 / 24389.0;
+const LAB_KAPPA: f32 = 24389.0 / 27.0;
+const D65: [f32; 3] = [0.95047, 1.0, 1.08883];
+
+const RGB_TO_XYZ: [[f32; 3]; 3] = [
+    [0.4124564, 0.3575761, 0.1804375],
+    [0.2126729, 0.7151522, 0.072175],
+    [0.0193339, 0.119192, 0.9503041],
+];
+const XYZ_TO_RGB: [[f32; 3]; 3] = [
+    [3.2404542, -1.5371385, -0.4985314],
+    [-0.969266, 1.8760108, 0.041556],
+    [0.0556434, -0.2040259, 1.0572252],
+];
+const RGB_TO_LMS: [[f32; 3]; 3] = [
+    [0.41222147, 0.53633254, 0.051445993],
+    [0.2119035, 0.6806995, 0.10739696],
+    [0.08830246, 0.28171884, 0.6299787],
+];
+const LMS_TO_OKLAB: [[f32; 3]; 3] = [
+    [0.21045426, 0.7936178, -0.004072047],
+    [1.9779985, -2.4285922, 0.4505937],
+    [0.025904037, 0.78277177, -0.80867577],
+];
+const OKLAB_TO_LMS: [[f32; 3]; 3] = [
+    [1.0, 0.39633778, 0.21580376],
+    [1.0, -0.105561346, -0.06385417],
+    [1.0, -0.08948418, -1.2914855],
+];
+const LMS_TO_RGB: [[f32; 3]; 3] = [
+    [4.0767417, -3.3077116, 0.23096993],
+    [-1.268438, 2.6097574, -0.3413194],
+    [-0.0041960863, -0.7034186, 1.7076147],
+];
+
+fn multiply(matrix: &[[f32; 3]; 3], vector: [f32; 3]) -> [f32; 3] {
+    matrix.map(|row| row[0] * vector[0] + row[1] * vector[1] + row[2] * vector[2])
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    match value <= 0.04045 {
+        true => value / 12.92,
+        false => ((value + 0.055) / 1.055).powf(2.4),
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    match value <= 0.0031308 {
+        true => value * 12.92,
+        false => 1.055 * value.powf(1.0 / 2.4) - 0.055,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantizeOptions {
+    pub max_colors: usize,       // Palette size limit (1..=256), including the transparent slot
+    pub sort_by_frequency: bool, // Order palette entries by decreasing pixel count
+    pub alpha_threshold: u8,     // RGBA pixels with alpha below this become transparent
+    pub alpha_mode: AlphaMode,
+    pub quantizer: Quantizer,
+    pub dither: Dither,
+    pub color_space: ColorSpace,
+}
+
+impl Default for QuantizeOptions {
+    fn default() -> Self {
+        QuantizeOptions {
+            max_colors: 256,
+            sort_by_frequency: false,
+            alpha_threshold: 128,
+            alpha_mode: AlphaMode::Threshold,
+            quantizer: Quantizer::MedianCut,
+            dither: Dither::None,
+            color_space: ColorSpace::Srgb,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantizedImage {
+    pub palette: Vec<[u8; 3]>,
+    pub indices: Vec<u8>, // One palette index per pixel
+    pub sorted: bool,     // Palette is ordered by decreasing frequency (the GIF sort flag)
+    pub transparent_index: Option<u8>, // Reserved palette slot for transparent pixels
+}
+
+fn check_rgb(pixels: &[u8]) -> Result<(), String> {
+    if !pixels.len().is_multiple_of(3) {
+        return Err("RGB data length must be a multiple of 3".to_string());
+    }
+    Ok(())
+}
+
+fn check_rgba(pixels: &[u8]) -> Result<(), String> {
+    if !pixels.len().is_multiple_of(4) {
+        return Err("RGBA data length must be a multiple of 4".to_string());
+    }
+    Ok(())
+}
+
+fn check_max_colors(max_colors: usize) -> Result<(), String> {
+    if !(1..=256).contains(&max_colors) {
+        return Err("Palette size must be between 1 and 256 colors".to_string());
+    }
+    Ok(())
+}
+
+// Reduce packed RGB pixels, `width` pixels wide, to an indexed image with at most
+// `max_colors` colors
+pub fn quantize(pixels: &[u8], width: usize, options: &QuantizeOptions) -> Result<QuantizedImage, String> {
+    check_rgb(pixels)?;
+    check_max_colors(options.max_colors)?;
+
+    let colors = rgb_colors(pixels);
+    quantize_colors(&colors, None, width, options.max_colors, options)
+}
+
+// Like `quantize`, for packed RGBA pixels. Transparent pixels (see `AlphaMode`) share
+// one palette slot, and only opaque pixels are used to build the palette.
+pub fn quantize_rgba(
+    pixels: &[u8],
+    width: usize,
+    options: &QuantizeOptions,
+) -> Result<QuantizedImage, String> {
+    check_rgba(pixels)?;
+    check_max_colors(options.max_colors)?;
+
+    let (colors, mask) = resolve_alpha(pixels, width, options);
+
+    if mask.iter().all(|&is_opaque| is_opaque) {
+        return quantize_colors(&colors, None, width, options.max_colors, options);
+    }
+
+    if mask.iter().any(|&is_opaque| is_opaque) && options.max_colors < 2 {
+        return Err("Transparency needs room for at least 2 palette colors".to_string());
+    }
+
+    // Leave room for the transparent slot
+    quantize_colors(&colors, Some(&mask), width, options.max_colors - 1, options)
+}
+
+// Palette of at most `max_colors` colors for packed RGB pixels, e.g. the pixels of
+// every frame when building a global palette
+pub fn build_palette(pixels: &[u8], options: &QuantizeOptions) -> Result<Vec<[u8; 3]>, String> {
+    check_rgb(pixels)?;
+    check_max_colors(options.max_colors)?;
+
+    let histogram = build_histogram(&rgb_colors(pixels));
+    Ok(reduce_colors(histogram, options.max_colors, options))
+}
+
+// Packed RGB of the pixels that stay opaque under the alpha mode, to build a palette from
+pub fn opaque_pixels(pixels: &[u8], width: usize, options: &QuantizeOptions) -> Result<Vec<u8>, String> {
+    check_rgba(pixels)?;
+
+    let (colors, mask) = resolve_alpha(pixels, width, options);
+    Ok(colors
+        .iter()
+        .zip(&mask)
+        .filter(|&(_, &is_opaque)| is_opaque)
+        .flat_map(|(color, _)| *color)
+        .collect())
+}
+
+// Map packed RGB pixels to an existing palette, dithering as configured
+pub fn remap_rgb(pixels: &[u8], width: usize, palette: &Palette, options: &QuantizeOptions) -> Result<Vec<u8>, String> {
+    check_rgb(pixels)?;
+
+    Ok(map_colors(&rgb_colors(pixels), None, width, palette, options))
+}
+
+// Map packed RGBA pixels to an existing palette. Transparent pixels get the palette's
+// transparent slot, which opaque pixels never map to.
+pub fn remap_rgba(pixels: &[u8], width: usize, palette: &Palette, options: &QuantizeOptions) -> Result<Vec<u8>, String> {
+    check_rgba(pixels)?;
+
+    let (colors, mask) = resolve_alpha(pixels, width, options);
+    if palette.transparent_index().is_none() && mask.iter().any(|&is_opaque| !is_opaque) {
+        return Err("Image has transparent pixels but the palette has no transparent slot".to_string());
+    }
+
+    Ok(map_colors(&colors, Some(&mask), width, palette, options))
+}
+
+// 8x8 Bayer matrix, values 0..64
+const BAYER_8X8: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+// Color of every pixel after the alpha mode, and whether it stays opaque
+fn resolve_alpha(pixels: &[u8], width: usize, options: &QuantizeOptions) -> (Vec<[u8; 3]>, Vec<bool>) {
+    let width = width.max(1);
+    let mut colors = Vec::with_capacity(pixels.len() / 4);
+    let mut mask = Vec::with_capacity(pixels.len() / 4);
+
+    for (i, pixel) in pixels.chunks_exact(4).enumerate() {
+        let alpha = pixel[3];
+        let color = [pixel[0], pixel[1], pixel[2]];
+
+        let (is_opaque, color) = match options.alpha_mode {
+            AlphaMode::Threshold => (alpha >= options.alpha_threshold, color),
+            AlphaMode::Matte(matte) => (
+                alpha >= options.alpha_threshold,
+                composite(color, alpha, matte),
+            ),
+            AlphaMode::OrderedDither => {
+                // Thresholds spread evenly between 0 and 255, so 0 is never and 255 always opaque
+                let (x, y) = (i % width, i / width);
+                let threshold = (BAYER_8X8[y % 8][x % 8] as u32 * 2 + 1) * 255 / 128;
+                (alpha as u32 >= threshold, color)
+            }
+        };
+
+        colors.push(color);
+        mask.push(is_opaque);
+    }
+
+    (colors, mask)
+}
+
+fn rgb_colors(pixels: &[u8]) -> Vec<[u8; 3]> {
+    pixels
+        .chunks_exact(3)
+        .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect()
+}
+
+// Blend a color with the given alpha over an opaque matte color
+pub fn composite(color: [u8; 3], alpha: u8, matte: [u8; 3]) -> [u8; 3] {
+    let alpha = alpha as u32;
+    let blend = |foreground: u8, background: u8| {
+        ((foreground as u32 * alpha + background as u32 * (255 - alpha) + 127) / 255) as u8
+    };
+
+    [
+        blend(color[0], matte[0]),
+        blend(color[1], matte[1]),
+        blend(color[2], matte[2]),
+    ]
+}
+
+// Build a palette from the opaque colors and map every pixel to it. When there's a
+// mask, the transparent slot is appended last so sorting doesn't move it.
+fn quantize_colors(
+    colors: &[[u8; 3]],
+    mask: Option<&[bool]>,
+    width: usize,
+    max_colors: usize,
+    options: &QuantizeOptions,
+) -> Result<QuantizedImage, String> {
+    let opaque: Vec<[u8; 3]> = match mask {
+        Some(mask) => colors
+            .iter()
+            .zip(mask)
+            .filter(|&(_, &is_opaque)| is_opaque)
+            .map(|(&color, _)| color)
+            .collect(),
+        None => colors.to_vec(),
+    };
+
+    let mut palette = reduce_colors(build_histogram(&opaque), max_colors, options);
+    let mut lookup = Palette::new(palette.clone())?.with_color_space(options.color_space);
+    if mask.is_some() {
+        lookup = lookup.with_transparent_slot()?;
+    }
+    let transparent_index = lookup.transparent_index();
+    let mut indices = map_colors(colors, mask, width, &lookup, options);
+
+    let sorted = options.sort_by_frequency;
+    if sorted {
+        sort_by_frequency(&mut palette, &mut indices);
+    }
+
+    if transparent_index.is_some() {
+        palette.push([0, 0, 0]);
+    }
+
+    Ok(QuantizedImage {
+        palette,
+        indices,
+        sorted,
+        transparent_index,
+    })
+}
+
+fn reduce_colors(histogram: Vec<([u8; 3], u32)>, max_colors: usize, options: &QuantizeOptions) -> Vec<[u8; 3]> {
+    if histogram.len() <= max_colors {
+        // Few enough colors, keep them exactly
+        return histogram.iter().map(|&(color, _)| color).collect();
+    }
+
+    let space = options.color_space;
+    let points = histogram
+        .iter()
+        .map(|&(color, count)| (space.from_srgb(color), count))
+        .collect();
+    match options.quantizer {
+        Quantizer::MedianCut => median_cut(points, max_colors, space),
+        Quantizer::Octree => octree(&points, max_colors, space),
+    }
+}
+
+// Unique colors with their pixel counts, in order of first appearance
+fn build_histogram(colors: &[[u8; 3]]) -> Vec<([u8; 3], u32)> {
+    let mut positions: HashMap<[u8; 3], usize> = HashMap::new();
+    let mut histogram = Vec::new();
+
+    for &color in colors {
+        let position = *positions.entry(color).or_insert_with(|| {
+            histogram.push((color, 0));
+            histogram.len() - 1
+        });
+        histogram[position].1 += 1;
+    }
+
+    histogram
+}
+
+fn median_cut(points: Vec<([f32; 3], u32)>, max_colors: usize, space: ColorSpace) -> Vec<[u8; 3]> {
+    let mut boxes = vec![points];
+
+    while boxes.len() < max_colors {
+        // Split the box with the widest channel range
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, points)| points.len() > 1)
+            .map(|(i, points)| (i, widest_channel(points)))
+            .max_by(|(_, (_, a)), (_, (_, b))| a.total_cmp(b));
+
+        let Some((index, (channel, _))) = widest else {
+            break;
+        };
+
+        let mut points = boxes.swap_remove(index);
+        points.sort_by(|(a, _), (b, _)| a[channel].total_cmp(&b[channel]));
+
+        // Split at the weighted median so both halves cover a similar number of pixels
+        let total: u64 = points.iter().map(|&(_, count)| count as u64).sum();
+        let mut seen = 0u64;
+        let mut split = 1;
+        for (i, &(_, count)) in points.iter().enumerate() {
+            seen += count as u64;
+            if seen * 2 >= total {
+                split = (i + 1).clamp(1, points.len() - 1);
+                break;
+            }
+        }
+
+        let upper = points.split_off(split);
+        boxes.push(points);
+        boxes.push(upper);
+    }
+
+    boxes.iter().map(|points| space.to_srgb(average_point(points))).collect()
+}
+
+fn widest_channel(points: &[([f32; 3], u32)]) -> (usize, f32) {
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+
+    for &(point, _) in points {
+        for channel in 0..3 {
+            min[channel] = min[channel].min(point[channel]);
+            max[channel] = max[channel].max(point[channel]);
+        }
+    }
+
+    (0..3)
+        .map(|channel| (channel, max[channel] - min[channel]))
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .unwrap_or((0, 0.0))
+}
+
+fn average_point(points: &[([f32; 3], u32)]) -> [f32; 3] {
+    let mut sum = [0f64; 3];
+    let mut total = 0u64;
+
+    for &(point, count) in points {
+        for channel in 0..3 {
+            sum[channel] += point[channel] as f64 * count as f64;
+        }
+        total += count as u64;
+    }
+
+    let total = total.max(1) as f64;
+    sum.map(|value| (value / total) as f32)
+}
+
+#[derive(Default)]
+struct OctreeNode {
+    children: [Option<usize>; 8],
+    sum: [f64; 3], // Coordinates weighted by pixel count
+    count: u64, // Pixels in this node's subtree
+    is_leaf: bool,
+}
+
+// Colors are placed by their coordinates in `space`, scaled to bytes
+fn octree(points: &[([f32; 3], u32)], max_colors: usize, space: ColorSpace) -> Vec<[u8; 3]> {
+    let mut nodes = vec![OctreeNode::default()];
+    // Nodes with children, by depth
+    let mut levels: Vec<Vec<usize>> = vec![Vec::new(); 8];
+    let mut leaves = 0;
+
+    for &(point, count) in points {
+        let color = space.octree_key(point);
+        let mut node = 0;
+        nodes[node].count += count as u64;
+
+        for (level, parents) in levels.iter_mut().enumerate() {
+            let shift = 7 - level;
+            let child = (((color[0] >> shift) & 1) << 2 | ((color[1] >> shift) & 1) << 1 | ((color[2] >> shift) & 1)) as usize;
+
+            node = match nodes[node].children[child] {
+                Some(existing) => existing,
+                None => {
+                    if nodes[node].children.iter().all(Option::is_none) {
+                        parents.push(node);
+                    }
+                    nodes.push(OctreeNode::default());
+                    let created = nodes.len() - 1;
+                    nodes[node].children[child] = Some(created);
+                    created
+                }
+            };
+            nodes[node].count += count as u64;
+        }
+
+        let leaf = &mut nodes[node];
+        for (sum, value) in leaf.sum.iter_mut().zip(point) {
+            *sum += value as f64 * count as f64;
+        }
+        // Colors with the same key share a leaf
+        if !leaf.is_leaf {
+            leaf.is_leaf = true;
+            leaves += 1;
+        }
+    }
+
+    // Fold the least used deepest nodes into their parents until the palette fits
+    for level in (0..8).rev() {
+        let mut reducible = std::mem::take(&mut levels[level]);
+        reducible.sort_by_key(|&node| std::cmp::Reverse(nodes[node].count));
+
+        while leaves > max_colors {
+            let Some(node) = reducible.pop() else {
+                break;
+            };
+
+            let children = std::mem::take(&mut nodes[node].children);
+            let mut sum = [0f64; 3];
+            for child in children.into_iter().flatten() {
+                for (sum, child_sum) in sum.iter_mut().zip(nodes[child].sum) {
+                    *sum += child_sum;
+                }
+                leaves -= 1;
+            }
+            nodes[node].sum = sum;
+            nodes[node].is_leaf = true;
+            leaves += 1;
+        }
+    }
+
+    let mut palette = Vec::with_capacity(leaves);
+    let mut stack = vec![0];
+    while let Some(node) = stack.pop() {
+        let node = &nodes[node];
+        if node.is_leaf {
+            let count = node.count.max(1) as f64;
+            palette.push(space.to_srgb(node.sum.map(|sum| (sum / count) as f32)));
+        } else {
+            stack.extend(node.children.iter().rev().flatten());
+        }
+    }
+
+    palette
+}
+
+// Map every RGB pixel to the index of its nearest palette color
+pub fn remap(pixels: &[u8], palette: &Palette) -> Vec<u8> {
+    let mut cache: HashMap<[u8; 3], u8> = HashMap::new();
+
+    pixels
+        .chunks_exact(3)
+        .map(|pixel| {
+            let color = [pixel[0], pixel[1], pixel[2]];
+            *cache.entry(color).or_insert_with(|| palette.nearest(color))
+        })
+        .collect()
+}
+
+// Palette index of every pixel, comparing colors in the options' color space. Pixels
+// masked out get the palette's transparent slot, which is otherwise never picked.
+fn map_colors(colors: &[[u8; 3]], mask: Option<&[bool]>, width: usize, palette: &Palette, options: &QuantizeOptions) -> Vec<u8> {
+    let palette = match palette.color_space() == options.color_space {
+        true => Cow::Borrowed(palette),
+        false => Cow::Owned(palette.clone().with_color_space(options.color_space)),
+    };
+    let is_opaque = |i: usize| mask.is_none_or(|mask| mask[i]);
+    let transparent = palette.transparent_index().unwrap_or(0);
+
+    match options.dither {
+        Dither::None => {
+            let mut cache: HashMap<[u8; 3], u8> = HashMap::new();
+
+            colors
+                .iter()
+                .enumerate()
+                .map(|(i, &color)| match is_opaque(i) {
+                    true => *cache.entry(color).or_insert_with(|| palette.nearest(color)),
+                    false => transparent,
+                })
+                .collect()
+        }
+        Dither::FloydSteinberg => {
+            let width = width.max(1);
+            // Accumulated error in 16ths for this row and the next, offset by one column
+            let mut current = vec![[0i32; 3]; width + 2];
+            let mut next = vec![[0i32; 3]; width + 2];
+            let mut indices = Vec::with_capacity(colors.len());
+
+            for (i, &color) in colors.iter().enumerate() {
+                let x = i % width;
+                if x == 0 && i > 0 {
+                    std::mem::swap(&mut current, &mut next);
+                    next.fill([0; 3]);
+                }
+
+                if !is_opaque(i) {
+                    indices.push(transparent);
+                    continue;
+                }
+
+                let mut wanted = [0i32; 3];
+                for channel in 0..3 {
+                    wanted[channel] = (color[channel] as i32 + current[x + 1][channel] / 16).clamp(0, 255);
+                }
+
+                let index = palette.nearest(wanted.map(|value| value as u8));
+                let chosen = palette.colors().get(index as usize).copied().unwrap_or([0, 0, 0]);
+                indices.push(index);
+
+                for channel in 0..3 {
+                    let error = wanted[channel] - chosen[channel] as i32;
+                    current[x + 2][channel] += error * 7;
+                    next[x][channel] += error * 3;
+                    next[x + 1][channel] += error * 5;
+                    next[x + 2][channel] += error;
+                }
+            }
+
+            indices
+        }
+    }
+}
+
+// Reorder palette entries by decreasing pixel count and rewrite the indices to match
+pub fn sort_by_frequency(palette: &mut Vec<[u8; 3]>, indices: &mut [u8]) {
+    let mut counts = vec![0u32; palette.len()];
+    for &index in indices.iter() {
+        if let Some(count) = counts.get_mut(index as usize) {
+            *count += 1;
+        }
+    }
+
+    // Stable sort keeps ties in their original order
+    let mut order: Vec<usize> = (0..palette.len()).collect();
+    order.sort_by_key(|&index| std::cmp::Reverse(counts[index]));
+
+    let mut new_index = vec![0u8; palette.len()];
+    for (position, &old) in order.iter().enumerate() {
+        new_index[old] = position as u8;
+    }
+
+    *palette = order.iter().map(|&old| palette[old]).collect();
+
+    for index in indices.iter_mut() {
+        if let Some(&mapped) = new_index.get(*index as usize) {
+            *index = mapped;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: usize) -> Vec<u8> {
+        let mut pixels = Vec::new();
+        for x in 0..width {
+            pixels.extend_from_slice(&[x as u8, (x * 2) as u8, 255 - x as u8]);
+        }
+        pixels
+    }
+
+    #[test]
+    fn test_exact_palette_when_few_colors() -> Result<(), String> {
+        let pixels = [255, 0, 0, 0, 0, 255, 255, 0, 0];
+        let image = quantize(&pixels, 3, &QuantizeOptions::default())?;
+
+        assert_eq!(image.palette, vec![[255, 0, 0], [0, 0, 255]]);
+        assert_eq!(image.indices, vec![0, 1, 0]);
+        assert!(!image.sorted);
+        Ok(())
+    }
+
+    #[test]
+    fn test_median_cut_limits_palette() -> Result<(), String> {
+        let pixels = gradient(200);
+        let options = QuantizeOptions {
+            max_colors: 16,
+            ..Default::default()
+        };
+        let image = quantize(&pixels, 200, &options)?;
+
+        assert_eq!(image.palette.len(), 16);
+        assert_eq!(image.indices.len(), 200);
+        assert!(image.indices.iter().all(|&index| (index as usize) < 16));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        assert!(quantize(&[0, 0], 1, &QuantizeOptions::default()).is_err());
+
+        let options = QuantizeOptions {
+            max_colors: 0,
+            ..Default::default()
+        };
+        assert!(quantize(&[0, 0, 0], 1, &options).is_err());
+    }
+
+    #[test]
+    fn test_sort_by_frequency() -> Result<(), String> {
+        let mut pixels = Vec::new();
+        pixels.extend_from_slice(&[1, 1, 1]);
+        for _ in 0..3 {
+            pixels.extend_from_slice(&[2, 2, 2]);
+        }
+        for _ in 0..2 {
+            pixels.extend_from_slice(&[3, 3, 3]);
+        }
+
+        let options = QuantizeOptions {
+            sort_by_frequency: true,
+            ..Default::default()
+        };
+        let image = quantize(&pixels, 6, &options)?;
+
+        assert!(image.sorted);
+        assert_eq!(image.palette, vec![[2, 2, 2], [3, 3, 3], [1, 1, 1]]);
+        assert_eq!(image.indices, vec![2, 0, 0, 0, 1, 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_preserves_pixels() {
+        let mut palette = vec![[10, 0, 0], [20, 0, 0], [30, 0, 0], [40, 0, 0]];
+        let mut indices = vec![3, 3, 1, 3, 0, 1];
+        let before: Vec<[u8; 3]> = indices.iter().map(|&i| palette[i as usize]).collect();
+
+        sort_by_frequency(&mut palette, &mut indices);
+
+        let after: Vec<[u8; 3]> = indices.iter().map(|&i| palette[i as usize]).collect();
+        assert_eq!(before, after);
+        assert_eq!(palette[0], [40, 0, 0]);
+        assert_eq!(palette[3], [30, 0, 0]);
+    }
+
+    #[test]
+    fn test_rgba_transparent_slot() -> Result<(), String> {
+        let pixels = [
+            255, 0, 0, 255, // opaque red
+            0, 255, 0, 10, // transparent
+            0, 0, 255, 200, // opaque blue
+            255, 0, 0, 127, // below the default threshold
+        ];
+        let image = quantize_rgba(&pixels, 4, &QuantizeOptions::default())?;
+
+        assert_eq!(image.palette, vec![[255, 0, 0], [0, 0, 255], [0, 0, 0]]);
+        assert_eq!(image.transparent_index, Some(2));
+        assert_eq!(image.indices, vec![0, 2, 1, 2]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rgba_palette_from_opaque_pixels_only() -> Result<(), String> {
+        let mut pixels = Vec::new();
+        for x in 0..100u8 {
+            pixels.extend_from_slice(&[x, x, x, 255]);
+            pixels.extend_from_slice(&[255, 0, x, 0]);
+        }
+
+        let options = QuantizeOptions {
+            max_colors: 8,
+            sort_by_frequency: true,
+            ..Default::default()
+        };
+        let image = quantize_rgba(&pixels, 20, &options)?;
+
+        // 7 opaque colors plus the transparent slot, which stays last after sorting
+        assert_eq!(image.palette.len(), 8);
+        assert_eq!(image.transparent_index, Some(7));
+        assert!(image.palette[..7].iter().all(|color| color[0] == color[1] && color[1] == color[2]));
+        assert!(image.indices.iter().skip(1).step_by(2).all(|&index| index == 7));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rgba_without_transparency() -> Result<(), String> {
+        let options = QuantizeOptions {
+            alpha_threshold: 0,
+            ..Default::default()
+        };
+        let image = quantize_rgba(&[1, 2, 3, 0, 4, 5, 6, 255], 2, &options)?;
+
+        assert_eq!(image.transparent_index, None);
+        assert_eq!(image.palette, vec![[1, 2, 3], [4, 5, 6]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rgba_fully_transparent() -> Result<(), String> {
+        let image = quantize_rgba(&[1, 2, 3, 0, 4, 5, 6, 0], 2, &QuantizeOptions::default())?;
+
+        assert_eq!(image.palette, vec![[0, 0, 0]]);
+        assert_eq!(image.transparent_index, Some(0));
+        assert_eq!(image.indices, vec![0, 0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_matte_compositing() -> Result<(), String> {
+        let pixels = [
+            255, 255, 255, 255, // opaque white
+            255, 255, 255, 128, // half transparent white edge
+            255, 255, 255, 0, // fully transparent
+        ];
+        let options = QuantizeOptions {
+            alpha_threshold: 1,
+            alpha_mode: AlphaMode::Matte([0, 0, 0]),
+            ..Default::default()
+        };
+        let image = quantize_rgba(&pixels, 3, &options)?;
+
+        assert_eq!(image.palette, vec![[255, 255, 255], [128, 128, 128], [0, 0, 0]]);
+        assert_eq!(image.indices, vec![0, 1, 2]);
+        assert_eq!(image.transparent_index, Some(2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_composite() {
+        assert_eq!(composite([200, 100, 0], 255, [0, 0, 255]), [200, 100, 0]);
+        assert_eq!(composite([200, 100, 0], 0, [0, 0, 255]), [0, 0, 255]);
+        assert_eq!(composite([255, 0, 0], 51, [0, 0, 0]), [51, 0, 0]);
+    }
+
+    #[test]
+    fn test_ordered_alpha_dither() -> Result<(), String> {
+        let options = QuantizeOptions {
+            alpha_mode: AlphaMode::OrderedDither,
+            ..Default::default()
+        };
+
+        for (alpha, expected) in [(0u8, 0usize), (64, 16), (128, 32), (192, 48), (255, 64)] {
+            let pixels: Vec<u8> = (0..64).flat_map(|_| [10, 20, 30, alpha]).collect();
+            let image = quantize_rgba(&pixels, 8, &options)?;
+
+            let opaque = image
+                .indices
+                .iter()
+                .filter(|&&index| Some(index) != image.transparent_index)
+                .count();
+            assert_eq!(opaque, expected, "alpha {}", alpha);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_octree_limits_palette() -> Result<(), String> {
+        let pixels = gradient(200);
+        let options = QuantizeOptions {
+            max_colors: 16,
+            quantizer: Quantizer::Octree,
+            ..Default::default()
+        };
+        let image = quantize(&pixels, 200, &options)?;
+
+        assert!(image.palette.len() <= 16 && image.palette.len() > 1);
+        assert!(image.indices.iter().all(|&index| (index as usize) < image.palette.len()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_octree_keeps_distinct_clusters() -> Result<(), String> {
+        let mut pixels = Vec::new();
+        for i in 0..50u8 {
+            pixels.extend_from_slice(&[i % 4, 0, 0]);
+            pixels.extend_from_slice(&[255 - i % 4, 255, 255]);
+        }
+        let options = QuantizeOptions {
+            max_colors: 2,
+            quantizer: Quantizer::Octree,
+            ..Default::default()
+        };
+        let image = quantize(&pixels, 10, &options)?;
+
+        assert_eq!(image.palette.len(), 2);
+        assert_ne!(image.indices[0], image.indices[1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_octree_counts_shared_leaves_once() -> Result<(), String> {
+        // Dark grays are nearly the same light, so the 16 of them fill only two linear leaves
+        let mut pixels: Vec<u8> = (0..16).flat_map(|value| [value; 3]).collect();
+        pixels.extend_from_slice(&[255, 255, 255]);
+        let options = QuantizeOptions {
+            max_colors: 4,
+            quantizer: Quantizer::Octree,
+            color_space: ColorSpace::LinearRgb,
+            ..Default::default()
+        };
+
+        assert_eq!(build_palette(&pixels, &options)?.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_floyd_steinberg_preserves_average() -> Result<(), String> {
+        let pixels: Vec<u8> = (0..32 * 32).flat_map(|_| [64, 64, 64]).collect();
+        let palette = Palette::new(vec![[0, 0, 0], [255, 255, 255]])?;
+
+        let plain = remap_rgb(&pixels, 32, &palette, &QuantizeOptions::default())?;
+        assert!(plain.iter().all(|&index| index == 0));
+
+        let options = QuantizeOptions {
+            dither: Dither::FloydSteinberg,
+            ..Default::default()
+        };
+        let dithered = remap_rgb(&pixels, 32, &palette, &options)?;
+        let white = dithered.iter().filter(|&&index| index == 1).count();
+
+        // A quarter of the pixels turn white to average out at 64
+        assert!((240..=272).contains(&white), "{} white pixels", white);
+        Ok(())
+    }
+
+    #[test]
+    fn test_remap_rgba_skips_transparent_slot() -> Result<(), String> {
+        let palette = Palette::new(vec![[0, 0, 0], [250, 250, 250], [0, 0, 0]])?;
+        let pixels = [1, 1, 1, 255, 9, 9, 9, 0, 240, 240, 240, 255];
+
+        let indices = remap_rgba(&pixels, 3, &palette.clone().with_transparent_index(0)?, &QuantizeOptions::default())?;
+        assert_eq!(indices, vec![2, 0, 1]);
+
+        assert!(remap_rgba(&pixels, 3, &palette, &QuantizeOptions::default()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_global_palette_from_opaque_pixels() -> Result<(), String> {
+        let options = QuantizeOptions::default();
+        let opaque = opaque_pixels(&[1, 2, 3, 255, 4, 5, 6, 0, 7, 8, 9, 255], 3, &options)?;
+        assert_eq!(opaque, vec![1, 2, 3, 7, 8, 9]);
+
+        let palette = build_palette(&opaque, &QuantizeOptions { max_colors: 1, ..options })?;
+        assert_eq!(palette, vec![[4, 5, 6]]);
+        Ok(())
+    }
+
+    const COLOR_SPACES: [ColorSpace; 4] = [ColorSpace::Srgb, ColorSpace::LinearRgb, ColorSpace::Lab, ColorSpace::Oklab];
+
+    #[test]
+    fn test_color_space_round_trip() {
+        for space in COLOR_SPACES {
+            for color in [[0, 0, 0], [255, 255, 255], [255, 0, 0], [12, 200, 99], [1, 2, 3], [240, 180, 150]] {
+                assert_eq!(space.to_srgb(space.from_srgb(color)), color, "{:?}", space);
+            }
+        }
+
+        let [l, a, b] = ColorSpace::Lab.from_srgb([255, 255, 255]);
+        assert!((l - 100.0).abs() < 0.01 && a.abs() < 0.01 && b.abs() < 0.01, "{} {} {}", l, a, b);
+        let [l, a, b] = ColorSpace::Oklab.from_srgb([255, 255, 255]);
+        assert!((l - 1.0).abs() < 0.001 && a.abs() < 0.001 && b.abs() < 0.001, "{} {} {}", l, a, b);
+    }
+
+    #[test]
+    fn test_color_space_remap() -> Result<(), String> {
+        // Gray 125 is closer to black in sRGB and linear light, but looks closer to white
+        let palette = Palette::new(vec![[0, 0, 0], [255, 255, 255]])?;
+        for (space, expected) in COLOR_SPACES.into_iter().zip([0, 0, 1, 1]) {
+            let options = QuantizeOptions { color_space: space, ..Default::default() };
+            assert_eq!(remap_rgb(&[125, 125, 125], 1, &palette, &options)?, vec![expected], "{:?}", space);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_color_space_averages() -> Result<(), String> {
+        let pixels = [0, 0, 0, 255, 255, 255];
+        for quantizer in [Quantizer::MedianCut, Quantizer::Octree] {
+            let options = |color_space| QuantizeOptions { max_colors: 1, quantizer, color_space, ..Default::default() };
+            assert_eq!(build_palette(&pixels, &options(ColorSpace::Srgb))?, vec![[128, 128, 128]], "{:?}", quantizer);
+            // Half the light of white is a much brighter gray once encoded
+            assert_eq!(build_palette(&pixels, &options(ColorSpace::LinearRgb))?, vec![[188, 188, 188]], "{:?}", quantizer);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+// MIT License
+// Copyright (c) 2025 Gianluca Cannata <gcannata23@gmail.com>
+//
+// av-gif - A GIF encoder written in Rust
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::encoder::{GifEncoder, GifEvent};
+
+// Forwards events to another encoder while keeping an owned copy of each one, so a
+// producer's exact event stream can be saved (with the `serde` feature) and replayed.
+pub struct RecordingEncoder<E> {
+    encoder: E,
+    events: Vec<GifEvent<'static>>,
+}
+
+impl<E: GifEncoder> RecordingEncoder<E> {
+    pub fn new(encoder: E) -> Self {
+        RecordingEncoder {
+            encoder,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn events(&self) -> &[GifEvent<'static>] {
+        &self.events
+    }
+
+    pub fn get_ref(&self) -> &E {
+        &self.encoder
+    }
+
+    pub fn into_parts(self) -> (E, Vec<GifEvent<'static>>) {
+        (self.encoder, self.events)
+    }
+}
+
+impl<E: GifEncoder> GifEncoder for RecordingEncoder<E> {
+    // Events are recorded even when rejected, since a bad stream is what needs replaying
+    fn process_event<'a>(&mut self, event: GifEvent<'a>) -> Result<(), String> {
+        let event = event.into_owned();
+        self.events.push(event.clone());
+        self.encoder.process_event(event)
+    }
+}
+
+// Feed recorded events to `encoder` in order, stopping at the first one it rejects
+pub fn replay<'a, E, I>(encoder: &mut E, events: I) -> Result<usize, String>
+where
+    E: GifEncoder + ?Sized,
+    I: IntoIterator<Item = GifEvent<'a>>,
+{
+    let mut count = 0;
+    for event in events {
+        encoder
+            .process_event(event)
+            .map_err(|err| format!("Event {}: {}", count, err))?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::{DisposalMethod, GifEncoderState, LoopConfig, ScreenOptions};
+
+    fn produce<E: GifEncoder>(encoder: &mut E) -> Result<(), String> {
+        let palette = [[0, 0, 0], [255, 0, 0], [0, 255, 0]];
+        let local_palette = [[9, 9, 9], [8, 8, 8]];
+        let pixels = [0u8, 1, 2, 1, 0, 2];
+
+        encoder.process_event(GifEvent::StartGif { width: 3, height: 2, global_palette: Some(palette[..].into()), background_color_index: 0, looping: LoopConfig::repeat(2), version: None, screen: ScreenOptions::default() })?;
+        encoder.process_event(GifEvent::Comment { text: b"recorded"[..].into() })?;
+        encoder.process_event(GifEvent::StartFrame { delay: 7, disposal_method: DisposalMethod::Previous, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: Some(0), user_input: false, is_interlaced: false, rect: None })?;
+        encoder.process_event(GifEvent::WriteImageChunk { data: pixels[..].into() })?;
+        encoder.process_event(GifEvent::EndFrame)?;
+        encoder.process_event(GifEvent::StartFrame { delay: 3, disposal_method: DisposalMethod::None, global_palette: None, local_palette: Some(local_palette[..].into()), local_palette_sorted: false, transparent_color_index: None, user_input: true, is_interlaced: true, rect: None })?;
+        encoder.process_event(GifEvent::WriteImageChunk { data: [1u8, 0, 1, 0, 1, 0][..].into() })?;
+        encoder.process_event(GifEvent::FlushFrame)?;
+        encoder.process_event(GifEvent::EndFrame)?;
+        encoder.process_event(GifEvent::EndGif)
+    }
+
+    #[test]
+    fn test_record_and_replay() -> Result<(), String> {
+        let mut recorder = RecordingEncoder::new(GifEncoderState::new());
+        produce(&mut recorder)?;
+        let (original, events) = recorder.into_parts();
+
+        let mut replayed = GifEncoderState::new();
+        assert_eq!(replay(&mut replayed, events)?, 10);
+        assert_eq!(replayed.get_encoded_data(), original.get_encoded_data());
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_reports_failing_event() {
+        let events = vec![GifEvent::EndFrame];
+        let err = replay(&mut GifEncoderState::new(), events).unwrap_err();
+        assert!(err.starts_with("Event 0:"));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serialized_round_trip() -> Result<(), String> {
+        let mut recorder = RecordingEncoder::new(GifEncoderState::new());
+        produce(&mut recorder)?;
+        let (original, events) = recorder.into_parts();
+
+        let json = serde_json::to_string(&events).map_err(|err| err.to_string())?;
+        let loaded: Vec<GifEvent> = serde_json::from_str(&json).map_err(|err| err.to_string())?;
+        assert_eq!(loaded, events);
+
+        let mut replayed = GifEncoderState::new();
+        replay(&mut replayed, loaded)?;
+        assert_eq!(replayed.get_encoded_data(), original.get_encoded_data());
+        Ok(())
+    }
+}
@@ -0,0 +1,242 @@
+// MIT License
+// Copyright (c) 2025 Gianluca Cannata <gcannata23@gmail.com>
+//
+// av-gif - A GIF encoder written in Rust
+use std::f32::consts::PI;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResizeFilter {
+    Nearest,  // Picks the closest source pixel, keeps hard edges and exact colors
+    Bilinear, // Triangle filter, widened when downscaling so every source pixel counts
+    Lanczos3, // Windowed sinc over 3 lobes, the sharpest of the three
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResizeTarget {
+    Exact(u16, u16), // Width and height, the aspect ratio may change
+    Fit(u16),        // Largest dimension, aspect ratio kept; smaller images are left alone
+}
+
+// Where and how to resample frames before they're quantized
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resize {
+    pub target: ResizeTarget,
+    pub filter: ResizeFilter,
+}
+
+impl Resize {
+    pub fn to(width: u16, height: u16) -> Self {
+        Resize {
+            target: ResizeTarget::Exact(width, height),
+            filter: ResizeFilter::Lanczos3,
+        }
+    }
+
+    pub fn fit(max_dimension: u16) -> Self {
+        Resize {
+            target: ResizeTarget::Fit(max_dimension),
+            filter: ResizeFilter::Lanczos3,
+        }
+    }
+
+    pub fn with_filter(mut self, filter: ResizeFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    // Size of a resized `width` x `height` image, which is also the screen size to give
+    // StartGif when every frame goes through this resize
+    pub fn size_for(&self, width: u16, height: u16) -> Result<(u16, u16), String> {
+        if width == 0 || height == 0 {
+            return Err(format!("Can't resize a {}x{} image", width, height));
+        }
+
+        let size = match self.target {
+            ResizeTarget::Exact(width, height) => (width, height),
+            ResizeTarget::Fit(max) if width.max(height) <= max => (width, height),
+            ResizeTarget::Fit(max) => {
+                let scale = max as f32 / width.max(height) as f32;
+                let scaled = |len: u16| ((len as f32 * scale).round() as u16).clamp(1, max);
+                (scaled(width), scaled(height))
+            }
+        };
+
+        if size.0 == 0 || size.1 == 0 {
+            return Err(format!("Can't resize to {}x{}", size.0, size.1));
+        }
+        Ok(size)
+    }
+}
+
+// Resample interleaved 8-bit pixels with `channels` samples each. With 4 channels the last
+// one is alpha, and colors are weighted by it so transparent pixels don't bleed into
+// their neighbours.
+pub fn resample(
+    pixels: &[u8],
+    (width, height): (usize, usize),
+    channels: usize,
+    (new_width, new_height): (usize, usize),
+    filter: ResizeFilter,
+) -> Vec<u8> {
+    let has_alpha = channels == 4;
+    let mut samples: Vec<f32> = pixels.iter().map(|&sample| sample as f32).collect();
+    if has_alpha {
+        for pixel in samples.chunks_exact_mut(4) {
+            let alpha = pixel[3] / 255.0;
+            pixel[..3].iter_mut().for_each(|sample| *sample *= alpha);
+        }
+    }
+
+    // Rows first, then columns
+    let columns = contributions(width, new_width, filter);
+    let mut horizontal = vec![0.0f32; new_width * height * channels];
+    for (source, target) in samples
+        .chunks_exact(width * channels)
+        .zip(horizontal.chunks_exact_mut(new_width * channels))
+    {
+        for (pixel, contribution) in target.chunks_exact_mut(channels).zip(&columns) {
+            accumulate(pixel, contribution, |x| &source[x * channels..(x + 1) * channels]);
+        }
+    }
+
+    let rows = contributions(height, new_height, filter);
+    let row_len = new_width * channels;
+    let mut resized = vec![0.0f32; row_len * new_height];
+    for (target, contribution) in resized.chunks_exact_mut(row_len).zip(&rows) {
+        for (x, pixel) in target.chunks_exact_mut(channels).enumerate() {
+            let offset = x * channels;
+            accumulate(pixel, contribution, |y| &horizontal[y * row_len + offset..y * row_len + offset + channels]);
+        }
+    }
+
+    if has_alpha {
+        for pixel in resized.chunks_exact_mut(4) {
+            let alpha = pixel[3].clamp(0.0, 255.0) / 255.0;
+            if alpha > 0.0 {
+                pixel[..3].iter_mut().for_each(|sample| *sample /= alpha);
+            }
+        }
+    }
+
+    // Lanczos lobes can overshoot, so clamp before rounding
+    resized
+        .iter()
+        .map(|&sample| (sample.clamp(0.0, 255.0) + 0.5) as u8)
+        .collect()
+}
+
+// Source pixels and weights making up one output pixel along an axis
+struct Contribution {
+    start: usize,
+    weights: Vec<f32>,
+}
+
+fn accumulate<'s>(pixel: &mut [f32], contribution: &Contribution, source: impl Fn(usize) -> &'s [f32]) {
+    for (index, &weight) in contribution.weights.iter().enumerate() {
+        for (sample, &value) in pixel.iter_mut().zip(source(contribution.start + index)) {
+            *sample += value * weight;
+        }
+    }
+}
+
+fn contributions(source_len: usize, target_len: usize, filter: ResizeFilter) -> Vec<Contribution> {
+    let scale = source_len as f32 / target_len as f32;
+    // Downscaling stretches the kernel over the source pixels each output pixel covers
+    let stretch = scale.max(1.0);
+    let radius = match filter {
+        ResizeFilter::Nearest => 0.0,
+        ResizeFilter::Bilinear => 1.0,
+        ResizeFilter::Lanczos3 => 3.0,
+    } * stretch;
+
+    (0..target_len)
+        .map(|index| {
+            let center = (index as f32 + 0.5) * scale;
+            let nearest = Contribution {
+                start: (center as usize).min(source_len - 1),
+                weights: vec![1.0],
+            };
+            if filter == ResizeFilter::Nearest {
+                return nearest;
+            }
+
+            let start = (center - radius).floor().max(0.0) as usize;
+            let end = ((center + radius).ceil() as usize).min(source_len);
+            let mut weights: Vec<f32> = (start..end)
+                .map(|source| kernel(filter, (source as f32 + 0.5 - center) / stretch))
+                .collect();
+
+            let total: f32 = weights.iter().sum();
+            if total.abs() < f32::EPSILON {
+                return nearest;
+            }
+            weights.iter_mut().for_each(|weight| *weight /= total);
+            Contribution { start, weights }
+        })
+        .collect()
+}
+
+fn kernel(filter: ResizeFilter, x: f32) -> f32 {
+    let x = x.abs();
+    match filter {
+        ResizeFilter::Nearest => (x < 0.5) as u8 as f32,
+        ResizeFilter::Bilinear => (1.0 - x).max(0.0),
+        ResizeFilter::Lanczos3 if x < f32::EPSILON => 1.0,
+        ResizeFilter::Lanczos3 if x < 3.0 => {
+            let x = x * PI;
+            3.0 * x.sin() * (x / 3.0).sin() / (x * x)
+        }
+        ResizeFilter::Lanczos3 => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [ResizeFilter; 3] = [ResizeFilter::Nearest, ResizeFilter::Bilinear, ResizeFilter::Lanczos3];
+
+    #[test]
+    fn test_size_for() -> Result<(), String> {
+        assert_eq!(Resize::to(32, 10).size_for(640, 480)?, (32, 10));
+        assert_eq!(Resize::fit(320).size_for(640, 480)?, (320, 240));
+        assert_eq!(Resize::fit(320).size_for(480, 1000)?, (154, 320));
+        assert_eq!(Resize::fit(320).size_for(100, 50)?, (100, 50));
+        assert_eq!(Resize::fit(10).size_for(1000, 1)?, (10, 1));
+        assert!(Resize::to(0, 10).size_for(640, 480).is_err());
+        assert!(Resize::to(4, 4).size_for(0, 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_flat_color_is_preserved() {
+        let pixels = [10, 200, 30].repeat(7 * 5);
+        for filter in FILTERS {
+            for size in [(3, 2), (7, 5), (16, 11)] {
+                assert_eq!(resample(&pixels, (7, 5), 3, size, filter), [10, 200, 30].repeat(size.0 * size.1), "{:?} {:?}", filter, size);
+            }
+        }
+    }
+
+    #[test]
+    fn test_nearest_upscale_and_downscale() {
+        assert_eq!(resample(&[1, 2], (2, 1), 1, (4, 2), ResizeFilter::Nearest), vec![1, 1, 2, 2, 1, 1, 2, 2]);
+        assert_eq!(resample(&[1, 2, 3, 4], (4, 1), 1, (2, 1), ResizeFilter::Nearest), vec![2, 4]);
+    }
+
+    #[test]
+    fn test_downscale_averages() {
+        // Halving a black and white checkerboard gives gray with the smoothing filters
+        let pixels: Vec<u8> = (0..64).map(|index| if (index % 8 + index / 8) % 2 == 0 { 0 } else { 255 }).collect();
+        for filter in [ResizeFilter::Bilinear, ResizeFilter::Lanczos3] {
+            let resized = resample(&pixels, (8, 8), 1, (4, 4), filter);
+            assert!(resized.iter().all(|&value| (100..=155).contains(&value)), "{:?} {:?}", filter, resized);
+        }
+    }
+
+    #[test]
+    fn test_transparent_pixels_dont_bleed() {
+        let pixels = [255, 0, 0, 255, 0, 0, 255, 0];
+        assert_eq!(resample(&pixels, (2, 1), 4, (1, 1), ResizeFilter::Bilinear), vec![255, 0, 0, 128]);
+    }
+}
@@ -0,0 +1,162 @@
+// MIT License
+// Copyright (c) 2025 Gianluca Cannata <gcannata23@gmail.com>
+//
+// av-gif - A GIF encoder written in Rust
+use alloc::string::{String, ToString};
+use core::time::Duration;
+
+// Browsers replace delays below 2 centiseconds with 10, making animations play slower
+pub const BROWSER_MIN_DELAY: u16 = 2;
+
+// Turns frame rates or presentation timestamps into centisecond delays.
+//
+// Delays are rounded against the total elapsed time rather than per frame,
+// so the rounding error never accumulates: at 30 fps frames get 3 or 4 cs
+// and every 30 frames add up to exactly one second.
+#[derive(Debug, Clone)]
+pub struct FrameTimer {
+    frame_rate: Option<(u32, u32)>, // Frames per second as numerator / denominator
+    origin: Option<Duration>,       // Timestamp of the first frame
+    frame_index: u64,
+    elapsed: u64, // Centiseconds handed out so far
+    min_delay: u16,
+}
+
+impl FrameTimer {
+    // Timer driven by presentation timestamps
+    pub fn new() -> Self {
+        FrameTimer {
+            frame_rate: None,
+            origin: None,
+            frame_index: 0,
+            elapsed: 0,
+            min_delay: 0,
+        }
+    }
+
+    // Timer for a constant frame rate, e.g. 30000 / 1001 for NTSC video
+    pub fn with_frame_rate(numerator: u32, denominator: u32) -> Result<Self, String> {
+        if numerator == 0 || denominator == 0 {
+            return Err("Frame rate must be positive".to_string());
+        }
+
+        Ok(FrameTimer {
+            frame_rate: Some((numerator, denominator)),
+            ..Self::new()
+        })
+    }
+
+    // Raise delays below `min_delay` centiseconds, later frames are shortened to catch up
+    pub fn with_min_delay(mut self, min_delay: u16) -> Self {
+        self.min_delay = min_delay;
+        self
+    }
+
+    // Avoid delays that browsers would silently slow down to 10 cs
+    pub fn with_browser_min_delay(self) -> Self {
+        self.with_min_delay(BROWSER_MIN_DELAY)
+    }
+
+    // Delay of the next frame at the configured frame rate
+    pub fn next_delay(&mut self) -> Result<u16, String> {
+        let (numerator, denominator) = self
+            .frame_rate
+            .ok_or("Timer has no frame rate, use delay_for_timestamps")?;
+
+        self.frame_index += 1;
+
+        // End of this frame in centiseconds, rounded to nearest
+        let numerator = numerator as u128;
+        let end = (self.frame_index as u128 * denominator as u128 * 100 * 2 + numerator)
+            / (numerator * 2);
+
+        Ok(self.advance_to(end as u64))
+    }
+
+    // Delay of a frame shown at `timestamp` until the next one shows at `next_timestamp`
+    pub fn delay_for_timestamps(&mut self, timestamp: Duration, next_timestamp: Duration) -> u16 {
+        let origin = *self.origin.get_or_insert(timestamp);
+
+        // End of this frame in centiseconds, rounded to nearest
+        let end = next_timestamp.saturating_sub(origin).as_nanos();
+        let end = (end + 5_000_000) / 10_000_000;
+
+        self.advance_to(end as u64)
+    }
+
+    fn advance_to(&mut self, end: u64) -> u16 {
+        let delay = end
+            .saturating_sub(self.elapsed)
+            .max(self.min_delay as u64)
+            .min(u16::MAX as u64);
+
+        self.elapsed += delay;
+        delay as u16
+    }
+}
+
+impl Default for FrameTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_rate_has_no_drift() -> Result<(), String> {
+        let mut timer = FrameTimer::with_frame_rate(30, 1)?;
+        let delays: Vec<u16> = (0..30).map(|_| timer.next_delay()).collect::<Result<_, _>>()?;
+
+        assert!(delays.iter().all(|&delay| delay == 3 || delay == 4));
+        assert_eq!(delays.iter().map(|&delay| delay as u32).sum::<u32>(), 100);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fractional_frame_rate() -> Result<(), String> {
+        let mut timer = FrameTimer::with_frame_rate(30000, 1001)?;
+        let total: u64 = (0..30000).map(|_| timer.next_delay().map(|delay| delay as u64)).sum::<Result<_, _>>()?;
+
+        assert_eq!(total, 100100);
+        Ok(())
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let mut timer = FrameTimer::new();
+        let timestamps: Vec<Duration> = (0..=90)
+            .map(|frame| Duration::from_nanos(5_000_000_000 + frame * 1_000_000_000 / 30))
+            .collect();
+
+        let total: u32 = timestamps
+            .windows(2)
+            .map(|pair| timer.delay_for_timestamps(pair[0], pair[1]) as u32)
+            .sum();
+
+        assert_eq!(total, 300);
+    }
+
+    #[test]
+    fn test_browser_min_delay() -> Result<(), String> {
+        let mut timer = FrameTimer::with_frame_rate(100, 1)?.with_browser_min_delay();
+        assert_eq!(timer.next_delay()?, 2);
+
+        // The skipped centisecond is taken back from the next frame
+        let mut timer = FrameTimer::new().with_browser_min_delay();
+        let delays = [
+            timer.delay_for_timestamps(Duration::ZERO, Duration::from_millis(10)),
+            timer.delay_for_timestamps(Duration::from_millis(10), Duration::from_millis(60)),
+        ];
+        assert_eq!(delays, [2, 4]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_frame_rate() {
+        assert!(FrameTimer::with_frame_rate(0, 1).is_err());
+        assert!(FrameTimer::new().next_delay().is_err());
+    }
+}
@@ -0,0 +1,322 @@
+// MIT License
+// Copyright (c) 2025 Gianluca Cannata <gcannata23@gmail.com>
+//
+// av-gif - A GIF encoder written in Rust
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::decoder::{DecodedFrame, DecodedGif};
+use crate::encoder::{DisposalMethod, FrameRect};
+
+// Geometric operations on an image, rotations are clockwise
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transform {
+    Crop(FrameRect),
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    FlipHorizontal, // Mirror left to right
+    FlipVertical,   // Mirror top to bottom
+}
+
+impl Transform {
+    // Size of a `width` x `height` image after the transform
+    pub fn size(&self, width: u16, height: u16) -> Result<(u16, u16), String> {
+        match self {
+            Transform::Crop(rect) if !rect.fits(width, height) => Err(format!(
+                "Crop of {}x{} at {},{} doesn't fit the {}x{} image",
+                rect.width, rect.height, rect.left, rect.top, width, height
+            )),
+            Transform::Crop(rect) => Ok((rect.width, rect.height)),
+            Transform::Rotate90 | Transform::Rotate270 => Ok((height, width)),
+            _ => Ok((width, height)),
+        }
+    }
+
+    // Where a frame covering `rect` of a `width` x `height` screen ends up, or None when
+    // a crop leaves nothing of it or the frame doesn't fit the screen
+    pub fn rect(&self, rect: FrameRect, width: u16, height: u16) -> Option<FrameRect> {
+        let (right, bottom) = (rect.left.checked_add(rect.width)?, rect.top.checked_add(rect.height)?);
+        let (from_right, from_bottom) = (width.checked_sub(right)?, height.checked_sub(bottom)?);
+        match *self {
+            Transform::Crop(crop) => {
+                let left = rect.left.max(crop.left);
+                let top = rect.top.max(crop.top);
+                let right = right.min(crop.left.saturating_add(crop.width));
+                let bottom = bottom.min(crop.top.saturating_add(crop.height));
+                (left < right && top < bottom)
+                    .then(|| FrameRect::new(left - crop.left, top - crop.top, right - left, bottom - top))
+            }
+            Transform::Rotate90 => Some(FrameRect::new(from_bottom, rect.left, rect.height, rect.width)),
+            Transform::Rotate180 => Some(FrameRect::new(from_right, from_bottom, rect.width, rect.height)),
+            Transform::Rotate270 => Some(FrameRect::new(rect.top, from_right, rect.height, rect.width)),
+            Transform::FlipHorizontal => Some(FrameRect::new(from_right, rect.top, rect.width, rect.height)),
+            Transform::FlipVertical => Some(FrameRect::new(rect.left, from_bottom, rect.width, rect.height)),
+        }
+    }
+}
+
+// Apply a transform to interleaved pixels with `channels` bytes each: 3 or 4 for truecolor
+// frames, 1 for palette indices. Returns the pixels with their new width and height.
+pub fn transform_pixels(
+    pixels: &[u8],
+    width: u16,
+    height: u16,
+    channels: usize,
+    transform: &Transform,
+) -> Result<(Vec<u8>, u16, u16), String> {
+    let expected = width as usize * height as usize * channels;
+    if pixels.len() != expected {
+        return Err(format!(
+            "Image of {}x{} needs {} bytes of pixel data, got {}",
+            width,
+            height,
+            expected,
+            pixels.len()
+        ));
+    }
+
+    let (new_width, new_height) = transform.size(width, height)?;
+    let (w, h) = (width as usize, height as usize);
+
+    // Source coordinates of each output pixel
+    let source = |x: usize, y: usize| -> (usize, usize) {
+        match transform {
+            Transform::Crop(rect) => (rect.left as usize + x, rect.top as usize + y),
+            Transform::Rotate90 => (y, h - 1 - x),
+            Transform::Rotate180 => (w - 1 - x, h - 1 - y),
+            Transform::Rotate270 => (w - 1 - y, x),
+            Transform::FlipHorizontal => (w - 1 - x, y),
+            Transform::FlipVertical => (x, h - 1 - y),
+        }
+    };
+
+    let mut transformed = Vec::with_capacity(new_width as usize * new_height as usize * channels);
+    for y in 0..new_height as usize {
+        for x in 0..new_width as usize {
+            let (source_x, source_y) = source(x, y);
+            let start = (source_y * w + source_x) * channels;
+            transformed.extend_from_slice(&pixels[start..start + channels]);
+        }
+    }
+
+    Ok((transformed, new_width, new_height))
+}
+
+// Transform an indexed frame of a `width` x `height` screen, moving it to its new place.
+// Colors are untouched, so no requantization happens.
+pub fn transform_frame(
+    frame: &DecodedFrame,
+    width: u16,
+    height: u16,
+    transform: &Transform,
+) -> Result<DecodedFrame, String> {
+    transform.size(width, height)?;
+
+    let clipped = clip_to_screen(frame, width, height)?;
+    let Some((frame, rect)) = clipped
+        .as_ref()
+        .and_then(|clipped| Some((clipped, transform.rect(clipped.rect(), width, height)?)))
+    else {
+        // Off screen or cropped away entirely: a transparent pixel keeps the frame's delay
+        let transparent_index = frame.transparent_color_index.unwrap_or(0);
+        return Ok(DecodedFrame {
+            left: 0,
+            top: 0,
+            width: 1,
+            height: 1,
+            disposal_method: DisposalMethod::None,
+            transparent_color_index: Some(transparent_index),
+            indices: vec![transparent_index],
+            ..frame.clone()
+        });
+    };
+
+    // Crops are given in screen coordinates, the frame only sees its own part
+    let local = match transform {
+        Transform::Crop(crop) => Transform::Crop(FrameRect::new(
+            crop.left.max(frame.left) - frame.left,
+            crop.top.max(frame.top) - frame.top,
+            rect.width,
+            rect.height,
+        )),
+        transform => *transform,
+    };
+    let (indices, _, _) = transform_pixels(&frame.indices, frame.width, frame.height, 1, &local)?;
+
+    Ok(DecodedFrame {
+        left: rect.left,
+        top: rect.top,
+        width: rect.width,
+        height: rect.height,
+        indices,
+        ..frame.clone()
+    })
+}
+
+// The part of a frame inside a `width` x `height` screen, or None when none of it is.
+// Pixels outside are never shown (see `render`), so they have nowhere to go either.
+fn clip_to_screen(frame: &DecodedFrame, width: u16, height: u16) -> Result<Option<DecodedFrame>, String> {
+    let visible_width = frame.width.min(width.saturating_sub(frame.left));
+    let visible_height = frame.height.min(height.saturating_sub(frame.top));
+    if visible_width == 0 || visible_height == 0 {
+        return Ok(None);
+    }
+
+    let crop = Transform::Crop(FrameRect::new(0, 0, visible_width, visible_height));
+    let (indices, _, _) = transform_pixels(&frame.indices, frame.width, frame.height, 1, &crop)?;
+    Ok(Some(DecodedFrame {
+        width: visible_width,
+        height: visible_height,
+        indices,
+        ..frame.clone()
+    }))
+}
+
+// Transform every frame of a decoded GIF along with its logical screen. Write the result
+// with `replay::replay(&mut encoder, gif.events())`.
+pub fn transform_gif(gif: &DecodedGif, transform: &Transform) -> Result<DecodedGif, String> {
+    let (width, height) = transform.size(gif.width, gif.height)?;
+    let frames = gif
+        .frames
+        .iter()
+        .map(|frame| transform_frame(frame, gif.width, gif.height, transform))
+        .collect::<Result<_, _>>()?;
+
+    Ok(DecodedGif {
+        width,
+        height,
+        frames,
+        ..gif.clone()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::{decode, render, Canvas};
+    use crate::encoder::{GifEncoder, GifEncoderState, GifEvent, LoopConfig, ScreenOptions};
+    use crate::replay::replay;
+
+    // 3x2 image:
+    //   1 2 3
+    //   4 5 6
+    const PIXELS: [u8; 6] = [1, 2, 3, 4, 5, 6];
+
+    #[test]
+    fn test_transform_pixels() -> Result<(), String> {
+        let cases = [
+            (Transform::Rotate90, vec![4, 1, 5, 2, 6, 3], (2, 3)),
+            (Transform::Rotate180, vec![6, 5, 4, 3, 2, 1], (3, 2)),
+            (Transform::Rotate270, vec![3, 6, 2, 5, 1, 4], (2, 3)),
+            (Transform::FlipHorizontal, vec![3, 2, 1, 6, 5, 4], (3, 2)),
+            (Transform::FlipVertical, vec![4, 5, 6, 1, 2, 3], (3, 2)),
+            (Transform::Crop(FrameRect::new(1, 0, 2, 2)), vec![2, 3, 5, 6], (2, 2)),
+        ];
+        for (transform, expected, size) in cases {
+            assert_eq!(transform_pixels(&PIXELS, 3, 2, 1, &transform)?, (expected, size.0, size.1), "{:?}", transform);
+        }
+
+        // Whole pixels move together
+        let rgb: Vec<u8> = PIXELS.iter().flat_map(|&value| [value, value, value]).collect();
+        assert_eq!(transform_pixels(&rgb, 3, 2, 3, &Transform::Rotate90)?.0[..3], [4, 4, 4]);
+
+        assert!(transform_pixels(&PIXELS, 3, 2, 1, &Transform::Crop(FrameRect::new(2, 0, 2, 1))).is_err());
+        assert!(transform_pixels(&PIXELS, 2, 2, 1, &Transform::Rotate90).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rect_follows_pixels() {
+        // A frame's rect must land where its pixels do when the whole screen is transformed
+        let (width, height) = (5u16, 3u16);
+        let rect = FrameRect::new(1, 0, 2, 2);
+        let screen: Vec<u8> = (0..width * height).map(|index| ((1..3).contains(&(index % width)) && index / width < 2) as u8).collect();
+
+        for transform in [Transform::Rotate90, Transform::Rotate180, Transform::Rotate270, Transform::FlipHorizontal, Transform::FlipVertical] {
+            let (transformed, new_width, _) = transform_pixels(&screen, width, height, 1, &transform).unwrap();
+            let moved = transform.rect(rect, width, height).unwrap();
+            for (index, &inside) in transformed.iter().enumerate() {
+                let (x, y) = (index as u16 % new_width, index as u16 / new_width);
+                let expected = x >= moved.left && x < moved.left + moved.width && y >= moved.top && y < moved.top + moved.height;
+                assert_eq!(inside == 1, expected, "{:?}", transform);
+            }
+        }
+
+        assert_eq!(Transform::Crop(FrameRect::new(2, 1, 3, 2)).rect(rect, width, height), Some(FrameRect::new(0, 0, 1, 1)));
+        assert_eq!(Transform::Crop(FrameRect::new(3, 0, 2, 3)).rect(rect, width, height), None);
+    }
+
+    fn animation() -> Result<Vec<u8>, String> {
+        let mut encoder = GifEncoderState::new();
+        encoder.process_event(GifEvent::StartGif { width: 4, height: 3, global_palette: Some(vec![[0, 0, 0], [255, 0, 0], [0, 255, 0], [0, 0, 255]].into()), background_color_index: 0, looping: LoopConfig::infinite(), version: None, screen: ScreenOptions::default() })?;
+        encoder.process_event(GifEvent::StartFrame { delay: 4, disposal_method: DisposalMethod::None, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: None, user_input: false, is_interlaced: false, rect: None })?;
+        encoder.process_event(GifEvent::WriteImageChunk { data: vec![0, 1, 2, 3, 1, 2, 3, 0, 2, 3, 0, 1].into() })?;
+        encoder.process_event(GifEvent::EndFrame)?;
+        encoder.process_event(GifEvent::StartFrame { delay: 6, disposal_method: DisposalMethod::Background, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index: Some(0), user_input: false, is_interlaced: true, rect: Some(FrameRect::new(2, 1, 2, 2)) })?;
+        encoder.process_event(GifEvent::WriteImageChunk { data: vec![3, 0, 3, 3].into() })?;
+        encoder.process_event(GifEvent::EndFrame)?;
+        encoder.process_event(GifEvent::StartFrame { delay: 8, disposal_method: DisposalMethod::None, global_palette: None, local_palette: Some(vec![[9, 9, 9], [7, 7, 7]].into()), local_palette_sorted: false, transparent_color_index: None, user_input: false, is_interlaced: false, rect: Some(FrameRect::new(0, 0, 1, 1)) })?;
+        encoder.process_event(GifEvent::WriteImageChunk { data: vec![1].into() })?;
+        encoder.process_event(GifEvent::EndFrame)?;
+        encoder.process_event(GifEvent::EndGif)?;
+        Ok(encoder.get_encoded_data().to_vec())
+    }
+
+    // Canvas as 4-byte pixels with a coverage byte, so it can go through `transform_pixels`
+    fn canvas_bytes(canvas: &Canvas) -> Vec<u8> {
+        canvas
+            .iter()
+            .flat_map(|pixel| match pixel {
+                Some([r, g, b]) => [*r, *g, *b, 1],
+                None => [0; 4],
+            })
+            .collect()
+    }
+
+    // Every transform of `original` must render as the transformed canvases of the original
+    fn assert_renders_transformed(original: &DecodedGif) -> Result<(), String> {
+        let canvases = render(original)?;
+
+        for transform in [Transform::Rotate90, Transform::Rotate180, Transform::Rotate270, Transform::FlipHorizontal, Transform::FlipVertical, Transform::Crop(FrameRect::new(1, 1, 3, 2)), Transform::Crop(FrameRect::new(0, 0, 2, 1))] {
+            let transformed = transform_gif(original, &transform)?;
+
+            let mut encoder = GifEncoderState::new();
+            replay(&mut encoder, transformed.events())?;
+            crate::validate::assert_valid(encoder.get_encoded_data());
+            let written = decode(encoder.get_encoded_data())?;
+
+            assert_eq!((written.width, written.height), transform.size(original.width, original.height)?);
+            assert_eq!(written.frames.iter().map(|frame| frame.delay).collect::<Vec<_>>(), vec![4, 6, 8]);
+            for (canvas, expected) in render(&written)?.iter().zip(&canvases) {
+                let (expected, _, _) = transform_pixels(&canvas_bytes(expected), original.width, original.height, 4, &transform)?;
+                assert_eq!(canvas_bytes(canvas), expected, "{:?}", transform);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_transformed_gif_renders_transformed() -> Result<(), String> {
+        let original = decode(&animation()?)?;
+        assert_renders_transformed(&original)?;
+
+        assert!(transform_gif(&original, &Transform::Crop(FrameRect::new(3, 0, 2, 1))).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_frames_past_the_screen_are_clipped() -> Result<(), String> {
+        // Decoders accept frames reaching past the screen, the encoder doesn't write them
+        let mut original = decode(&animation()?)?;
+        original.frames[1].left = 3;
+        original.frames[2].left = 4;
+        assert_renders_transformed(&original)?;
+
+        let clipped = transform_frame(&original.frames[1], 4, 3, &Transform::FlipHorizontal)?;
+        assert_eq!((clipped.rect(), clipped.indices), (FrameRect::new(0, 1, 1, 2), vec![3, 3]));
+        Ok(())
+    }
+}
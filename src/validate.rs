@@ -0,0 +1,401 @@
+// MIT License
+// Copyright (c) 2025 Gianluca Cannata <gcannata23@gmail.com>
+//
+// av-gif - A GIF encoder written in Rust
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use weezl::{decode::Decoder, BitOrder, LzwStatus};
+
+use crate::decoder::{deinterlace, Reader};
+
+// Something in a GIF stream that the GIF89a specification doesn't allow
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub offset: usize, // Start of the offending block or field
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "offset {}: {}", self.offset, self.message)
+    }
+}
+
+// Check a GIF byte stream against the GIF89a specification (and GIF87a for files that
+// claim that version) and list every violation found. Checking goes on past problems
+// that leave the block structure intact; truncated data or an unknown block introducer
+// end it, since nothing after them can be located.
+pub fn validate(data: &[u8]) -> Vec<Violation> {
+    let mut validator = Validator {
+        reader: Reader { data, position: 0 },
+        violations: Vec::new(),
+        is_gif87a: false,
+        width: 0,
+        height: 0,
+        global_color_table: None,
+        graphic_control: None,
+    };
+
+    if let Err(message) = validator.validate() {
+        let offset = validator.reader.position;
+        validator.violations.push(Violation { offset, message });
+    }
+    validator.violations
+}
+
+struct Validator<'a> {
+    reader: Reader<'a>,
+    violations: Vec<Violation>,
+    is_gif87a: bool,
+    width: u16,
+    height: u16,
+    global_color_table: Option<usize>, // Number of entries
+    graphic_control: Option<PendingControl>,
+}
+
+// A graphic control extension that hasn't met its graphic rendering block yet
+struct PendingControl {
+    offset: usize,
+    transparent_color_index: Option<u8>,
+}
+
+impl<'a> Validator<'a> {
+    fn violation(&mut self, offset: usize, message: impl Into<String>) {
+        self.violations.push(Violation {
+            offset,
+            message: message.into(),
+        });
+    }
+
+    fn validate(&mut self) -> Result<(), String> {
+        match self.reader.bytes(6)? {
+            b"GIF89a" => {}
+            b"GIF87a" => self.is_gif87a = true,
+            [b'G', b'I', b'F', ..] => self.violation(0, "Unknown GIF version"),
+            _ => return Err("Not a GIF file".to_string()),
+        }
+
+        self.logical_screen()?;
+
+        loop {
+            let offset = self.reader.position;
+            match self.reader.u8()? {
+                0x21 => self.extension(offset)?,
+                0x2C => self.image(offset)?,
+                0x3B => {
+                    if let Some(control) = self.graphic_control.take() {
+                        self.violation(control.offset, "Graphic control extension isn't followed by an image");
+                    }
+                    if self.reader.position < self.reader.data.len() {
+                        let extra = self.reader.data.len() - self.reader.position;
+                        self.violation(self.reader.position, format!("{} bytes after the trailer", extra));
+                    }
+                    return Ok(());
+                }
+                byte => return Err(format!("Unknown block introducer 0x{:02X}", byte)),
+            }
+        }
+    }
+
+    fn logical_screen(&mut self) -> Result<(), String> {
+        let offset = self.reader.position;
+        self.width = self.reader.u16()?;
+        self.height = self.reader.u16()?;
+        let packed_fields = self.reader.u8()?;
+        let background_color_index = self.reader.u8()?;
+        let pixel_aspect_ratio = self.reader.u8()?;
+
+        if self.is_gif87a && packed_fields & 0b0000_1000 != 0 {
+            self.violation(offset + 4, "Sort flag is reserved in GIF87a");
+        }
+        if self.is_gif87a && pixel_aspect_ratio != 0 {
+            self.violation(offset + 6, "Pixel aspect ratio is reserved in GIF87a");
+        }
+
+        self.global_color_table = self.color_table(packed_fields)?;
+        match self.global_color_table {
+            Some(entries) if background_color_index as usize >= entries => self.violation(
+                offset + 5,
+                format!("Background color index {} is outside the {} color global table", background_color_index, entries),
+            ),
+            None if background_color_index != 0 => {
+                self.violation(offset + 5, "Background color index is set without a global color table")
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Skip a color table, returning its number of entries
+    fn color_table(&mut self, packed_fields: u8) -> Result<Option<usize>, String> {
+        if packed_fields & 0b1000_0000 == 0 {
+            return Ok(None);
+        }
+
+        let entries = 2usize << (packed_fields & 0b0000_0111);
+        self.reader.bytes(entries * 3)?;
+        Ok(Some(entries))
+    }
+
+    fn extension(&mut self, offset: usize) -> Result<(), String> {
+        let label = self.reader.u8()?;
+        if self.is_gif87a {
+            self.violation(offset, "Extensions aren't allowed in GIF87a");
+        }
+
+        match label {
+            0xF9 => {
+                let (block_size, body) = self.first_sub_block()?;
+                let (_, extra) = self.reader.skip_sub_blocks()?;
+
+                if block_size != 4 || extra > 0 {
+                    self.violation(offset, "Graphic control extension must hold one 4-byte block");
+                }
+                if body.len() < 4 {
+                    return Ok(());
+                }
+
+                let packed_fields = body[0];
+                if packed_fields & 0b1110_0000 != 0 {
+                    self.violation(offset + 3, "Reserved graphic control bits are set");
+                }
+                if (packed_fields >> 2) & 0b0000_0111 > 3 {
+                    self.violation(offset + 3, "Disposal method is reserved");
+                }
+                if let Some(previous) = self.graphic_control.take() {
+                    self.violation(previous.offset, "Graphic control extension isn't followed by an image");
+                }
+                self.graphic_control = Some(PendingControl {
+                    offset,
+                    transparent_color_index: (packed_fields & 0b0000_0001 != 0).then_some(body[3]),
+                });
+            }
+            0xFF => {
+                let (block_size, _) = self.first_sub_block()?;
+                self.reader.skip_sub_blocks()?;
+                if block_size != 11 {
+                    self.violation(offset, "Application extension identifier block must be 11 bytes");
+                }
+            }
+            0x01 => {
+                let (block_size, _) = self.first_sub_block()?;
+                self.reader.skip_sub_blocks()?;
+                if block_size != 12 {
+                    self.violation(offset, "Plain text extension header block must be 12 bytes");
+                }
+                // Plain text is a graphic rendering block, so it uses up the graphic control
+                self.graphic_control = None;
+            }
+            0xFE => {
+                self.reader.skip_sub_blocks()?;
+            }
+            label => {
+                self.reader.skip_sub_blocks()?;
+                self.violation(offset, format!("Unknown extension label 0x{:02X}", label));
+            }
+        }
+        Ok(())
+    }
+
+    // Size and contents of the first sub-block, empty when the chain ends right away
+    fn first_sub_block(&mut self) -> Result<(usize, &'a [u8]), String> {
+        let len = self.reader.u8()? as usize;
+        if len == 0 {
+            self.reader.position -= 1; // Leave the terminator for `skip_sub_blocks`
+            return Ok((0, &[]));
+        }
+        Ok((len, self.reader.bytes(len)?))
+    }
+
+    fn image(&mut self, offset: usize) -> Result<(), String> {
+        let left = self.reader.u16()?;
+        let top = self.reader.u16()?;
+        let width = self.reader.u16()?;
+        let height = self.reader.u16()?;
+        let packed_fields = self.reader.u8()?;
+
+        if packed_fields & 0b0001_1000 != 0 {
+            self.violation(offset + 9, "Reserved image descriptor bits are set");
+        }
+        if self.is_gif87a && packed_fields & 0b0010_0000 != 0 {
+            self.violation(offset + 9, "Sort flag is reserved in GIF87a");
+        }
+        if left as u32 + width as u32 > self.width as u32 || top as u32 + height as u32 > self.height as u32 {
+            self.violation(
+                offset,
+                format!("Image of {}x{} at {},{} doesn't fit the {}x{} screen", width, height, left, top, self.width, self.height),
+            );
+        }
+
+        let color_table = self.color_table(packed_fields)?.or(self.global_color_table);
+        if color_table.is_none() {
+            self.violation(offset, "Image has neither a local nor a global color table");
+        }
+
+        if let Some(control) = self.graphic_control.take() {
+            match (control.transparent_color_index, color_table) {
+                (Some(index), Some(entries)) if index as usize >= entries => self.violation(
+                    control.offset + 6,
+                    format!("Transparent color index {} is outside the {} color table", index, entries),
+                ),
+                _ => {}
+            }
+        }
+
+        let data_offset = self.reader.position;
+        let min_code_size = self.reader.u8()?;
+        let compressed_data = self.reader.sub_blocks()?;
+
+        if !(2..=8).contains(&min_code_size) {
+            self.violation(data_offset, format!("LZW minimum code size {} is outside 2..=8", min_code_size));
+            return Ok(());
+        }
+
+        let (indices, ended) = match decompress(&compressed_data, min_code_size) {
+            Ok(result) => result,
+            Err(message) => {
+                self.violation(data_offset, message);
+                return Ok(());
+            }
+        };
+
+        if !ended {
+            self.violation(data_offset, "Image data has no end of information code");
+        }
+
+        let expected = width as usize * height as usize;
+        if indices.len() != expected {
+            self.violation(
+                data_offset,
+                format!("Image data holds {} pixels instead of {}", indices.len(), expected),
+            );
+        }
+
+        if let Some(entries) = color_table {
+            let indices = match packed_fields & 0b0100_0000 != 0 && indices.len() == expected {
+                true => deinterlace(&indices, width, height),
+                false => indices,
+            };
+            if let Some(position) = indices.iter().position(|&index| index as usize >= entries) {
+                self.violation(
+                    data_offset,
+                    format!(
+                        "Pixel {} uses color index {} outside the {} color table",
+                        position, indices[position], entries
+                    ),
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+// Decompress image data, telling whether it was closed by an end of information code
+fn decompress(data: &[u8], min_code_size: u8) -> Result<(Vec<u8>, bool), String> {
+    let mut decoder = Decoder::new(BitOrder::Lsb, min_code_size);
+    let mut indices = Vec::new();
+    let mut buffer = [0u8; 4096];
+    let mut input = data;
+
+    loop {
+        let result = decoder.decode_bytes(input, &mut buffer);
+        input = &input[result.consumed_in..];
+        indices.extend_from_slice(&buffer[..result.consumed_out]);
+
+        match result.status {
+            Ok(LzwStatus::Ok) => {}
+            Ok(LzwStatus::Done) => return Ok((indices, true)),
+            Ok(LzwStatus::NoProgress) => return Ok((indices, false)),
+            Err(err) => return Err(format!("Invalid LZW data: {:?}", err)),
+        }
+    }
+}
+
+// Test helper: fail with the full list of violations
+#[cfg(test)]
+pub(crate) fn assert_valid(data: &[u8]) {
+    let violations = validate(data);
+    assert!(
+        violations.is_empty(),
+        "{}",
+        violations.iter().map(|violation| violation.to_string()).collect::<Vec<_>>().join("\n")
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::{DisposalMethod, GifEncoder, GifEncoderState, GifEvent, GifVersion, LoopConfig, ScreenOptions};
+
+    fn encode(version: Option<GifVersion>, events: &[GifEvent]) -> Result<Vec<u8>, String> {
+        // GIF87a has no loop extension
+        let looping = match version {
+            Some(GifVersion::Gif87a) => LoopConfig::once(),
+            _ => LoopConfig::infinite(),
+        };
+        let mut encoder = GifEncoderState::new();
+        encoder.process_event(GifEvent::StartGif { width: 4, height: 2, global_palette: Some(vec![[0, 0, 0], [255, 255, 255], [255, 0, 0]].into()), background_color_index: 2, looping, version, screen: ScreenOptions::default() })?;
+        for event in events {
+            encoder.process_event(event.clone())?;
+        }
+        encoder.process_event(GifEvent::EndGif)?;
+        Ok(encoder.get_encoded_data().to_vec())
+    }
+
+    fn frame(disposal_method: DisposalMethod, delay: u16, transparent_color_index: Option<u8>, is_interlaced: bool) -> GifEvent<'static> {
+        GifEvent::StartFrame { delay, disposal_method, global_palette: None, local_palette: None, local_palette_sorted: false, transparent_color_index, user_input: false, is_interlaced, rect: None }
+    }
+
+    #[test]
+    fn test_encoder_output_conforms() -> Result<(), String> {
+        let pixels = GifEvent::WriteImageChunk { data: vec![0, 1, 2, 0, 1, 2, 0, 1].into() };
+
+        assert_valid(&encode(None, &[frame(DisposalMethod::Background, 0, None, false), pixels.clone(), GifEvent::EndFrame])?);
+        assert_valid(&encode(Some(GifVersion::Gif87a), &[frame(DisposalMethod::None, 0, None, true), pixels.clone(), GifEvent::EndFrame])?);
+        assert_valid(&encode(None, &[GifEvent::Comment { text: vec![b'a'; 600].into() }, frame(DisposalMethod::Background, 3, Some(2), true), pixels.clone(), GifEvent::EndFrame, frame(DisposalMethod::Background, 3, None, false), pixels.clone(), GifEvent::EndFrame])?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reports_violations() -> Result<(), String> {
+        let data = encode(None, &[frame(DisposalMethod::Background, 5, Some(1), false), GifEvent::WriteImageChunk { data: vec![0, 1, 2, 0, 1, 2, 0, 1].into() }, GifEvent::EndFrame])?;
+        assert!(validate(&data).is_empty());
+
+        // Transparent index outside the 4 color table
+        let mut bad = data.clone();
+        let gce = bad.windows(2).position(|window| window == [0x21, 0xF9]).unwrap();
+        bad[gce + 6] = 9;
+        assert_eq!(validate(&bad), vec![Violation { offset: gce + 6, message: "Transparent color index 9 is outside the 4 color table".to_string() }]);
+
+        // Stray block terminator after the trailer, reserved disposal method
+        let mut bad = data.clone();
+        bad[gce + 3] |= 0b0001_0000;
+        bad.push(0x00);
+        let messages: Vec<_> = validate(&bad).into_iter().map(|violation| violation.message).collect();
+        assert_eq!(messages, vec!["Disposal method is reserved", "1 bytes after the trailer"]);
+
+        // Two-byte LZW minimum code size
+        let mut bad = data.clone();
+        let descriptor = bad.iter().position(|&byte| byte == 0x2C).unwrap();
+        bad.insert(descriptor + 11, 0x00);
+        assert!(!validate(&bad).is_empty());
+
+        // Truncated stream
+        let messages: Vec<_> = validate(&data[..data.len() - 1]).into_iter().map(|violation| violation.message).collect();
+        assert_eq!(messages, vec![format!("Unexpected end of data at offset {}", data.len() - 1)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gif87a_rules() -> Result<(), String> {
+        let data = encode(None, &[GifEvent::Comment { text: b"hi".as_slice().into() }, frame(DisposalMethod::Background, 0, None, false), GifEvent::WriteImageChunk { data: vec![0; 8].into() }, GifEvent::EndFrame])?;
+        let mut bad = data.clone();
+        bad[..6].copy_from_slice(b"GIF87a");
+        let messages: Vec<_> = validate(&bad).into_iter().map(|violation| violation.message).collect();
+        assert_eq!(messages, vec!["Extensions aren't allowed in GIF87a"; 2]);
+        Ok(())
+    }
+}
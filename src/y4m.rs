@@ -0,0 +1,391 @@
+// MIT License
+// Copyright (c) 2025 Gianluca Cannata <gcannata23@gmail.com>
+//
+// av-gif - A GIF encoder written in Rust
+use std::io::{BufRead, Read};
+
+use crate::encoder::ScreenOptions;
+use crate::frame::Frame;
+use crate::timing::FrameTimer;
+
+// Matrix used to turn Y'CbCr into RGB. y4m doesn't say, so the caller picks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMatrix {
+    Bt601, // SD video
+    Bt709, // HD video
+}
+
+impl ColorMatrix {
+    // Luma weights of red and blue
+    fn weights(self) -> (f32, f32) {
+        match self {
+            ColorMatrix::Bt601 => (0.299, 0.114),
+            ColorMatrix::Bt709 => (0.2126, 0.0722),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chroma {
+    C420,      // Chroma planes at half width and half height
+    C422,      // Chroma planes at half width
+    C444,      // Full resolution chroma
+    C444Alpha, // Full resolution chroma plus an alpha plane
+    Mono,      // Luma only
+}
+
+impl Chroma {
+    fn from_tag(tag: &str) -> Result<Self, String> {
+        match tag {
+            "420" | "420jpeg" | "420paldv" | "420mpeg2" => Ok(Chroma::C420),
+            "422" => Ok(Chroma::C422),
+            "444" => Ok(Chroma::C444),
+            "444alpha" => Ok(Chroma::C444Alpha),
+            "mono" => Ok(Chroma::Mono),
+            _ => Err(format!("Unsupported y4m colorspace {}, only 8-bit input is supported", tag)),
+        }
+    }
+
+    // Chroma plane size for a frame of `width` x `height`
+    fn plane_size(self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Chroma::C420 => (width.div_ceil(2), height.div_ceil(2)),
+            Chroma::C422 => (width.div_ceil(2), height),
+            Chroma::C444 | Chroma::C444Alpha => (width, height),
+            Chroma::Mono => (0, 0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Y4mHeader {
+    pub width: u16,
+    pub height: u16,
+    pub frame_rate: (u32, u32),   // Frames per second as numerator / denominator
+    pub pixel_aspect: (u32, u32), // Pixel width / height, 0:0 when unknown
+    pub chroma: Chroma,
+    pub full_range: bool, // Samples use 0..=255 instead of the video range 16..=235
+}
+
+impl Y4mHeader {
+    // Screen options carrying the stream's pixel aspect ratio
+    pub fn screen_options(&self) -> ScreenOptions {
+        match self.pixel_aspect {
+            (width, height) if width != 0 && height != 0 && width != height => {
+                ScreenOptions::default().with_aspect_ratio(width as f32 / height as f32)
+            }
+            _ => ScreenOptions::default(),
+        }
+    }
+}
+
+// Reads frames from a YUV4MPEG2 stream as RGB (or RGBA for 444alpha) frames whose
+// delays follow the stream's frame rate
+pub struct Y4mReader<R> {
+    reader: R,
+    header: Y4mHeader,
+    matrix: ColorMatrix,
+    timer: FrameTimer,
+}
+
+impl<R: BufRead> Y4mReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, String> {
+        let line = read_line(&mut reader)?.ok_or("Empty y4m stream")?;
+        let mut words = line.split(' ');
+        if words.next() != Some("YUV4MPEG2") {
+            return Err("Not a YUV4MPEG2 stream".to_string());
+        }
+
+        let (mut width, mut height) = (None, None);
+        let mut frame_rate = None;
+        let mut pixel_aspect = (0, 0);
+        let mut chroma = Chroma::C420;
+        let mut full_range = false;
+
+        for word in words.filter(|word| !word.is_empty()) {
+            let (tag, value) = word.split_at(word.chars().next().map_or(0, char::len_utf8));
+            match tag {
+                "W" => width = Some(parse_number(value)?),
+                "H" => height = Some(parse_number(value)?),
+                "F" => frame_rate = Some(parse_ratio(value)?),
+                "A" => pixel_aspect = parse_ratio(value)?,
+                "C" => chroma = Chroma::from_tag(value)?,
+                "X" if value == "COLORRANGE=FULL" => full_range = true,
+                _ => {} // Interlacing and other extensions don't change the samples
+            }
+        }
+
+        let (width, height): (u32, u32) = match (width, height) {
+            (Some(width), Some(height)) => (width, height),
+            _ => return Err("y4m header has no frame size".to_string()),
+        };
+        let (width, height) = match (u16::try_from(width), u16::try_from(height)) {
+            (Ok(width), Ok(height)) if width > 0 && height > 0 => (width, height),
+            _ => return Err(format!("Frame size {}x{} doesn't fit a GIF", width, height)),
+        };
+
+        let frame_rate = frame_rate.ok_or("y4m header has no frame rate")?;
+        let timer = FrameTimer::with_frame_rate(frame_rate.0, frame_rate.1)?;
+
+        Ok(Y4mReader {
+            reader,
+            header: Y4mHeader {
+                width,
+                height,
+                frame_rate,
+                pixel_aspect,
+                chroma,
+                full_range,
+            },
+            matrix: ColorMatrix::Bt601,
+            timer,
+        })
+    }
+
+    pub fn with_color_matrix(mut self, matrix: ColorMatrix) -> Self {
+        self.matrix = matrix;
+        self
+    }
+
+    // Raise delays below `min_delay` centiseconds, see `FrameTimer::with_min_delay`
+    pub fn with_min_delay(mut self, min_delay: u16) -> Self {
+        self.timer = self.timer.with_min_delay(min_delay);
+        self
+    }
+
+    pub fn header(&self) -> &Y4mHeader {
+        &self.header
+    }
+
+    // Next frame, or None at the end of the stream
+    pub fn read_frame(&mut self) -> Result<Option<Frame<'static>>, String> {
+        let Some(line) = read_line(&mut self.reader)? else {
+            return Ok(None);
+        };
+        if line.split(' ').next() != Some("FRAME") {
+            return Err("Expected a y4m FRAME marker".to_string());
+        }
+
+        let (width, height) = (self.header.width as usize, self.header.height as usize);
+        let (chroma_width, chroma_height) = self.header.chroma.plane_size(width, height);
+        let has_alpha = self.header.chroma == Chroma::C444Alpha;
+        let alpha_size = if has_alpha { width * height } else { 0 };
+
+        let mut data = vec![0u8; width * height + chroma_width * chroma_height * 2 + alpha_size];
+        self.reader
+            .read_exact(&mut data)
+            .map_err(|err| format!("Truncated y4m frame: {}", err))?;
+
+        let (luma, rest) = data.split_at(width * height);
+        let (cb, rest) = rest.split_at(chroma_width * chroma_height);
+        let (cr, alpha) = rest.split_at(chroma_width * chroma_height);
+
+        let converter = Converter::new(self.matrix, self.header.full_range);
+        let mut pixels = Vec::with_capacity(width * height * if has_alpha { 4 } else { 3 });
+
+        for y in 0..height {
+            for x in 0..width {
+                let i = y * width + x;
+                let (u, v) = match self.header.chroma {
+                    Chroma::Mono => (128, 128),
+                    chroma => {
+                        let (cx, cy) = match chroma {
+                            Chroma::C420 => (x / 2, y / 2),
+                            Chroma::C422 => (x / 2, y),
+                            _ => (x, y),
+                        };
+                        let c = cy * chroma_width + cx;
+                        (cb[c], cr[c])
+                    }
+                };
+
+                pixels.extend_from_slice(&converter.rgb(luma[i], u, v));
+                if has_alpha {
+                    pixels.push(alpha[i]);
+                }
+            }
+        }
+
+        let frame = match has_alpha {
+            true => Frame::rgba(self.header.width, self.header.height, pixels),
+            false => Frame::rgb(self.header.width, self.header.height, pixels),
+        };
+        Ok(Some(frame.with_delay(self.timer.next_delay()?)))
+    }
+}
+
+impl<R: BufRead> Iterator for Y4mReader<R> {
+    type Item = Result<Frame<'static>, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
+
+// Y'CbCr to RGB in 16.16 fixed point
+struct Converter {
+    luma_scale: i32,
+    luma_offset: i32,
+    chroma_scale: i32,
+    red_cr: i32,
+    green_cb: i32,
+    green_cr: i32,
+    blue_cb: i32,
+}
+
+impl Converter {
+    fn new(matrix: ColorMatrix, full_range: bool) -> Self {
+        let (kr, kb) = matrix.weights();
+        let kg = 1.0 - kr - kb;
+        let fixed = |value: f32| (value * 65536.0 + 0.5) as i32;
+
+        let (luma_scale, luma_offset, chroma_scale) = match full_range {
+            true => (1.0, 0, 1.0),
+            false => (255.0 / 219.0, 16, 255.0 / 224.0),
+        };
+
+        Converter {
+            luma_scale: fixed(luma_scale),
+            luma_offset,
+            chroma_scale: fixed(chroma_scale),
+            red_cr: fixed(2.0 * (1.0 - kr)),
+            green_cb: fixed(2.0 * kb * (1.0 - kb) / kg),
+            green_cr: fixed(2.0 * kr * (1.0 - kr) / kg),
+            blue_cb: fixed(2.0 * (1.0 - kb)),
+        }
+    }
+
+    fn rgb(&self, y: u8, cb: u8, cr: u8) -> [u8; 3] {
+        let y = (y as i32 - self.luma_offset) as i64 * self.luma_scale as i64;
+        let cb = ((cb as i32 - 128) as i64 * self.chroma_scale as i64) >> 16;
+        let cr = ((cr as i32 - 128) as i64 * self.chroma_scale as i64) >> 16;
+
+        let channel = |value: i64| ((value + 32768) >> 16).clamp(0, 255) as u8;
+        [
+            channel(y + cr * self.red_cr as i64),
+            channel(y - cb * self.green_cb as i64 - cr * self.green_cr as i64),
+            channel(y + cb * self.blue_cb as i64),
+        ]
+    }
+}
+
+// Far more than any real header needs, so garbage input fails fast instead of being read
+// into memory until the end of the stream
+const MAX_LINE_LEN: u64 = 4096;
+
+// Header or frame line without the newline, None at the end of the stream
+fn read_line<R: BufRead>(reader: &mut R) -> Result<Option<String>, String> {
+    let mut line = Vec::new();
+    let mut limited = reader.take(MAX_LINE_LEN);
+    if limited.read_until(b'\n', &mut line).map_err(|err| err.to_string())? == 0 {
+        return Ok(None);
+    }
+    if line.pop() != Some(b'\n') {
+        return Err("Truncated y4m header".to_string());
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| "y4m header is not valid text".to_string())
+}
+
+fn parse_number(value: &str) -> Result<u32, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid y4m header value {}", value))
+}
+
+fn parse_ratio(value: &str) -> Result<(u32, u32), String> {
+    let (numerator, denominator) = value
+        .split_once(':')
+        .ok_or(format!("Invalid y4m ratio {}", value))?;
+    Ok((parse_number(numerator)?, parse_number(denominator)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::PixelFormat;
+
+    fn stream(header: &str, frames: &[&[u8]]) -> Vec<u8> {
+        let mut data = format!("YUV4MPEG2 {}\n", header).into_bytes();
+        for frame in frames {
+            data.extend_from_slice(b"FRAME\n");
+            data.extend_from_slice(frame);
+        }
+        data
+    }
+
+    #[test]
+    fn test_header() -> Result<(), String> {
+        let data = stream("W4 H2 F30000:1001 Ip A4:3 C422 XYSCSS=422", &[]);
+        let reader = Y4mReader::new(&data[..])?;
+        let header = reader.header();
+
+        assert_eq!((header.width, header.height), (4, 2));
+        assert_eq!(header.frame_rate, (30000, 1001));
+        assert_eq!(header.pixel_aspect, (4, 3));
+        assert_eq!(header.chroma, Chroma::C422);
+        assert!(!header.full_range);
+        assert_eq!(header.screen_options().pixel_aspect_ratio, 70);
+        Ok(())
+    }
+
+    #[test]
+    fn test_420_frames() -> Result<(), String> {
+        // 2x2 frame, one chroma sample: video range white and black luma with neutral chroma,
+        // then a saturated red
+        let gray: &[u8] = &[235, 16, 16, 235, 128, 128];
+        let red: &[u8] = &[81, 81, 81, 81, 90, 240];
+        let data = stream("W2 H2 F25:1", &[gray, red]);
+
+        let frames = Y4mReader::new(&data[..])?.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].format, PixelFormat::Rgb);
+        assert_eq!(&frames[0].pixels[..], &[255, 255, 255, 0, 0, 0, 0, 0, 0, 255, 255, 255]);
+        assert_eq!(frames.iter().map(|frame| frame.delay).collect::<Vec<_>>(), vec![4, 4]);
+
+        let pixel = &frames[1].pixels[..3];
+        assert!(pixel[0] >= 250 && pixel[1] <= 5 && pixel[2] <= 5, "{:?}", pixel);
+        Ok(())
+    }
+
+    #[test]
+    fn test_full_range_bt709() -> Result<(), String> {
+        // Pure blue in full range BT.709: Y = 18, Cb = 255, Cr = 116
+        let data = stream("W1 H1 F1:1 C444 XCOLORRANGE=FULL", &[&[18, 255, 116]]);
+        let mut reader = Y4mReader::new(&data[..])?.with_color_matrix(ColorMatrix::Bt709);
+
+        let frame = reader.read_frame()?.ok_or("missing frame")?;
+        let pixel = &frame.pixels[..];
+        assert!(pixel[0] <= 3 && pixel[1] <= 3 && pixel[2] >= 252, "{:?}", pixel);
+        assert_eq!(frame.delay, 100);
+        Ok(())
+    }
+
+    #[test]
+    fn test_alpha_and_mono() -> Result<(), String> {
+        let data = stream("W2 H1 F10:1 C444alpha XCOLORRANGE=FULL", &[&[0, 255, 128, 128, 128, 128, 255, 0]]);
+        let frame = Y4mReader::new(&data[..])?.read_frame()?.ok_or("missing frame")?;
+        assert_eq!(frame.format, PixelFormat::Rgba);
+        assert_eq!(&frame.pixels[..], &[0, 0, 0, 255, 255, 255, 255, 0]);
+
+        let data = stream("W3 H1 F10:1 Cmono XCOLORRANGE=FULL", &[&[7, 8, 9]]);
+        let frame = Y4mReader::new(&data[..])?.read_frame()?.ok_or("missing frame")?;
+        assert_eq!(&frame.pixels[..], &[7, 7, 7, 8, 8, 8, 9, 9, 9]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_streams() {
+        assert!(Y4mReader::new(&b"P6 1 1 255\n"[..]).is_err());
+        assert!(Y4mReader::new(&b"YUV4MPEG2 W2 H2 F25:1 C420p10\n"[..]).is_err());
+        assert!(Y4mReader::new(&b"YUV4MPEG2 W2 F25:1\n"[..]).is_err());
+        let long_line = [b'Y'; 8192].iter().chain(b"\n").copied().collect::<Vec<_>>();
+        assert_eq!(Y4mReader::new(long_line.as_slice()).err(), Some("Truncated y4m header".to_string()));
+        assert!(Y4mReader::new("YUV4MPEG2 W2 é H2 Ü1 F25:1\n".as_bytes()).is_ok());
+
+        let data = stream("W2 H2 F25:1", &[&[1, 2, 3]]);
+        let mut reader = Y4mReader::new(&data[..]).unwrap();
+        assert!(reader.read_frame().is_err());
+    }
+}